@@ -123,6 +123,11 @@ impl ChunkStore {
         self.max_disk_usage
     }
 
+    /// Chunks already stored are kept even if they exceed a reduced `max_disk_usage`.
+    pub fn set_max_disk_usage(&mut self, max_disk_usage: usize) {
+        self.max_disk_usage = max_disk_usage;
+    }
+
     pub fn current_disk_usage(&self) -> usize {
         self.current_disk_usage
     }
@@ -178,6 +183,13 @@ impl ChunkStore {
         names
     }
 
+    /// Syncs the store's directory, the chunks themselves being synced as they're put.  The
+    /// directory is a temporary one, removed when the store is dropped, so the chunks don't
+    /// outlive the vault.
+    pub fn flush(&self) -> ::std::io::Result<()> {
+        try!(File::open(self.tempdir.path())).sync_all()
+    }
+
     pub fn has_disk_space(&self, required_space: usize) -> bool {
         self.current_disk_usage + required_space <= self.max_disk_usage
     }
@@ -157,6 +157,20 @@ mod test {
         chunk_store_utest.put(name, value.into_bytes());
     }
 
+    #[test]
+    fn set_max_disk_usage() {
+        let k_disk_size: usize = 116;
+        let mut chunk_store = ::chunk_store::ChunkStore::new(k_disk_size);
+        let name = ::utils::random_name();
+        chunk_store.put(name.clone(), get_random_non_empty_string(100).into_bytes());
+        chunk_store.set_max_disk_usage(50);
+        assert_eq!(chunk_store.max_disk_usage(), 50);
+        assert!(chunk_store.has_chunk(name));
+        assert!(!chunk_store.has_disk_space(0));
+        chunk_store.set_max_disk_usage(k_disk_size);
+        assert!(chunk_store.has_disk_space(16));
+    }
+
     #[test]
     fn put_and_get_value_should_be_same() {
         let data_size = 50;
@@ -188,4 +202,13 @@ mod test {
         assert_eq!(put(name.clone(), 10usize), 10usize);
         assert_eq!(put(name.clone(), 5usize), 5usize);  // last inserted data size
     }
+
+    #[test]
+    fn flush() {
+        let mut chunk_store = ::chunk_store::ChunkStore::new(K_DEFAULT_MAX_DISK_USAGE);
+        let name = ::utils::random_name();
+        chunk_store.put(name.clone(), vec![1u8; ONE_KB]);
+        assert!(chunk_store.flush().is_ok());
+        assert_eq!(chunk_store.get(name), vec![1u8; ONE_KB]);
+    }
 }
@@ -0,0 +1,119 @@
+// Copyright 2015 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+/// A change of our close group, telling the personas which of their accounts it affects.
+///
+/// An account is affected if the nodes closest to its name, among those of the close group, are
+/// not the same after the churn.  Only affected accounts need to be refreshed, and only those for
+/// which we're no longer one of the closest nodes are dropped.
+pub struct Churn {
+    our_name: Option<::routing::NameType>,
+    old_close_group: Vec<::routing::NameType>,
+    new_close_group: Vec<::routing::NameType>,
+}
+
+impl Churn {
+    /// The close groups are those reported by routing, which start with our own name.
+    pub fn new(old_close_group: Vec<::routing::NameType>,
+               new_close_group: Vec<::routing::NameType>)
+               -> Churn {
+        Churn {
+            our_name: new_close_group.first().cloned(),
+            old_close_group: old_close_group,
+            new_close_group: new_close_group,
+        }
+    }
+
+    pub fn our_name(&self) -> Option<::routing::NameType> {
+        self.our_name
+    }
+
+    pub fn new_close_group(&self) -> &Vec<::routing::NameType> {
+        &self.new_close_group
+    }
+
+    /// Whether the nodes responsible for `name` changed.
+    pub fn affects(&self, name: &::routing::NameType) -> bool {
+        closest_nodes(&self.old_close_group, name) != closest_nodes(&self.new_close_group, name)
+    }
+
+    /// Whether we're still responsible for `name`, and so keep its account.
+    pub fn keeps(&self, name: &::routing::NameType) -> bool {
+        match self.our_name {
+            Some(ref our_name) => closest_nodes(&self.new_close_group, name).contains(our_name),
+            None => false,
+        }
+    }
+}
+
+/// The nodes among `nodes` which are closest to `target`, closest first.  As closeness is a strict
+/// order, two sets of nodes are the same if and only if these lists are equal.
+pub fn closest_nodes(nodes: &Vec<::routing::NameType>,
+                     target: &::routing::NameType)
+                     -> Vec<::routing::NameType> {
+    let mut nodes = nodes.clone();
+    nodes.sort_by(|lhs, rhs| {
+        if ::routing::closer_to_target(lhs, rhs, target) {
+            ::std::cmp::Ordering::Less
+        } else if ::routing::closer_to_target(rhs, lhs, target) {
+            ::std::cmp::Ordering::Greater
+        } else {
+            ::std::cmp::Ordering::Equal
+        }
+    });
+    nodes.dedup();
+    nodes.truncate(::routing::types::GROUP_SIZE);
+    nodes
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn close_group(size: usize) -> Vec<::routing::NameType> {
+        (0..size).map(|_| ::utils::random_name()).collect()
+    }
+
+    #[test]
+    fn affects_and_keeps() {
+        let old_close_group = close_group(::routing::types::GROUP_SIZE);
+        let name = ::utils::random_name();
+
+        // an unchanged group affects nothing
+        let churn = Churn::new(old_close_group.clone(), old_close_group.clone());
+        assert!(!churn.affects(&name));
+        assert!(churn.keeps(&name));
+
+        // a node joining only affects the name if it's one of the closest to it
+        let joining = ::utils::random_name();
+        let mut nodes = old_close_group.clone();
+        nodes.push(joining.clone());
+        let churn = Churn::new(old_close_group.clone(), nodes.clone());
+        assert_eq!(churn.affects(&name), closest_nodes(&nodes, &name).contains(&joining));
+
+        // the group closest to the name itself is new after joining the network
+        let churn = Churn::new(vec![], old_close_group.clone());
+        assert!(churn.affects(&name));
+
+        // and nothing is kept without a close group
+        let churn = Churn::new(old_close_group.clone(), vec![]);
+        assert!(churn.affects(&name));
+        assert!(!churn.keeps(&name));
+    }
+}
@@ -0,0 +1,229 @@
+// Copyright 2015 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use std::io::Read;
+
+/// Name of the JSON file, looked up in the current directory, which overrides the defaults below.
+pub const CONFIG_FILE_NAME: &'static str = "safe_vault.config";
+
+/// Settings of a vault which may be adjusted by its owner.  Each field left out of the config file
+/// keeps its default; if the file is missing or can't be parsed, the defaults are used throughout.
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Eq, Debug, Clone)]
+pub struct Config {
+    /// Total size in bytes of the undelivered messages an MPID account may hold in its outbox
+    pub mpid_outbox_size: u64,
+    /// Maximum size in bytes of the body of a single MPID message
+    pub mpid_message_size: u64,
+    /// Seconds an undelivered MPID message, and its header, are kept before being dropped
+    pub mpid_message_retention: u64,
+    /// Disk space in bytes the PmidNode offers to the network
+    pub max_disk_space: u64,
+    /// Order in which the PmidNode evicts sacrificial chunks to make room for normal ones
+    pub eviction_policy: ::pmid_node::EvictionPolicy,
+    /// Seconds between two rounds of proof-of-storage challenges sent by the DataManagers
+    pub storage_challenge_interval: u64,
+    /// Seconds a stopping vault waits for routing to leave the network before exiting regardless
+    pub shutdown_deadline: u64,
+    /// Responses of the close group which have to agree before a refreshed account is accepted,
+    /// capped at the group size
+    pub refresh_quorum: usize,
+    /// Milliseconds the close group has to stay unchanged before churns are handled, those within
+    /// it being coalesced into one refresh; 0 handles each churn as it comes
+    pub churn_settle_window: u64,
+    /// Number of ImmutableData chunks cached from the responses passing through the vault
+    pub data_cache_capacity: usize,
+    /// Seconds a chunk stays in the data cache
+    pub data_cache_ttl: u64,
+    /// Seconds a Get waits for the data to be fetched before the requester is sent a failure
+    pub request_timeout: u64,
+    /// Number of Gets which may wait for the same data, further ones are failed straight away
+    pub max_requests_per_name: usize,
+    /// Number of Gets which may wait for data in total
+    pub max_cached_requests: usize,
+    /// Name, as 128 hex digits, of the wallet the farming rewards of the PmidNode are paid into;
+    /// rewards are dropped until one is chosen
+    pub farmer_wallet: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            mpid_outbox_size: 1024 * 1024,
+            mpid_message_size: 100 * 1024,
+            mpid_message_retention: 30 * 24 * 60 * 60,
+            max_disk_space: 1073741824,
+            eviction_policy: Default::default(),
+            storage_challenge_interval: 10 * 60,
+            shutdown_deadline: 10,
+            refresh_quorum: ::types::default_quorum(),
+            churn_settle_window: 1000,
+            data_cache_capacity: 100,
+            data_cache_ttl: 10 * 60,
+            request_timeout: 60,
+            max_requests_per_name: 50,
+            max_cached_requests: 5000,
+            farmer_wallet: None,
+        }
+    }
+}
+
+// The config file as written by the owner, any field of which may be left out
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Eq, Debug, Clone)]
+struct ConfigFile {
+    mpid_outbox_size: Option<u64>,
+    mpid_message_size: Option<u64>,
+    mpid_message_retention: Option<u64>,
+    max_disk_space: Option<u64>,
+    eviction_policy: Option<::pmid_node::EvictionPolicy>,
+    storage_challenge_interval: Option<u64>,
+    shutdown_deadline: Option<u64>,
+    refresh_quorum: Option<usize>,
+    churn_settle_window: Option<u64>,
+    data_cache_capacity: Option<usize>,
+    data_cache_ttl: Option<u64>,
+    request_timeout: Option<u64>,
+    max_requests_per_name: Option<usize>,
+    max_cached_requests: Option<usize>,
+    farmer_wallet: Option<String>,
+}
+
+impl ConfigFile {
+    fn merge_onto(self, default: Config) -> Config {
+        Config {
+            mpid_outbox_size: self.mpid_outbox_size.unwrap_or(default.mpid_outbox_size),
+            mpid_message_size: self.mpid_message_size.unwrap_or(default.mpid_message_size),
+            mpid_message_retention:
+                self.mpid_message_retention.unwrap_or(default.mpid_message_retention),
+            max_disk_space: self.max_disk_space.unwrap_or(default.max_disk_space),
+            eviction_policy: self.eviction_policy.unwrap_or(default.eviction_policy),
+            storage_challenge_interval:
+                self.storage_challenge_interval.unwrap_or(default.storage_challenge_interval),
+            shutdown_deadline: self.shutdown_deadline.unwrap_or(default.shutdown_deadline),
+            refresh_quorum: self.refresh_quorum.unwrap_or(default.refresh_quorum),
+            churn_settle_window: self.churn_settle_window.unwrap_or(default.churn_settle_window),
+            data_cache_capacity: self.data_cache_capacity.unwrap_or(default.data_cache_capacity),
+            data_cache_ttl: self.data_cache_ttl.unwrap_or(default.data_cache_ttl),
+            request_timeout: self.request_timeout.unwrap_or(default.request_timeout),
+            max_requests_per_name:
+                self.max_requests_per_name.unwrap_or(default.max_requests_per_name),
+            max_cached_requests: self.max_cached_requests.unwrap_or(default.max_cached_requests),
+            farmer_wallet: self.farmer_wallet.or(default.farmer_wallet),
+        }
+    }
+}
+
+impl Config {
+    /// Reads the config file when the vault starts, and then periodically for a changed max disk
+    /// space or farmer wallet to be advertised; the other settings only change on restart.
+    pub fn load() -> Config {
+        match Config::read_file(CONFIG_FILE_NAME) {
+            Ok(Some(config)) => config,
+            Ok(None) => {
+                info!("No {} found, using the default vault config", CONFIG_FILE_NAME);
+                Default::default()
+            }
+            Err(error) => {
+                error!("Using the default vault config, failed to read {}: {}", CONFIG_FILE_NAME,
+                       error);
+                Default::default()
+            }
+        }
+    }
+
+    /// The configured farmer wallet, if any and if it is a valid name.
+    pub fn farmer_wallet_name(&self) -> Option<::routing::NameType> {
+        use rustc_serialize::hex::FromHex;
+        let hex = match self.farmer_wallet {
+            Some(ref hex) => hex,
+            None => return None,
+        };
+        match hex.from_hex() {
+            Ok(ref bytes) if bytes.len() == 64 => {
+                let mut name = [0u8; 64];
+                for (byte, value) in name.iter_mut().zip(bytes.iter()) {
+                    *byte = *value;
+                }
+                Some(::routing::NameType::new(name))
+            }
+            _ => {
+                warn!("Ignoring farmer wallet {:?}, which isn't the hex of a name", hex);
+                None
+            }
+        }
+    }
+
+    // Returns None if there's no such file
+    fn read_file(path: &str) -> Result<Option<Config>, String> {
+        let mut file = match ::std::fs::File::open(path) {
+            Ok(file) => file,
+            Err(ref error) if error.kind() == ::std::io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(format!("{}", error)),
+        };
+        let mut contents = String::new();
+        let _ = try!(file.read_to_string(&mut contents).map_err(|e| format!("{}", e)));
+        let config_file: ConfigFile = try!(::rustc_serialize::json::decode(&contents)
+                                               .map_err(|e| format!("{}", e)));
+        Ok(Some(config_file.merge_onto(Default::default())))
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn write_file(path: &::std::path::Path, contents: &str) {
+        use std::io::Write;
+        let mut file = ::std::fs::File::create(path).unwrap();
+        file.write_all(contents.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn read_file() {
+        let dir = ::tempdir::TempDir::new("safe_vault_config").unwrap();
+        let path = dir.path().join(CONFIG_FILE_NAME);
+        let config = Config { mpid_outbox_size: 10, ..Default::default() };
+        write_file(&path, &::rustc_serialize::json::encode(&config).unwrap());
+        assert_eq!(Config::read_file(path.to_str().unwrap()), Ok(Some(config)));
+
+        // the fields left out keep their defaults
+        write_file(&path, "{ \"max_disk_space\": 1024, \"request_timeout\": 5 }");
+        let expected = Config { max_disk_space: 1024, request_timeout: 5, ..Default::default() };
+        assert_eq!(Config::read_file(path.to_str().unwrap()), Ok(Some(expected)));
+
+        // a missing file isn't an error, unlike an invalid one
+        assert_eq!(Config::read_file(dir.path().join("missing").to_str().unwrap()), Ok(None));
+        write_file(&path, "{ \"max_disk_space\": \"lots\" }");
+        assert!(Config::read_file(path.to_str().unwrap()).is_err());
+    }
+
+    #[test]
+    fn farmer_wallet() {
+        use rustc_serialize::hex::ToHex;
+        let wallet = ::utils::random_name();
+        let config = Config {
+            farmer_wallet: Some(wallet.0[..].to_hex()),
+            ..Default::default()
+        };
+        assert_eq!(config.farmer_wallet_name(), Some(wallet));
+        let config = Config { farmer_wallet: Some("00ff".to_string()), ..Default::default() };
+        assert!(config.farmer_wallet_name().is_none());
+        let config: Config = Default::default();
+        assert!(config.farmer_wallet_name().is_none());
+    }
+}
@@ -0,0 +1,63 @@
+// Copyright 2015 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use sodiumoxide::crypto;
+
+static NONCE_SIZE: usize = 32;
+
+/// Proof-of-storage messages travel as the tagged payload of a PlainData named after the chunk.
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Eq, Debug, Clone)]
+pub enum StorageChallenge {
+    /// Posted by the DataManagers of a chunk to one of its holders
+    Challenge {
+        nonce: Vec<u8>,
+    },
+    /// Posted back by the holder, `proof` being the hash of the nonce followed by the content
+    Proof {
+        proof: Vec<u8>,
+    },
+}
+
+impl ::types::PlainPayload for StorageChallenge {
+    fn type_tag() -> u64 {
+        ::types::payload_tags::STORAGE_CHALLENGE_TAG
+    }
+}
+
+/// The nonce the holders of a chunk are challenged with in a round, if they are challenged in it,
+/// which they are in one round out of `odds`.  Both only depend on the round and the chunk's name,
+/// so every DataManager of the chunk challenges its holders alike.
+pub fn round_nonce(chunk_name: &::routing::NameType, round: u64, odds: u64) -> Option<Vec<u8>> {
+    let encoded = match ::routing::utils::encode(&(chunk_name.clone(), round)) {
+        Ok(encoded) => encoded,
+        Err(_) => return None,
+    };
+    let hash = crypto::hash::sha512::hash(&encoded[..]).0;
+    let draw = hash[..8].iter().fold(0u64, |draw, byte| (draw << 8) | *byte as u64);
+    if draw % odds != 0 {
+        return None;
+    }
+    Some(hash[8..8 + NONCE_SIZE].to_vec())
+}
+
+/// The answer to a challenge, computed by the holder from its stored copy of the chunk and by the
+/// DataManagers, in advance, from a copy they know to be valid.
+pub fn compute_proof(nonce: &[u8], content: &[u8]) -> Vec<u8> {
+    let mut input = nonce.to_vec();
+    input.extend(content.iter().cloned());
+    crypto::hash::sha512::hash(&input[..]).0.to_vec()
+}
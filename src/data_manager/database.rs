@@ -15,7 +15,6 @@
 // Please review the Licences for the specific language governing permissions and limitations
 // relating to use of the SAFE Network Software.
 
-use cbor;
 use rustc_serialize::Encodable;
 use std::collections::HashMap;
 
@@ -62,19 +61,13 @@ impl ::types::Refreshable for Account {
         }
     }
 
-    fn merge(from_group: ::routing::NameType, responses: Vec<Account>) -> Option<Account> {
-        let mut stats = Vec::<(PmidNodes, u64)>::new();
-        for response in responses {
-            let account =
-                match ::routing::utils::decode::<Account>(&response.serialised_contents()) {
-                    Ok(result) => {
-                        if *result.name() != from_group {
-                            continue;
-                        }
-                        result
-                    }
-                    Err(_) => continue,
-                };
+    fn merge(from_group: ::routing::NameType,
+             responses: Vec<Account>,
+             quorum: usize)
+             -> Result<Account, ::types::MergeError> {
+        let accounts = try!(::types::responses_for(&from_group, responses, quorum, Account::name));
+        let mut stats = Vec::<(PmidNodes, usize)>::new();
+        for account in accounts {
             let push_in_vec = match stats.iter_mut().find(|a| a.0 == *account.data_holders()) {
                 Some(find_res) => {
                     find_res.1 += 1;
@@ -89,11 +82,10 @@ impl ::types::Refreshable for Account {
             }
         }
         stats.sort_by(|a, b| b.1.cmp(&a.1));
-        let (pmids, count) = stats[0].clone();
-        if count >= (::routing::types::GROUP_SIZE as u64 + 1) / 2 {
-            return Some(Account::new(from_group, pmids));
+        match stats.into_iter().next() {
+            Some((pmids, count)) if count >= quorum => Ok(Account::new(from_group, pmids)),
+            _ => Err(::types::MergeError::InsufficientQuorum),
         }
-        None
     }
 }
 
@@ -149,6 +141,9 @@ impl Database {
         }
     }
 
+    pub fn names(&self) -> Vec<DataName> {
+        self.storage.keys().cloned().collect()
+    }
 
     pub fn handle_account_transfer(&mut self, merged_account: Account) {
         let _ = self.storage.remove(merged_account.name());
@@ -163,34 +158,71 @@ impl Database {
         self.temp_storage_after_churn = self.storage.clone();
         let mut actions = Vec::<::types::MethodCall>::new();
         for (key, value) in self.storage.iter() {
-            if value.len() < 3 {
-                for pmid_node in value.iter() {
-                    info!("DataManager sends out a Get request in churn, fetching data {:?} from \
-                          pmid_node {:?}", *key, pmid_node);
-                    actions.push(::types::MethodCall::Get {
-                        location: ::routing::authority::Authority::ManagedNode(pmid_node.clone()),
-                        // DataManager only handles ::routing::immutable_data::ImmutableData
-                        data_request:
-                            ::routing::data::DataRequest::ImmutableData((*key).clone(),
-                                ::routing::immutable_data::ImmutableDataType::Normal)
-                    });
-                }
-            }
-            let account = Account::new((*key).clone(), (*value).clone());
-            let mut encoder = cbor::Encoder::from_memory();
-            if encoder.encode(&[account.clone()]).is_ok() {
-                debug!("DataManager sends out a refresh regarding account {:?}", account.name());
-                actions.push(::types::MethodCall::Refresh {
-                    type_tag: DATA_MANAGER_ACCOUNT_TAG,
-                    our_authority: ::routing::Authority::NaeManager(*account.name()),
-                    payload: encoder.as_bytes().to_vec()
-                });
-            }
+            actions.extend(replicate(key, value).into_iter());
+            actions.extend(refresh(key, value).into_iter());
         }
         self.storage.clear();
         debug!("DataManager storage cleaned in churn with actions.len() = {:?}", actions.len());
         actions
     }
+
+    /// Refreshes the accounts whose group changed, dropping those we're no longer responsible for.
+    /// The chunks kept which are held by too few nodes are fetched to be replicated.
+    pub fn handle_churn(&mut self, churn: &::churn::Churn) -> Vec<::types::MethodCall> {
+        self.temp_storage_after_churn = self.storage.clone();
+        let mut actions = Vec::<::types::MethodCall>::new();
+        let mut lost = Vec::new();
+        for (key, value) in self.storage.iter() {
+            if !churn.affects(key) {
+                continue;
+            }
+            if churn.keeps(key) {
+                actions.extend(replicate(key, value).into_iter());
+            } else {
+                lost.push(key.clone());
+            }
+            actions.extend(refresh(key, value).into_iter());
+        }
+        for name in lost {
+            let _ = self.storage.remove(&name);
+        }
+        debug!("DataManager handled churn with actions.len() = {:?}", actions.len());
+        actions
+    }
+}
+
+fn replicate(name: &DataName, pmid_nodes: &PmidNodes) -> Vec<::types::MethodCall> {
+    if pmid_nodes.len() >= 3 {
+        return vec![];
+    }
+    pmid_nodes.iter()
+              .map(|pmid_node| {
+                  info!("DataManager sends out a Get request in churn, fetching data {:?} from \
+                        pmid_node {:?}", name, pmid_node);
+                  ::types::MethodCall::Get {
+                      location: ::routing::authority::Authority::ManagedNode(pmid_node.clone()),
+                      // DataManager only handles ::routing::immutable_data::ImmutableData
+                      data_request:
+                          ::routing::data::DataRequest::ImmutableData(name.clone(),
+                              ::routing::immutable_data::ImmutableDataType::Normal)
+                  }
+              })
+              .collect()
+}
+
+fn refresh(name: &DataName, pmid_nodes: &PmidNodes) -> Option<::types::MethodCall> {
+    let account = Account::new(name.clone(), pmid_nodes.clone());
+    match ::transfer_parser::encode(DATA_MANAGER_ACCOUNT_TAG, &account) {
+        Some(payload) => {
+            debug!("DataManager sends out a refresh regarding account {:?}", name);
+            Some(::types::MethodCall::Refresh {
+                type_tag: DATA_MANAGER_ACCOUNT_TAG,
+                our_authority: ::routing::Authority::NaeManager(name.clone()),
+                payload: payload
+            })
+        }
+        None => None,
+    }
 }
 
 #[cfg(test)]
@@ -311,4 +343,19 @@ mod test {
         db.handle_account_transfer(Account::new(data_name.clone(), vec![]));
         assert_eq!(db.get_pmid_nodes(&data_name).len(), 0);
     }
+
+    #[test]
+    fn merge() {
+        use types::Refreshable;
+        let name = ::utils::random_name();
+        let holders = vec![::utils::random_name(), ::utils::random_name()];
+        let responses = vec![Account::new(name.clone(), holders.clone()),
+                             Account::new(name.clone(), holders.clone()),
+                             Account::new(name.clone(), vec![::utils::random_name()])];
+        assert_eq!(Account::merge(name.clone(), responses.clone(), 2),
+                   Ok(Account::new(name.clone(), holders)));
+        assert_eq!(Account::merge(name.clone(), responses, 3),
+                   Err(::types::MergeError::InsufficientQuorum));
+        assert_eq!(Account::merge(name, vec![], 1), Err(::types::MergeError::InsufficientQuorum));
+    }
 }
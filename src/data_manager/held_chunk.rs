@@ -0,0 +1,37 @@
+// Copyright 2015 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+/// Messages about a holder sent to the DataManagers of a chunk, by the holder itself or by its
+/// PmidManagers, or sent back to the holder, travelling as the tagged payload of a PlainData named
+/// after the chunk.
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Eq, Debug, Clone)]
+pub enum HeldChunk {
+    /// Posted by a PmidNode, after restarting, for each chunk found in its chunk store
+    Announce,
+    /// Posted by the PmidManagers of a holder which has fallen below the rank threshold, for each
+    /// chunk recorded in its account
+    Migrate,
+    /// Posted by the DataManagers of a chunk they don't track to a PmidNode announcing it, for the
+    /// node to free the space it takes
+    Reclaim,
+}
+
+impl ::types::PlainPayload for HeldChunk {
+    fn type_tag() -> u64 {
+        ::types::payload_tags::HELD_CHUNK_TAG
+    }
+}
@@ -15,32 +15,81 @@
 // Please review the Licences for the specific language governing permissions and limitations
 // relating to use of the SAFE Network Software.
 
+mod challenge;
 mod database;
+mod held_chunk;
 
 use std::cmp;
-use cbor;
 use rustc_serialize::Encodable;
 
+use pmid_manager::{INITIAL_RANK, RANK_THRESHOLD};
 use transfer_parser::transfer_tags::DATA_MANAGER_STATS_TAG;
+use types::PlainPayload;
 use utils;
 
 type Address = ::routing::NameType;
 
+/// Ranks of the PmidNodes, as known by the PmidManagers of this vault
+pub type Ranks = ::std::collections::HashMap<::routing::NameType, u64>;
+
+pub use self::challenge::{StorageChallenge, compute_proof};
 pub use self::database::Account;
+pub use self::held_chunk::HeldChunk;
 
 pub static PARALLELISM: usize = 4;
 static LRU_CACHE_SIZE: usize = 1000;
+// challenges prepared for a chunk, for the next rounds it is challenged in, once a valid copy of
+// it passes by
+static CHALLENGES_PER_CHUNK: usize = 8;
+// a chunk's holders are challenged in one round out of this many
+static CHALLENGE_ODDS: u64 = 64;
+// seconds a holder has to answer a challenge before it is deemed to have lost the chunk
+static CHALLENGE_TIMEOUT: i64 = 60;
+// seconds a migration away from a demoted holder is left to complete before it may be restarted
+static MIGRATION_TIMEOUT: i64 = 600;
 
 type ChunkNameAndPmidNode = (::routing::NameType, ::routing::NameType);
 
+// Challenges computed in advance from a valid copy of a chunk, each being the round it's for, the
+// nonce and its proof
+#[derive(Clone)]
+struct PreparedChallenges {
+    challenges: Vec<(u64, Vec<u8>, Vec<u8>)>,
+}
+
+struct PendingChallenge {
+    expected_proof: Vec<u8>,
+    sent_at: ::time::SteadyTime,
+}
+
+// A Get sent to a holder.  Only one forwarding a client's request is farmed: the fetches made to
+// prepare challenges, replace a failed holder or migrate a chunk earn the holder nothing.
+#[derive(Clone)]
+struct OnGoingGet {
+    sent_at: ::time::SteadyTime,
+    // who the chunk is fetched for, none for the group's own fetches
+    requester: Option<::routing::authority::Authority>,
+}
+
 pub struct DataManager {
     database: database::Database,
     // the higher the index is, the slower the farming rate will be
     resource_index: u64,
-    // key is pair of chunk_name and pmid_node, value is inserting time
-    on_going_gets: ::lru_time_cache::LruCache<ChunkNameAndPmidNode, ::time::SteadyTime>,
+    // key is pair of chunk_name and pmid_node
+    on_going_gets: ::lru_time_cache::LruCache<ChunkNameAndPmidNode, OnGoingGet>,
     // key is chunk_name and value is failing pmids
     failed_pmids: ::lru_time_cache::LruCache<::routing::NameType, Vec<::routing::NameType>>,
+    // key is chunk_name
+    challenges: ::lru_time_cache::LruCache<::routing::NameType, PreparedChallenges>,
+    // the latest round of challenges
+    round: u64,
+    // challenges sent to the holders and not answered yet
+    pending_challenges: ::std::collections::HashMap<ChunkNameAndPmidNode, PendingChallenge>,
+    // chunks fetched from the nodes announcing them, as proof that they hold them
+    announced_gets: ::lru_time_cache::LruCache<ChunkNameAndPmidNode, ::time::SteadyTime>,
+    // holders reported below the rank threshold by their PmidManagers, whose chunk is on its way
+    // to a replacement
+    migrations: ::lru_time_cache::LruCache<ChunkNameAndPmidNode, ::time::SteadyTime>,
 }
 
 #[derive(RustcEncodable, RustcDecodable, Clone, PartialEq, Eq, Debug)]
@@ -64,19 +113,13 @@ impl Stats {
 }
 
 impl ::types::Refreshable for Stats {
-    fn merge(from_group: ::routing::NameType, responses: Vec<Stats>) -> Option<Stats> {
-        let mut resource_indexes: Vec<u64> = Vec::new();
-        for value in responses {
-            match ::routing::utils::decode::<Stats>(&value.serialised_contents()) {
-                Ok(refreshable) => {
-                    if *refreshable.name() == from_group {
-                        resource_indexes.push(refreshable.resource_index());
-                    }
-                }
-                Err(_) => {}
-            }
-        }
-        Some(Stats::new(::routing::NameType([0u8; 64]), utils::median(resource_indexes)))
+    fn merge(from_group: ::routing::NameType,
+             responses: Vec<Stats>,
+             quorum: usize)
+             -> Result<Stats, ::types::MergeError> {
+        let stats = try!(::types::responses_for(&from_group, responses, quorum, Stats::name));
+        let resource_indexes = stats.iter().map(|a| a.resource_index()).collect::<Vec<u64>>();
+        Ok(Stats::new(from_group, utils::median(resource_indexes)))
     }
 }
 
@@ -90,18 +133,29 @@ impl DataManager {
             resource_index: 1,
             on_going_gets: ::lru_time_cache::LruCache::with_capacity(LRU_CACHE_SIZE),
             failed_pmids: ::lru_time_cache::LruCache::with_capacity(LRU_CACHE_SIZE),
+            challenges: ::lru_time_cache::LruCache::with_capacity(LRU_CACHE_SIZE),
+            round: 0,
+            pending_challenges: ::std::collections::HashMap::new(),
+            announced_gets: ::lru_time_cache::LruCache::with_expiry_duration_and_capacity(
+                                ::time::Duration::seconds(CHALLENGE_TIMEOUT), LRU_CACHE_SIZE),
+            migrations: ::lru_time_cache::LruCache::with_expiry_duration_and_capacity(
+                            ::time::Duration::seconds(MIGRATION_TIMEOUT), LRU_CACHE_SIZE),
         }
     }
 
     pub fn handle_get(&mut self,
                       name: &::routing::NameType,
-                      data_request: ::routing::data::DataRequest)
+                      data_request: ::routing::data::DataRequest,
+                      requester: ::routing::authority::Authority)
                       -> Vec<::types::MethodCall> {
         // before querying in the records, first ensure all records are valid
         let on_going_gets = self.on_going_gets.retrieve_all();
         let mut failing_entries = Vec::new();
+        let mut holders_failed = false;
         for on_going_get in on_going_gets {
-            if on_going_get.1 + ::time::Duration::seconds(10) < ::time::SteadyTime::now() {
+            if on_going_get.1.sent_at + ::time::Duration::seconds(10) <
+               ::time::SteadyTime::now() {
+                holders_failed = holders_failed || (on_going_get.0).0 == *name;
                 self.database.remove_pmid_node(&(on_going_get.0).0, (on_going_get.0).1.clone());
                 failing_entries.push(on_going_get.0.clone());
                 if self.failed_pmids.contains_key(&name) {
@@ -120,7 +174,12 @@ impl DataManager {
 
         let result = self.database.get_pmid_nodes(name);
         if result.len() == 0 {
-            return vec![];
+            let reason = if holders_failed {
+                ::types::GetFailureReason::AllHoldersFailed
+            } else {
+                ::types::GetFailureReason::NoSuchData
+            };
+            return vec![::types::MethodCall::GetFailed { name: name.clone(), reason: reason }];
         }
         let mut forward_to_pmids = Vec::new();
         for pmid in result.iter() {
@@ -128,14 +187,15 @@ impl DataManager {
                 location: ::routing::authority::Authority::ManagedNode(pmid.clone()),
                 data_request: data_request.clone()
             });
-            self.on_going_gets.add((name.clone(), pmid.clone()), ::time::SteadyTime::now());
+            self.add_on_going_get(name.clone(), pmid.clone(), Some(requester.clone()));
         }
         forward_to_pmids
     }
 
     pub fn handle_put(&mut self,
                       data: ::routing::immutable_data::ImmutableData,
-                      nodes_in_table: &mut Vec<::routing::NameType>)
+                      nodes_in_table: &mut Vec<::routing::NameType>,
+                      ranks: &Ranks)
                       -> Vec<::types::MethodCall> {
         let data_name = data.name();
         if self.database.exist(&data_name) {
@@ -148,12 +208,9 @@ impl DataManager {
           } else {
             cmp::Ordering::Greater
           });
-        let pmid_nodes_num = cmp::min(nodes_in_table.len(), PARALLELISM);
-        let mut dest_pmids: Vec<::routing::NameType> = Vec::new();
-        for index in 0..pmid_nodes_num {
-            dest_pmids.push(nodes_in_table[index].clone());
-        }
+        let dest_pmids = choose_holders(nodes_in_table, &vec![], PARALLELISM, ranks);
         self.database.put_pmid_nodes(&data_name, dest_pmids.clone());
+        self.prepare_challenges(&data);
         match *data.get_type_tag() {
             ::routing::immutable_data::ImmutableDataType::Sacrificial => {
                 self.resource_index = cmp::min(1048576,
@@ -173,9 +230,21 @@ impl DataManager {
 
     pub fn handle_get_response(&mut self,
                                from: ::routing::NameType,
-                               response: ::routing::data::Data)
+                               response: ::routing::data::Data,
+                               nodes_in_table: &Vec<::routing::NameType>,
+                               ranks: &Ranks)
                                -> Vec<::types::MethodCall> {
-        let _ = self.on_going_gets.remove(&(from.clone(), response.name()));
+        // Only a chunk we asked the holder for on behalf of a client is farmed.  The name of an
+        // ImmutableData is the hash of its content, so a chunk returned under the requested name is
+        // verified.
+        let requester = self.on_going_gets
+                            .remove(&(response.name(), from.clone()))
+                            .and_then(|on_going_get| on_going_get.requester);
+        let announced = self.announced_gets.remove(&(response.name(), from.clone())).is_some();
+        let farming_calls = match requester {
+            Some(requester) => self.farming_attempt(from, response.name(), &requester),
+            None => vec![],
+        };
         let mut failure_notifications = Vec::new();
         match self.failed_pmids.remove(&response.name()) {
             Some(failed_pmids) => {
@@ -191,7 +260,22 @@ impl DataManager {
             None => {}
         }
 
-        let replicate_to = self.replicate_to(&response.name());
+        if let ::routing::data::Data::ImmutableData(ref data) = response {
+            if !self.challenges.contains_key(&data.name()) {
+                self.prepare_challenges(data);
+            }
+            if announced {
+                self.add_announced_holder(data.name(), from.clone());
+            }
+        }
+
+        let restoring_calls = self.restore_holders(&response, nodes_in_table, ranks);
+
+        let replicate_to = if restoring_calls.is_empty() {
+            self.replicate_to(&response.name(), ranks)
+        } else {
+            None
+        };
         let replication_calls = match replicate_to {
             Some(pmid_node) => {
                 self.database.add_pmid_node(&response.name(), pmid_node.clone());
@@ -202,12 +286,17 @@ impl DataManager {
             }
             None => vec![],
         };
-        failure_notifications.into_iter().chain(replication_calls.into_iter()).collect()
+        farming_calls.into_iter()
+                     .chain(failure_notifications.into_iter())
+                     .chain(restoring_calls.into_iter())
+                     .chain(replication_calls.into_iter())
+                     .collect()
     }
 
     pub fn handle_put_response(&mut self,
                                response: ::routing::error::ResponseError,
-                               from_address: &::routing::NameType)
+                               from_address: &::routing::NameType,
+                               ranks: &Ranks)
                                -> Vec<::types::MethodCall> {
         info!("DataManager handle_put_responsen from {:?}", from_address);
         match response {
@@ -222,7 +311,7 @@ impl DataManager {
                         self.database.remove_pmid_node(&name, from_address.clone());
                         match *immutable_data.get_type_tag() {
                             ::routing::immutable_data::ImmutableDataType::Normal => {
-                                let replicate_to = self.replicate_to(&name);
+                                let replicate_to = self.replicate_to(&name, ranks);
                                 match replicate_to {
                                     Some(pmid_node) => {
                                         self.database.add_pmid_node(&name, pmid_node.clone());
@@ -251,6 +340,195 @@ impl DataManager {
         vec![]
     }
 
+    /// Called when the PmidManagers of a holder report it has fallen below the rank threshold.
+    /// Unless it is already under way, the chunk is fetched, preferably from another holder, and
+    /// migrated once the Get response arrives.  Only a node recorded as a holder is migrated from.
+    pub fn handle_holder_demoted(&mut self,
+                                 pmid_node: ::routing::NameType,
+                                 chunk_name: ::routing::NameType,
+                                 ranks: &Ranks)
+                                 -> Vec<::types::MethodCall> {
+        let holders = self.database.get_pmid_nodes(&chunk_name);
+        let key = (chunk_name.clone(), pmid_node.clone());
+        if !holders.contains(&pmid_node) || self.migrations.contains_key(&key) {
+            return vec![];
+        }
+        self.migrations.add(key, ::time::SteadyTime::now());
+        let source = holders.iter()
+                            .find(|holder| **holder != pmid_node &&
+                                           !self.migrations.contains_key(&(chunk_name.clone(),
+                                                                          (*holder).clone())) &&
+                                           rank_of(ranks, holder) >= RANK_THRESHOLD)
+                            .unwrap_or(&pmid_node)
+                            .clone();
+        info!("DataManager migrating {:?} away from {:?}, fetching it from {:?}", chunk_name,
+              pmid_node, source);
+        self.add_on_going_get(chunk_name.clone(), source.clone(), None);
+        vec![::types::MethodCall::Get {
+            location: ::routing::authority::Authority::ManagedNode(source),
+            data_request: ::routing::data::DataRequest::ImmutableData(chunk_name,
+                ::routing::immutable_data::ImmutableDataType::Normal),
+        }]
+    }
+
+    /// Called periodically with the current round of challenges.  Challenges left unanswered are
+    /// failures; then, once per round, the holders of the chunks picked for the round are
+    /// challenged to prove they still hold them.  The chunks and their nonces only depend on the
+    /// round, so the whole group challenges the same holders alike and their reports of lost data
+    /// accumulate.  Returns the name of each chunk along with the call, as each is sent on behalf
+    /// of the DataManagers of that chunk.
+    pub fn challenge_holders(&mut self,
+                             round: u64)
+                             -> Vec<(::routing::NameType, ::types::MethodCall)> {
+        let now = ::time::SteadyTime::now();
+        let expired: Vec<ChunkNameAndPmidNode> =
+            self.pending_challenges
+                .iter()
+                .filter(|&(_, pending)| {
+                    pending.sent_at + ::time::Duration::seconds(CHALLENGE_TIMEOUT) < now
+                })
+                .map(|(key, _)| key.clone())
+                .collect();
+        let mut challenge_calls = Vec::new();
+        for (chunk_name, pmid_node) in expired {
+            let _ = self.pending_challenges.remove(&(chunk_name.clone(), pmid_node.clone()));
+            info!("DataManager got no answer from {:?} to a challenge for {:?}", pmid_node,
+                  chunk_name);
+            for call in self.challenge_failed(chunk_name.clone(), pmid_node) {
+                challenge_calls.push((chunk_name.clone(), call));
+            }
+        }
+        if round <= self.round {
+            return challenge_calls;
+        }
+        self.round = round;
+
+        for chunk_name in self.database.names() {
+            if challenge::round_nonce(&chunk_name, round, CHALLENGE_ODDS).is_none() {
+                continue;
+            }
+            let holders = self.database
+                              .get_pmid_nodes(&chunk_name)
+                              .into_iter()
+                              .filter(|pmid_node| {
+                                  !self.pending_challenges
+                                       .contains_key(&(chunk_name.clone(), pmid_node.clone()))
+                              })
+                              .collect::<Vec<_>>();
+            if holders.is_empty() {
+                continue;
+            }
+            let (nonce, expected_proof) = match self.round_challenge(&chunk_name, round) {
+                Some(challenge) => challenge,
+                None => {
+                    // Challenges are prepared from the copy once it arrives
+                    self.add_on_going_get(chunk_name.clone(), holders[0].clone(), None);
+                    challenge_calls.push((chunk_name.clone(), ::types::MethodCall::Get {
+                        location: ::routing::authority::Authority::ManagedNode(holders[0]),
+                        data_request: ::routing::data::DataRequest::ImmutableData(
+                            chunk_name.clone(),
+                            ::routing::immutable_data::ImmutableDataType::Normal),
+                    }));
+                    continue;
+                }
+            };
+            for pmid_node in holders {
+                let key = (chunk_name.clone(), pmid_node);
+                if let Some(call) = self.challenge(key, nonce.clone(), expected_proof.clone()) {
+                    challenge_calls.push((chunk_name.clone(), call));
+                }
+            }
+        }
+        challenge_calls
+    }
+
+    pub fn handle_challenge_proof(&mut self,
+                                  pmid_node: ::routing::NameType,
+                                  chunk_name: ::routing::NameType,
+                                  proof: Vec<u8>)
+                                  -> Vec<::types::MethodCall> {
+        match self.pending_challenges.remove(&(chunk_name.clone(), pmid_node.clone())) {
+            Some(pending) => {
+                if pending.expected_proof == proof {
+                    vec![]
+                } else {
+                    info!("DataManager got a wrong proof from {:?} for {:?}", pmid_node,
+                          chunk_name);
+                    self.challenge_failed(chunk_name, pmid_node)
+                }
+            }
+            None => vec![],
+        }
+    }
+
+    /// Routing couldn't deliver a Get to a holder, so another holder not being asked already is
+    /// asked instead.
+    pub fn handle_failed_get(&mut self,
+                             pmid_node: ::routing::NameType,
+                             data_request: ::routing::data::DataRequest)
+                             -> Vec<::types::MethodCall> {
+        let chunk_name = match data_request {
+            ::routing::data::DataRequest::ImmutableData(name, _) => name,
+            _ => return vec![],
+        };
+        let requester = self.on_going_gets
+                            .remove(&(chunk_name.clone(), pmid_node.clone()))
+                            .and_then(|on_going_get| on_going_get.requester);
+        let holders = self.database.get_pmid_nodes(&chunk_name);
+        let other_holder = holders.into_iter().find(|holder| {
+            *holder != pmid_node &&
+            !self.on_going_gets.contains_key(&(chunk_name.clone(), holder.clone()))
+        });
+        match other_holder {
+            Some(holder) => {
+                self.add_on_going_get(chunk_name, holder.clone(), requester);
+                vec![::types::MethodCall::Get {
+                    location: ::routing::authority::Authority::ManagedNode(holder),
+                    data_request: data_request,
+                }]
+            }
+            None => vec![],
+        }
+    }
+
+    /// A PmidNode announcing a chunk we track, which is short of holders, is challenged to prove
+    /// it holds the chunk, and re-added as a holder once it does.  Without a challenge prepared for
+    /// the chunk, the chunk itself is fetched from the node as proof.  Announcements of chunks we
+    /// don't track are ignored: we may have just joined the group, so can't tell the node the
+    /// chunk is no longer needed.
+    pub fn handle_chunk_announcement(&mut self,
+                                     pmid_node: ::routing::NameType,
+                                     chunk_name: ::routing::NameType)
+                                     -> Vec<::types::MethodCall> {
+        if !self.database.exist(&chunk_name) {
+            info!("DataManager asking {:?} to reclaim untracked chunk {:?}", pmid_node,
+                  chunk_name);
+            return HeldChunk::Reclaim.to_plain_data(chunk_name)
+                                     .map(|content| ::types::MethodCall::Post {
+                                         location: ::routing::authority::Authority::ManagedNode(
+                                             pmid_node),
+                                         content: content,
+                                     })
+                                     .into_iter()
+                                     .collect();
+        }
+        let holders = self.database.get_pmid_nodes(&chunk_name);
+        let key = (chunk_name.clone(), pmid_node.clone());
+        if holders.contains(&pmid_node) || holders.len() >= PARALLELISM ||
+           self.announced_gets.contains_key(&key) {
+            return vec![];
+        }
+        // The chunk itself is the proof: the challenges prepared are kept for the holders, in
+        // the rounds they are for
+        info!("DataManager checking {:?} holds announced chunk {:?}", pmid_node, chunk_name);
+        self.announced_gets.add(key, ::time::SteadyTime::now());
+        vec![::types::MethodCall::Get {
+            location: ::routing::authority::Authority::ManagedNode(pmid_node),
+            data_request: ::routing::data::DataRequest::ImmutableData(chunk_name,
+                ::routing::immutable_data::ImmutableDataType::Normal),
+        }]
+    }
+
     pub fn handle_account_transfer(&mut self, merged_account: Account) {
         self.database.handle_account_transfer(merged_account);
     }
@@ -266,37 +544,220 @@ impl DataManager {
         // TODO: as Vault doesn't have access to what ID it is, we have to use the first one in the
         //       close group as its ID
         let mut result = self.database.retrieve_all_and_reset(close_group);
-        let data_manager_stats = Stats::new(close_group[0].clone(), self.resource_index);
-        let mut encoder = cbor::Encoder::from_memory();
-        if encoder.encode(&[data_manager_stats.clone()]).is_ok() {
-            result.push(::types::MethodCall::Refresh {
+        result.extend(self.stats_refresh(close_group[0].clone()).into_iter());
+        result
+    }
+
+    /// Only the accounts affected by the churn are refreshed, the stats always are.
+    pub fn handle_churn(&mut self, churn: &::churn::Churn) -> Vec<::types::MethodCall> {
+        let mut result = self.database.handle_churn(churn);
+        if let Some(our_name) = churn.our_name() {
+            result.extend(self.stats_refresh(our_name).into_iter());
+        }
+        result
+    }
+
+    fn stats_refresh(&self, our_name: ::routing::NameType) -> Option<::types::MethodCall> {
+        let data_manager_stats = Stats::new(our_name, self.resource_index);
+        match ::transfer_parser::encode(DATA_MANAGER_STATS_TAG, &data_manager_stats) {
+            Some(payload) => Some(::types::MethodCall::Refresh {
                 type_tag: DATA_MANAGER_STATS_TAG,
                 our_authority: ::routing::Authority::NaeManager(*data_manager_stats.name()),
-                payload: encoder.as_bytes().to_vec()
+                payload: payload
+            }),
+            None => None,
+        }
+    }
+
+    // A farming attempt succeeds with a probability of 1 / resource_index, i.e. the farming rate.
+    // The draw is derived from what the whole group knows of the Get rather than a random number,
+    // so that every member decides alike and the reward reaches the NodeManagers' quorum.
+    fn farming_attempt(&self,
+                       pmid_node: ::routing::NameType,
+                       chunk_name: ::routing::NameType,
+                       requester: &::routing::authority::Authority)
+                       -> Vec<::types::MethodCall> {
+        let draw = match ::routing::utils::encode(&(chunk_name.clone(), requester.clone(),
+                                                    pmid_node.clone(), self.resource_index)) {
+            Ok(encoded) => {
+                let hash = ::sodiumoxide::crypto::hash::sha512::hash(&encoded[..]);
+                hash.0[..8].iter().fold(0u64, |draw, byte| (draw << 8) | *byte as u64)
+            }
+            Err(_) => return vec![],
+        };
+        if draw % self.resource_index != 0 {
+            return vec![];
+        }
+        match ::pmid_manager::PmidManagerRequest::FarmingReward.to_plain_data(chunk_name) {
+            Some(content) => vec![::types::MethodCall::Post {
+                location: ::routing::authority::Authority::NodeManager(pmid_node),
+                content: content,
+            }],
+            None => vec![],
+        }
+    }
+
+    // Prepared for the next rounds the chunk is challenged in
+    fn prepare_challenges(&mut self, data: &::routing::immutable_data::ImmutableData) {
+        let mut challenges = Vec::with_capacity(CHALLENGES_PER_CHUNK);
+        let mut round = self.round;
+        while challenges.len() < CHALLENGES_PER_CHUNK {
+            round += 1;
+            if let Some(nonce) = challenge::round_nonce(&data.name(), round, CHALLENGE_ODDS) {
+                let proof = compute_proof(&nonce, &data.value()[..]);
+                challenges.push((round, nonce, proof));
+            }
+        }
+        self.challenges.add(data.name(), PreparedChallenges { challenges: challenges });
+    }
+
+    // Posts a challenge to the node, to be answered before CHALLENGE_TIMEOUT
+    fn challenge(&mut self,
+                 key: ChunkNameAndPmidNode,
+                 nonce: Vec<u8>,
+                 expected_proof: Vec<u8>)
+                 -> Option<::types::MethodCall> {
+        let challenge = StorageChallenge::Challenge { nonce: nonce };
+        let content = match challenge.to_plain_data(key.0.clone()) {
+            Some(content) => content,
+            None => return None,
+        };
+        let pmid_node = key.1.clone();
+        let _ = self.pending_challenges.insert(key, PendingChallenge {
+            expected_proof: expected_proof,
+            sent_at: ::time::SteadyTime::now(),
+        });
+        Some(::types::MethodCall::Post {
+            location: ::routing::authority::Authority::ManagedNode(pmid_node),
+            content: content,
+        })
+    }
+
+    // A node which proved it holds an announced chunk is a holder again, unless the chunk got
+    // enough holders in the meantime
+    fn add_announced_holder(&mut self,
+                            chunk_name: ::routing::NameType,
+                            pmid_node: ::routing::NameType) {
+        let holders = self.database.get_pmid_nodes(&chunk_name);
+        if !self.database.exist(&chunk_name) || holders.contains(&pmid_node) ||
+           holders.len() >= PARALLELISM {
+            return;
+        }
+        info!("DataManager re-adding {:?} as a holder of {:?}", pmid_node, chunk_name);
+        self.database.add_pmid_node(&chunk_name, pmid_node);
+    }
+
+    // Each prepared challenge is only used in its round, a holder can't answer it from a past
+    // answer.  Once they are all past, new ones are prepared from the next copy fetched.
+    fn round_challenge(&mut self,
+                       chunk_name: &::routing::NameType,
+                       round: u64)
+                       -> Option<(Vec<u8>, Vec<u8>)> {
+        let (challenge, exhausted) = match self.challenges.get_mut(chunk_name) {
+            Some(prepared) => {
+                prepared.challenges.retain(|&(prepared_round, _, _)| prepared_round >= round);
+                let challenge = prepared.challenges
+                                        .iter()
+                                        .find(|&&(prepared_round, _, _)| prepared_round == round)
+                                        .map(|&(_, ref nonce, ref proof)| {
+                                            (nonce.clone(), proof.clone())
+                                        });
+                (challenge, prepared.challenges.is_empty())
+            }
+            None => return None,
+        };
+        if exhausted {
+            let _ = self.challenges.remove(chunk_name);
+        }
+        challenge
+    }
+
+    // The holder is accounted as having lost the chunk, which is fetched from another holder to be
+    // put to a replacement once it arrives
+    fn challenge_failed(&mut self,
+                        chunk_name: ::routing::NameType,
+                        pmid_node: ::routing::NameType)
+                        -> Vec<::types::MethodCall> {
+        self.database.remove_pmid_node(&chunk_name, pmid_node.clone());
+        let mut failure_calls = Vec::new();
+        let lost_data = ::pmid_manager::PmidManagerRequest::LostData;
+        if let Some(content) = lost_data.to_plain_data(chunk_name.clone()) {
+            failure_calls.push(::types::MethodCall::Post {
+                location: ::routing::authority::Authority::NodeManager(pmid_node),
+                content: content,
             });
         }
-        result
+        if let Some(holder) = self.database.get_pmid_nodes(&chunk_name).first() {
+            self.add_on_going_get(chunk_name.clone(), holder.clone(), None);
+            failure_calls.push(::types::MethodCall::Get {
+                location: ::routing::authority::Authority::ManagedNode(holder.clone()),
+                data_request: ::routing::data::DataRequest::ImmutableData(chunk_name,
+                    ::routing::immutable_data::ImmutableDataType::Normal),
+            });
+        }
+        failure_calls
     }
 
-    fn replicate_to(&mut self, name: &::routing::NameType) -> Option<::routing::NameType> {
+    fn add_on_going_get(&mut self,
+                        chunk_name: ::routing::NameType,
+                        pmid_node: ::routing::NameType,
+                        requester: Option<::routing::authority::Authority>) {
+        self.on_going_gets.add((chunk_name, pmid_node),
+                               OnGoingGet { sent_at: ::time::SteadyTime::now(),
+                                            requester: requester });
+    }
+
+    // Holders ranked below the threshold or being migrated from are dropped, and the chunk is put
+    // to the best ranked of the closest nodes until it has PARALLELISM holders again
+    fn restore_holders(&mut self,
+                       response: &::routing::data::Data,
+                       nodes_in_table: &Vec<::routing::NameType>,
+                       ranks: &Ranks)
+                       -> Vec<::types::MethodCall> {
+        let name = response.name();
+        let holders = self.database.get_pmid_nodes(&name);
+        let low_ranked: Vec<::routing::NameType> =
+            holders.iter()
+                   .filter(|holder| {
+                       rank_of(ranks, holder) < RANK_THRESHOLD ||
+                       self.migrations.contains_key(&(name.clone(), (*holder).clone()))
+                   })
+                   .cloned()
+                   .collect();
+        let missing = PARALLELISM.saturating_sub(holders.len() - low_ranked.len());
+        if missing == 0 {
+            return vec![];
+        }
+        let mut candidates = nodes_in_table.clone();
+        sort_by_closeness(&mut candidates, &name);
+        let replacements = choose_holders(&candidates, &holders, missing, ranks);
+        for pmid_node in low_ranked {
+            info!("DataManager removing {:?} as a holder of {:?}, its rank is too low",
+                  pmid_node, name);
+            let _ = self.migrations.remove(&(name.clone(), pmid_node.clone()));
+            self.database.remove_pmid_node(&name, pmid_node);
+        }
+        let mut restoring_calls = Vec::new();
+        for pmid_node in replacements {
+            self.database.add_pmid_node(&name, pmid_node.clone());
+            restoring_calls.push(::types::MethodCall::Put {
+                location: ::routing::authority::Authority::NodeManager(pmid_node),
+                content: response.clone(),
+            });
+        }
+        restoring_calls
+    }
+
+    fn replicate_to(&mut self,
+                    name: &::routing::NameType,
+                    ranks: &Ranks)
+                    -> Option<::routing::NameType> {
         match self.database.temp_storage_after_churn.get(name) {
             Some(pmid_nodes) => {
                 if pmid_nodes.len() < 3 {
-                    self.database.close_grp_from_churn.sort_by(|a, b| {
-                        if ::routing::closer_to_target(&a, &b, &name) {
-                          cmp::Ordering::Less
-                        } else {
-                          cmp::Ordering::Greater
-                        }
-                    });
-                    let mut close_grp_node_to_add = ::routing::NameType::new([0u8; 64]);
-                    for close_grp_it in self.database.close_grp_from_churn.iter() {
-                        if pmid_nodes.iter().find(|a| **a == *close_grp_it).is_none() {
-                            close_grp_node_to_add = close_grp_it.clone();
-                            break;
-                        }
-                    }
-                    return Some(close_grp_node_to_add);
+                    sort_by_closeness(&mut self.database.close_grp_from_churn, name);
+                    return choose_holders(&self.database.close_grp_from_churn, pmid_nodes, 1,
+                                          ranks).pop();
                 }
             }
             None => {}
@@ -305,10 +766,50 @@ impl DataManager {
     }
 }
 
+fn rank_of(ranks: &Ranks, pmid_node: &::routing::NameType) -> u64 {
+    ranks.get(pmid_node).cloned().unwrap_or(INITIAL_RANK)
+}
+
+fn sort_by_closeness(nodes: &mut Vec<::routing::NameType>, target: &::routing::NameType) {
+    nodes.sort_by(|a, b| {
+        if ::routing::closer_to_target(&a, &b, &target) {
+            cmp::Ordering::Less
+        } else {
+            cmp::Ordering::Greater
+        }
+    });
+}
+
+// Picks up to `count` holders among the candidates, which must be sorted by closeness to the
+// chunk.  Nodes ranked below the threshold and the current holders are skipped; of the closest
+// `2 * count` remaining ones the best ranked are preferred, closeness breaking ties.
+fn choose_holders(candidates: &Vec<::routing::NameType>,
+                  holders: &Vec<::routing::NameType>,
+                  count: usize,
+                  ranks: &Ranks)
+                  -> Vec<::routing::NameType> {
+    let mut eligible: Vec<::routing::NameType> =
+        candidates.iter()
+                  .filter(|candidate| !holders.contains(candidate) &&
+                                      rank_of(ranks, candidate) >= RANK_THRESHOLD)
+                  .take(2 * count)
+                  .cloned()
+                  .collect();
+    // sort_by is stable, so equally ranked nodes stay in order of closeness
+    eligible.sort_by(|a, b| rank_of(ranks, b).cmp(&rank_of(ranks, a)));
+    eligible.truncate(count);
+    eligible
+}
+
 #[cfg(test)]
 mod test {
-    use super::{DataManager, Stats};
+    use super::{DataManager, HeldChunk, Ranks, Stats};
     use super::database::Account;
+    use types::PlainPayload;
+
+    fn client() -> ::routing::authority::Authority {
+        ::routing::authority::Authority::ClientManager(::utils::random_name())
+    }
 
     #[test]
     fn handle_put_get() {
@@ -325,7 +826,8 @@ mod test {
                                       ::routing::NameType::new([7u8; 64]),
                                       ::routing::NameType::new([8u8; 64])];
         {
-            let put_result = data_manager.handle_put(data.clone(), &mut nodes_in_table);
+            let put_result = data_manager.handle_put(data.clone(), &mut nodes_in_table,
+                                                     &Ranks::new());
             assert_eq!(put_result.len(), super::PARALLELISM);
             for i in 0..put_result.len() {
                 match put_result[i].clone() {
@@ -342,7 +844,7 @@ mod test {
         {
             let request = ::routing::data::DataRequest::ImmutableData(data_name.clone(),
                               ::routing::immutable_data::ImmutableDataType::Normal);
-            let get_result = data_manager.handle_get(&data_name, request.clone());
+            let get_result = data_manager.handle_get(&data_name, request.clone(), client());
             assert_eq!(get_result.len(), super::PARALLELISM);
             for i in 0..get_result.len() {
                 match get_result[i].clone() {
@@ -355,6 +857,271 @@ mod test {
                 }
             }
         }
+        {
+            let missing = ::utils::random_name();
+            let request = ::routing::data::DataRequest::ImmutableData(missing.clone(),
+                              ::routing::immutable_data::ImmutableDataType::Normal);
+            assert_eq!(data_manager.handle_get(&missing, request, client()),
+                       vec![::types::MethodCall::GetFailed {
+                           name: missing,
+                           reason: ::types::GetFailureReason::NoSuchData,
+                       }]);
+        }
+    }
+
+    #[test]
+    fn farming_reward() {
+        let mut data_manager = DataManager::new();
+        let data = ::routing::immutable_data::ImmutableData::new(
+                       ::routing::immutable_data::ImmutableDataType::Normal,
+                       ::routing::types::generate_random_vec_u8(1024));
+        let mut nodes_in_table = vec![::utils::random_name(), ::utils::random_name()];
+        let _ = data_manager.handle_put(data.clone(), &mut nodes_in_table, &Ranks::new());
+        let request = ::routing::data::DataRequest::ImmutableData(data.name(),
+                          ::routing::immutable_data::ImmutableDataType::Normal);
+        let _ = data_manager.handle_get(&data.name(), request, client());
+
+        // with the lowest resource index every attempt succeeds
+        assert_eq!(data_manager.resource_index, 1);
+        let response = ::routing::data::Data::ImmutableData(data.clone());
+        let reward = ::pmid_manager::PmidManagerRequest::FarmingReward;
+        let ranks = Ranks::new();
+        assert_eq!(data_manager.handle_get_response(nodes_in_table[0], response.clone(),
+                                                    &nodes_in_table, &ranks),
+                   vec![::types::MethodCall::Post {
+                       location: ::routing::authority::Authority::NodeManager(nodes_in_table[0]),
+                       content: reward.to_plain_data(data.name()).unwrap(),
+                   }]);
+        // a chunk can't be farmed twice for a single request, nor by a node not asked for it
+        assert!(data_manager.handle_get_response(nodes_in_table[0], response.clone(),
+                                                 &nodes_in_table, &ranks).is_empty());
+        assert!(data_manager.handle_get_response(::utils::random_name(), response,
+                                                 &nodes_in_table, &ranks).is_empty());
+    }
+
+    #[test]
+    fn farming_draw() {
+        // every member of the group draws alike for the same Get, whatever its own RNG
+        let mut members = vec![DataManager::new(), DataManager::new()];
+        for member in members.iter_mut() {
+            member.resource_index = 4;
+        }
+        let pmid_node = ::utils::random_name();
+        let mut rewards = 0;
+        for _ in 0..64 {
+            let (chunk_name, requester) = (::utils::random_name(), client());
+            let draws = members.iter()
+                               .map(|member| member.farming_attempt(pmid_node, chunk_name,
+                                                                    &requester))
+                               .collect::<Vec<_>>();
+            assert_eq!(draws[0], draws[1]);
+            rewards += draws[0].len();
+        }
+        // and the reward is only given for some of them
+        assert!(rewards > 0 && rewards < 64);
+    }
+
+    #[test]
+    fn ranked_holders() {
+        let mut data_manager = DataManager::new();
+        let data = ::routing::immutable_data::ImmutableData::new(
+                       ::routing::immutable_data::ImmutableDataType::Normal,
+                       ::routing::types::generate_random_vec_u8(1024));
+        let mut nodes_in_table: Vec<::routing::NameType> =
+            (0..8).map(|_| ::utils::random_name()).collect();
+        super::sort_by_closeness(&mut nodes_in_table, &data.name());
+        // the closest node is ranked too low, the furthest one is preferred for its high rank
+        let mut ranks = Ranks::new();
+        let _ = ranks.insert(nodes_in_table[0], 0);
+        let _ = ranks.insert(nodes_in_table[7], super::INITIAL_RANK + 20);
+        let put_result = data_manager.handle_put(data.clone(), &mut nodes_in_table, &ranks);
+        let holders = vec![nodes_in_table[7], nodes_in_table[1], nodes_in_table[2],
+                           nodes_in_table[3]];
+        assert_eq!(put_result.iter().map(|call| match *call {
+                       ::types::MethodCall::Put { ref location, .. } => location.clone(),
+                       _ => panic!("Unexpected"),
+                   }).collect::<Vec<_>>(),
+                   holders.iter()
+                          .map(|holder| ::routing::authority::Authority::NodeManager(*holder))
+                          .collect::<Vec<_>>());
+
+        // once its PmidManagers report a holder below the threshold, whatever rank this vault
+        // knows it by, its chunk is fetched from another holder...
+        let request = ::routing::data::DataRequest::ImmutableData(data.name(),
+                          ::routing::immutable_data::ImmutableDataType::Normal);
+        assert_eq!(data_manager.handle_holder_demoted(nodes_in_table[1], data.name(), &ranks),
+                   vec![::types::MethodCall::Get {
+                       location: ::routing::authority::Authority::ManagedNode(nodes_in_table[7]),
+                       data_request: request,
+                   }]);
+        // ...only once while the migration is under way, and never for a node not holding it
+        assert!(data_manager.handle_holder_demoted(nodes_in_table[1], data.name(), &ranks)
+                            .is_empty());
+        assert!(data_manager.handle_holder_demoted(nodes_in_table[5], data.name(), &ranks)
+                            .is_empty());
+
+        // ...and moved to the best ranked of the closest eligible nodes, the fetch not being farmed
+        let response = ::routing::data::Data::ImmutableData(data.clone());
+        assert_eq!(data_manager.handle_get_response(nodes_in_table[7], response.clone(),
+                                                    &nodes_in_table, &ranks),
+                   vec![::types::MethodCall::Put {
+                            location: ::routing::authority::Authority::NodeManager(
+                                          nodes_in_table[4]),
+                            content: response,
+                        }]);
+        assert_eq!(data_manager.database.get_pmid_nodes(&data.name()),
+                   vec![nodes_in_table[7], nodes_in_table[2], nodes_in_table[3],
+                        nodes_in_table[4]]);
+    }
+
+    #[test]
+    fn storage_challenge() {
+        let mut data_manager = DataManager::new();
+        let data = ::routing::immutable_data::ImmutableData::new(
+                       ::routing::immutable_data::ImmutableDataType::Normal,
+                       ::routing::types::generate_random_vec_u8(1024));
+        let mut nodes_in_table: Vec<::routing::NameType> =
+            (0..8).map(|_| ::utils::random_name()).collect();
+        let _ = data_manager.handle_put(data.clone(), &mut nodes_in_table, &Ranks::new());
+        let holders = data_manager.database.get_pmid_nodes(&data.name());
+
+        // the chunk is only challenged in some rounds, with a nonce any DataManager would pick
+        let round_nonce = |round: u64| {
+            super::challenge::round_nonce(&data.name(), round, super::CHALLENGE_ODDS)
+        };
+        let skipped = (1..).find(|round| round_nonce(*round).is_none()).unwrap();
+        assert!(data_manager.challenge_holders(skipped).is_empty());
+        let round = (skipped + 1..).find(|round| round_nonce(*round).is_some()).unwrap();
+        let nonce = round_nonce(round).unwrap();
+        let challenges = data_manager.challenge_holders(round);
+        assert_eq!(challenges.len(), holders.len());
+        for (&(chunk_name, ref call), holder) in challenges.iter().zip(holders.iter()) {
+            assert_eq!(chunk_name, data.name());
+            match *call {
+                ::types::MethodCall::Post { ref location, ref content } => {
+                    assert_eq!(*location, ::routing::authority::Authority::ManagedNode(*holder));
+                    assert_eq!(super::StorageChallenge::from_plain_data(content),
+                               Some(super::StorageChallenge::Challenge { nonce: nonce.clone() }));
+                }
+                _ => panic!("Unexpected"),
+            }
+        }
+        // the holders are only challenged once a round
+        assert!(data_manager.challenge_holders(round).is_empty());
+
+        // a right proof is accepted
+        let proof = super::compute_proof(&nonce, &data.value()[..]);
+        assert!(data_manager.handle_challenge_proof(holders[0], data.name(), proof).is_empty());
+
+        // a wrong proof is accounted as lost data, and the chunk fetched to be replicated
+        let lost_data = ::pmid_manager::PmidManagerRequest::LostData;
+        let request = ::routing::data::DataRequest::ImmutableData(data.name(),
+                          ::routing::immutable_data::ImmutableDataType::Normal);
+        assert_eq!(data_manager.handle_challenge_proof(holders[1], data.name(), nonce.clone()),
+                   vec![::types::MethodCall::Post {
+                            location: ::routing::authority::Authority::NodeManager(holders[1]),
+                            content: lost_data.to_plain_data(data.name()).unwrap(),
+                        },
+                        ::types::MethodCall::Get {
+                            location: ::routing::authority::Authority::ManagedNode(holders[0]),
+                            data_request: request.clone(),
+                        }]);
+        assert_eq!(data_manager.database.get_pmid_nodes(&data.name()).len(), holders.len() - 1);
+        // an unsolicited proof is ignored
+        assert!(data_manager.handle_challenge_proof(holders[1], data.name(), vec![]).is_empty());
+
+        // a challenge left unanswered is a failure too
+        for (_, pending) in data_manager.pending_challenges.iter_mut() {
+            pending.sent_at = pending.sent_at -
+                              ::time::Duration::seconds(super::CHALLENGE_TIMEOUT + 1);
+        }
+        let failures = data_manager.challenge_holders(round);
+        assert!(failures.contains(&(data.name(), ::types::MethodCall::Post {
+            location: ::routing::authority::Authority::NodeManager(holders[2]),
+            content: lost_data.to_plain_data(data.name()).unwrap(),
+        })));
+        assert_eq!(data_manager.database.get_pmid_nodes(&data.name()), vec![holders[0]]);
+
+        // once the chunk arrives it is put to new holders, and no reward is claimed for it
+        let response = ::routing::data::Data::ImmutableData(data.clone());
+        let replication = data_manager.handle_get_response(holders[0], response, &nodes_in_table,
+                                                           &Ranks::new());
+        assert!(replication.iter().all(|call| match *call {
+            ::types::MethodCall::Put { .. } => true,
+            _ => false,
+        }));
+        assert_eq!(replication.len(), super::PARALLELISM - 1);
+        assert_eq!(data_manager.database.get_pmid_nodes(&data.name()).len(), super::PARALLELISM);
+    }
+
+    #[test]
+    fn failed_get() {
+        let mut data_manager = DataManager::new();
+        let data = ::routing::immutable_data::ImmutableData::new(
+                       ::routing::immutable_data::ImmutableDataType::Normal,
+                       ::routing::types::generate_random_vec_u8(1024));
+        let mut nodes_in_table = vec![::utils::random_name(), ::utils::random_name()];
+        let _ = data_manager.handle_put(data.clone(), &mut nodes_in_table, &Ranks::new());
+        let request = ::routing::data::DataRequest::ImmutableData(data.name(),
+                          ::routing::immutable_data::ImmutableDataType::Normal);
+        data_manager.add_on_going_get(data.name(), nodes_in_table[0], Some(client()));
+
+        assert_eq!(data_manager.handle_failed_get(nodes_in_table[0], request.clone()),
+                   vec![::types::MethodCall::Get {
+                       location: ::routing::authority::Authority::ManagedNode(nodes_in_table[1]),
+                       data_request: request.clone(),
+                   }]);
+        // the other holder is being asked already
+        assert!(data_manager.handle_failed_get(nodes_in_table[0], request).is_empty());
+    }
+
+    #[test]
+    fn chunk_announcement() {
+        let mut data_manager = DataManager::new();
+        let data = ::routing::immutable_data::ImmutableData::new(
+                       ::routing::immutable_data::ImmutableDataType::Normal,
+                       ::routing::types::generate_random_vec_u8(1024));
+        let mut nodes_in_table = vec![::utils::random_name(), ::utils::random_name()];
+        let _ = data_manager.handle_put(data.clone(), &mut nodes_in_table, &Ranks::new());
+        data_manager.database.remove_pmid_node(&data.name(), nodes_in_table[0]);
+
+        // the announcing node is asked for the chunk, and only re-added as a holder once it
+        // returns it
+        let fetch = |pmid_node: ::routing::NameType| {
+            vec![::types::MethodCall::Get {
+                location: ::routing::authority::Authority::ManagedNode(pmid_node),
+                data_request: ::routing::data::DataRequest::ImmutableData(data.name(),
+                    ::routing::immutable_data::ImmutableDataType::Normal),
+            }]
+        };
+        let announced = nodes_in_table[0];
+        assert_eq!(data_manager.handle_chunk_announcement(announced, data.name()),
+                   fetch(announced));
+        assert!(!data_manager.database.get_pmid_nodes(&data.name()).contains(&announced));
+        assert!(data_manager.handle_chunk_announcement(announced, data.name()).is_empty());
+        let _ = data_manager.handle_get_response(announced,
+                                                 ::routing::data::Data::ImmutableData(data.clone()),
+                                                 &vec![], &Ranks::new());
+        assert!(data_manager.database.get_pmid_nodes(&data.name()).contains(&announced));
+
+        // a node not returning it is left out, it never held the chunk for us
+        let silent = ::utils::random_name();
+        assert_eq!(data_manager.handle_chunk_announcement(silent, data.name()), fetch(silent));
+        assert!(!data_manager.database.get_pmid_nodes(&data.name()).contains(&silent));
+
+        // nodes aren't added beyond PARALLELISM holders
+        data_manager.database.add_pmid_node(&data.name(), ::utils::random_name());
+        assert_eq!(data_manager.database.get_pmid_nodes(&data.name()).len(), super::PARALLELISM);
+        assert!(data_manager.handle_chunk_announcement(::utils::random_name(), data.name())
+                            .is_empty());
+
+        // and the announcing node is asked to reclaim the space of an untracked chunk
+        let untracked = ::utils::random_name();
+        assert_eq!(data_manager.handle_chunk_announcement(announced, untracked),
+                   vec![::types::MethodCall::Post {
+                       location: ::routing::authority::Authority::ManagedNode(announced),
+                       content: HeldChunk::Reclaim.to_plain_data(untracked).unwrap(),
+                   }]);
     }
 
     #[test]
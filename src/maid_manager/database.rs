@@ -15,7 +15,6 @@
 // Please review the Licences for the specific language governing permissions and limitations
 // relating to use of the SAFE Network Software.
 
-use cbor;
 use rustc_serialize::{Decoder, Encodable, Encoder};
 use std::collections;
 
@@ -45,27 +44,51 @@ impl Account {
 }
 
 impl ::types::Refreshable for Account {
-    fn merge(from_group: ::routing::NameType, responses: Vec<Account>) -> Option<Account> {
+    fn merge(from_group: ::routing::NameType,
+             responses: Vec<Account>,
+             quorum: usize)
+             -> Result<Account, ::types::MergeError> {
+        let accounts = try!(::types::responses_for(&from_group, responses, quorum, Account::name));
         let mut data_stored: Vec<u64> = Vec::new();
         let mut space_available: Vec<u64> = Vec::new();
-        for response in responses {
-            let account =
-                match ::routing::utils::decode::<Account>(&response.serialised_contents()) {
-                    Ok(result) => {
-                        if *result.name() != from_group {
-                            continue;
-                        }
-                        result
-                    }
-                    Err(_) => continue,
-                };
+        let mut wallet_balance: Vec<u64> = Vec::new();
+        for account in accounts {
             data_stored.push(account.value().data_stored());
             space_available.push(account.value().space_available());
+            wallet_balance.push(account.value().wallet_balance());
         }
-        Some(Account::new(from_group,
-                          AccountValue::new(utils::median(data_stored),
-                                            utils::median(space_available))))
+        Ok(Account::new(from_group,
+                        AccountValue::new(utils::median(data_stored),
+                                          utils::median(space_available),
+                                          utils::median(wallet_balance))))
     }
+
+    // Accounts of version 0 predate the wallet, which is empty for them
+    fn decode_version(version: u64, contents: &Vec<u8>) -> Option<Account> {
+        match version {
+            0 => ::routing::utils::decode::<AccountV0>(contents).ok().map(|account| {
+                Account::new(account.name,
+                             AccountValue::new(account.value.data_stored,
+                                               account.value.space_available,
+                                               0))
+            }),
+            1 | ::transfer_parser::FORMAT_VERSION => ::routing::utils::decode(contents).ok(),
+            _ => None,
+        }
+    }
+}
+
+// An account as transferred by version 0 of the transfer format
+#[derive(RustcDecodable)]
+struct AccountV0 {
+    name: MaidNodeName,
+    value: AccountValueV0,
+}
+
+#[derive(RustcDecodable)]
+struct AccountValueV0 {
+    data_stored: u64,
+    space_available: u64,
 }
 
 
@@ -74,19 +97,24 @@ impl ::types::Refreshable for Account {
 pub struct AccountValue {
     data_stored: u64,
     space_available: u64,
+    // safecoin held by the client
+    wallet_balance: u64,
 }
 
 impl Default for AccountValue {
-    // FIXME: to bypass the AccountCreation process for simple network allowance is granted
-    // automatically
+    // a newly created account has to buy its space
     fn default() -> AccountValue {
-        AccountValue { data_stored: 0, space_available: 1073741824 }
+        AccountValue { data_stored: 0, space_available: 0, wallet_balance: 0 }
     }
 }
 
 impl AccountValue {
-    pub fn new(data_stored: u64, space_available: u64) -> AccountValue {
-        AccountValue { data_stored: data_stored, space_available: space_available }
+    pub fn new(data_stored: u64, space_available: u64, wallet_balance: u64) -> AccountValue {
+        AccountValue {
+            data_stored: data_stored,
+            space_available: space_available,
+            wallet_balance: wallet_balance,
+        }
     }
 
     pub fn put_data(&mut self, size: u64) -> bool {
@@ -116,6 +144,32 @@ impl AccountValue {
     pub fn data_stored(&self) -> u64 {
         self.data_stored
     }
+
+    pub fn wallet_balance(&self) -> u64 {
+        self.wallet_balance
+    }
+
+    pub fn debit_wallet(&mut self, amount: u64) -> bool {
+        if amount > self.wallet_balance {
+            return false;
+        }
+        self.wallet_balance -= amount;
+        true
+    }
+
+    pub fn credit_wallet(&mut self, amount: u64) {
+        self.wallet_balance = self.wallet_balance.saturating_add(amount);
+    }
+
+    /// Spends `amount` of safecoin on `amount * bytes_per_coin` bytes of space.
+    pub fn buy_space(&mut self, amount: u64, bytes_per_coin: u64) -> bool {
+        if !self.debit_wallet(amount) {
+            return false;
+        }
+        self.space_available =
+            self.space_available.saturating_add(amount.saturating_mul(bytes_per_coin));
+        true
+    }
 }
 
 
@@ -128,15 +182,57 @@ impl MaidManagerDatabase {
         MaidManagerDatabase { storage: collections::HashMap::with_capacity(10000) }
     }
 
-    pub fn get_balance(&mut self, name: &MaidNodeName) -> u64 {
-        let default: AccountValue = Default::default();
-        self.storage.entry(name.clone()).or_insert(default).space_available()
+    pub fn exist(&self, name: &MaidNodeName) -> bool {
+        self.storage.contains_key(name)
+    }
+
+    /// Returns false if the account already exists.
+    pub fn create_account(&mut self, name: &MaidNodeName) -> bool {
+        if self.exist(name) {
+            return false;
+        }
+        let _ = self.storage.insert(name.clone(), Default::default());
+        true
+    }
+
+    pub fn get_balance(&self, name: &MaidNodeName) -> u64 {
+        self.storage.get(name).map(|value| value.space_available()).unwrap_or(0)
     }
 
     pub fn put_data(&mut self, name: &MaidNodeName, size: u64) -> bool {
-        let default: AccountValue = Default::default();
-        let entry = self.storage.entry(name.clone()).or_insert(default);
-        entry.put_data(size)
+        match self.storage.get_mut(name) {
+            Some(value) => value.put_data(size),
+            None => false,
+        }
+    }
+
+    pub fn buy_space(&mut self, name: &MaidNodeName, amount: u64, bytes_per_coin: u64) -> bool {
+        match self.storage.get_mut(name) {
+            Some(value) => value.buy_space(amount, bytes_per_coin),
+            None => false,
+        }
+    }
+
+    pub fn get_wallet_balance(&self, name: &MaidNodeName) -> u64 {
+        self.storage.get(name).map(|value| value.wallet_balance()).unwrap_or(0)
+    }
+
+    pub fn debit_wallet(&mut self, name: &MaidNodeName, amount: u64) -> bool {
+        match self.storage.get_mut(name) {
+            Some(value) => value.debit_wallet(amount),
+            None => false,
+        }
+    }
+
+    // Only a client which created its account has a wallet to be credited
+    pub fn credit_wallet(&mut self, name: &MaidNodeName, amount: u64) -> bool {
+        match self.storage.get_mut(name) {
+            Some(value) => {
+                value.credit_wallet(amount);
+                true
+            }
+            None => false,
+        }
     }
 
     pub fn handle_account_transfer(&mut self, merged_account: Account) {
@@ -147,19 +243,32 @@ impl MaidManagerDatabase {
     }
 
     pub fn retrieve_all_and_reset(&mut self) -> Vec<::types::MethodCall> {
-        let mut actions = Vec::with_capacity(self.storage.len());
+        let actions = self.storage
+                          .iter()
+                          .filter_map(|(key, value)| refresh(key, value))
+                          .collect();
+        self.storage.clear();
+        actions
+    }
+
+    /// Refreshes the accounts whose group changed, dropping those we're no longer responsible for.
+    pub fn handle_churn(&mut self, churn: &::churn::Churn) -> Vec<::types::MethodCall> {
+        let mut actions = Vec::new();
+        let mut lost = Vec::new();
         for (key, value) in self.storage.iter() {
-            let account = Account::new((*key).clone(), (*value).clone());
-            let mut encoder = cbor::Encoder::from_memory();
-            if encoder.encode(&[account.clone()]).is_ok() {
-                actions.push(::types::MethodCall::Refresh {
-                    type_tag: MAID_MANAGER_ACCOUNT_TAG,
-                    our_authority: ::routing::Authority::ClientManager(*account.name()),
-                    payload: encoder.as_bytes().to_vec()
-                });
+            if !churn.affects(key) {
+                continue;
+            }
+            if let Some(action) = refresh(key, value) {
+                actions.push(action);
+            }
+            if !churn.keeps(key) {
+                lost.push(key.clone());
             }
         }
-        self.storage.clear();
+        for name in lost {
+            let _ = self.storage.remove(&name);
+        }
         actions
     }
 
@@ -172,16 +281,39 @@ impl MaidManagerDatabase {
     }
 }
 
+fn refresh(name: &MaidNodeName, value: &AccountValue) -> Option<::types::MethodCall> {
+    let account = Account::new(name.clone(), value.clone());
+    match ::transfer_parser::encode(MAID_MANAGER_ACCOUNT_TAG, &account) {
+        Some(payload) => Some(::types::MethodCall::Refresh {
+            type_tag: MAID_MANAGER_ACCOUNT_TAG,
+            our_authority: ::routing::Authority::ClientManager(name.clone()),
+            payload: payload
+        }),
+        None => None,
+    }
+}
+
 
 #[cfg(test)]
 mod test {
     use cbor;
     use super::*;
 
+    // Creates an account holding 1GiB of space
+    fn create_account(db: &mut MaidManagerDatabase, name: &MaidNodeName) {
+        assert!(db.create_account(name));
+        assert!(db.credit_wallet(name, 1));
+        assert!(db.buy_space(name, 1, 1073741824));
+    }
+
     #[test]
     fn put_data() {
         let mut db = MaidManagerDatabase::new();
         let name = ::utils::random_name();
+        // no account
+        assert!(!db.put_data(&name, 0));
+        assert!(!db.exist(&name));
+        create_account(&mut db, &name);
         assert!(db.put_data(&name, 0));
         assert!(db.put_data(&name, 1));
         assert!(db.put_data(&name, 1073741823));
@@ -198,6 +330,7 @@ mod test {
         let name = ::utils::random_name();
         db.delete_data(&name, 0);
         assert!(!db.storage.contains_key(&name));
+        create_account(&mut db, &name);
         assert!(db.put_data(&name, 0));
         assert!(db.storage.contains_key(&name));
         db.delete_data(&name, 1);
@@ -217,11 +350,12 @@ mod test {
     fn handle_account_transfer() {
         let mut db = MaidManagerDatabase::new();
         let name = ::utils::random_name();
+        create_account(&mut db, &name);
         assert!(db.put_data(&name, 0));
         assert!(db.put_data(&name, 1073741823));
         assert!(!db.put_data(&name, 2));
 
-        let mut account_value: AccountValue = Default::default();
+        let mut account_value = AccountValue::new(0, 1073741824, 0);
         account_value.put_data(1073741822);
         {
             let account = Account::new(name.clone(), account_value.clone());
@@ -239,10 +373,68 @@ mod test {
         assert!(db.put_data(&name, 1073741824));
     }
 
+    #[test]
+    fn buy_space() {
+        let mut db = MaidManagerDatabase::new();
+        let name = ::utils::random_name();
+        assert!(!db.buy_space(&name, 0, 1024));
+        assert!(db.create_account(&name));
+        assert!(!db.create_account(&name));
+        assert_eq!(db.get_balance(&name), 0);
+        assert!(!db.buy_space(&name, 1, 1024));
+        assert!(db.credit_wallet(&name, 3));
+        assert!(db.buy_space(&name, 2, 1024));
+        assert_eq!(db.get_balance(&name), 2048);
+        assert_eq!(db.get_wallet_balance(&name), 1);
+        assert!(!db.put_data(&name, 2049));
+        assert!(db.put_data(&name, 2048));
+    }
+
+    #[test]
+    fn wallet() {
+        let mut db = MaidManagerDatabase::new();
+        let name = ::utils::random_name();
+        // there is no wallet before the account is created
+        assert!(!db.debit_wallet(&name, 0));
+        assert!(!db.debit_wallet(&name, 1));
+        assert!(!db.credit_wallet(&name, 100));
+        assert!(!db.exist(&name));
+        assert!(db.create_account(&name));
+        assert!(db.debit_wallet(&name, 0));
+        assert!(db.credit_wallet(&name, 100));
+        assert_eq!(db.get_wallet_balance(&name), 100);
+        assert!(!db.debit_wallet(&name, 101));
+        assert!(db.debit_wallet(&name, 60));
+        assert_eq!(db.get_wallet_balance(&name), 40);
+        assert!(db.credit_wallet(&name, ::std::u64::MAX));
+        assert_eq!(db.get_wallet_balance(&name), ::std::u64::MAX);
+    }
+
+    #[test]
+    fn merge() {
+        use types::Refreshable;
+        let name = ::utils::random_name();
+        let responses = vec![Account::new(name.clone(), AccountValue::new(1, 1000, 10)),
+                             Account::new(name.clone(), AccountValue::new(1, 1000, 30)),
+                             Account::new(name.clone(), AccountValue::new(1, 1000, 20)),
+                             // an account for another name is ignored
+                             Account::new(::utils::random_name(), AccountValue::new(0, 0, 0))];
+        let merged = Account::merge(name.clone(), responses.clone(), 3).unwrap();
+        assert_eq!(merged, Account::new(name.clone(), AccountValue::new(1, 1000, 20)));
+
+        // a single response, or one for another name, is no longer enough
+        assert_eq!(Account::merge(name.clone(), responses.clone(), 4),
+                   Err(::types::MergeError::InsufficientQuorum));
+        assert_eq!(Account::merge(name.clone(), vec![responses[3].clone()], 1),
+                   Err(::types::MergeError::NameMismatch));
+        assert_eq!(Account::merge(name, vec![], 1), Err(::types::MergeError::InsufficientQuorum));
+    }
+
     #[test]
     fn maid_manager_account_serialisation() {
         let obj_before = Account::new(::routing::NameType([1u8; 64]),
                                       AccountValue::new(::rand::random::<u64>(),
+                                                        ::rand::random::<u64>(),
                                                         ::rand::random::<u64>()));
 
         let mut e = cbor::Encoder::from_memory();
@@ -16,11 +16,21 @@
 // relating to use of the SAFE Network Software.
 
 mod database;
+mod wallet;
 
-pub use self::database::Account;
+pub use self::database::{Account, AccountValue};
+pub use self::wallet::WalletRequest;
+
+use types::PlainPayload;
 
 type Address = ::routing::NameType;
 
+// bytes of space a safecoin buys (see docs/maid_account.md).  The price is fixed, so every member
+// of a client's ClientManager group grants the same space for the same coins.  It isn't derived
+// from the farming rate, which each DataManager group holds for its own chunks and which the
+// ClientManagers don't know.
+pub static BYTES_PER_COIN: u64 = 1073741824;
+
 pub struct MaidManager {
     database: database::MaidManagerDatabase,
 }
@@ -33,14 +43,112 @@ impl MaidManager {
     pub fn handle_put(&mut self, from: &::routing::NameType,
                       from_authority: ::routing::authority::Authority,
                       data: ::routing::data::Data) -> Vec<::types::MethodCall> {
+        if !self.database.exist(from) {
+            return vec![::types::MethodCall::NoAccount { location: from_authority, data: data }];
+        }
         if self.database.put_data(from, data.payload_size() as u64) {
             vec![::types::MethodCall::Put {
                      location: ::routing::authority::Authority::NaeManager(data.name()),
                      content: data
                  }]
         } else {
-            vec![::types::MethodCall::LowBalance { location: from_authority,
-                                                   data: data, balance: self.database.get_balance(from) as u32}]
+            vec![::types::MethodCall::LowBalance {
+                     location: from_authority,
+                     data: data,
+                     balance: self.database.get_balance(from),
+                 }]
+        }
+    }
+
+    /// A transfer is validated and debited by the sender's ClientManagers, which then ask the
+    /// recipient's ClientManagers to credit the amount.  If the recipient has no account, they
+    /// refund it to the sender.
+    pub fn handle_post(&mut self,
+                       account: &::routing::NameType,
+                       from_authority: ::routing::authority::Authority,
+                       data: ::routing::data::Data,
+                       request: WalletRequest)
+                       -> Vec<::types::MethodCall> {
+        match (request, from_authority.clone()) {
+            (WalletRequest::Transfer { recipient, amount },
+             ::routing::authority::Authority::Client(_, _)) => {
+                if recipient == *account {
+                    return vec![::types::MethodCall::InvalidRequest { data: data }];
+                }
+                if !self.database.debit_wallet(account, amount) {
+                    return vec![::types::MethodCall::LowBalance {
+                        location: from_authority,
+                        data: data,
+                        balance: self.database.get_wallet_balance(account),
+                    }];
+                }
+                let credit = WalletRequest::Credit { sender: *account, amount: amount };
+                match credit.to_plain_data(data.name()) {
+                    Some(content) => vec![::types::MethodCall::Post {
+                        location: ::routing::authority::Authority::ClientManager(recipient),
+                        content: content,
+                    }],
+                    None => vec![],
+                }
+            }
+            // Credited by the ClientManagers of the paying client, who are refunded the amount if
+            // it can't be credited to a wallet nobody created
+            (WalletRequest::Credit { sender, amount },
+             ::routing::authority::Authority::ClientManager(from_group)) => {
+                if sender != from_group {
+                    return vec![::types::MethodCall::InvalidRequest { data: data }];
+                }
+                if self.database.credit_wallet(account, amount) {
+                    return vec![];
+                }
+                let refund = WalletRequest::Refund { recipient: *account, amount: amount };
+                match refund.to_plain_data(data.name()) {
+                    Some(content) => vec![::types::MethodCall::Post {
+                        location: ::routing::authority::Authority::ClientManager(sender),
+                        content: content,
+                    }],
+                    None => vec![],
+                }
+            }
+            // or, for a farming reward, by the NodeManagers of the farming PmidNode
+            (WalletRequest::Credit { sender, amount },
+             ::routing::authority::Authority::NodeManager(from_group)) => {
+                if sender != from_group || !self.database.credit_wallet(account, amount) {
+                    return vec![::types::MethodCall::InvalidRequest { data: data }];
+                }
+                vec![]
+            }
+            (WalletRequest::Refund { recipient, amount },
+             ::routing::authority::Authority::ClientManager(from_group)) => {
+                if recipient != from_group || !self.database.credit_wallet(account, amount) {
+                    return vec![::types::MethodCall::InvalidRequest { data: data }];
+                }
+                vec![]
+            }
+            (WalletRequest::CreateAccount, ::routing::authority::Authority::Client(_, _)) => {
+                if self.database.create_account(account) {
+                    vec![]
+                } else {
+                    vec![::types::MethodCall::InvalidRequest { data: data }]
+                }
+            }
+            (WalletRequest::BuySpace { amount },
+             ::routing::authority::Authority::Client(_, _)) => {
+                if !self.database.exist(account) {
+                    return vec![::types::MethodCall::NoAccount { location: from_authority,
+                                                                 data: data }];
+                }
+                if self.database.buy_space(account, amount, BYTES_PER_COIN) {
+                    vec![]
+                } else {
+                    vec![::types::MethodCall::LowBalance {
+                        location: from_authority,
+                        data: data,
+                        balance: self.database.get_wallet_balance(account),
+                    }]
+                }
+            }
+            _ => vec![::types::MethodCall::InvalidRequest { data: data }],
         }
     }
 
@@ -51,14 +159,28 @@ impl MaidManager {
     pub fn retrieve_all_and_reset(&mut self) -> Vec<::types::MethodCall> {
         self.database.retrieve_all_and_reset()
     }
+
+    pub fn handle_churn(&mut self, churn: &::churn::Churn) -> Vec<::types::MethodCall> {
+        self.database.handle_churn(churn)
+    }
 }
 
 #[cfg(test)]
 mod test {
     use sodiumoxide::crypto;
+    use types::PlainPayload;
 
     use super::*;
 
+    fn post(maid_manager: &mut MaidManager,
+            account: &::routing::NameType,
+            from_authority: ::routing::authority::Authority,
+            request: WalletRequest)
+            -> Vec<::types::MethodCall> {
+        let data = request.to_plain_data(::utils::random_name()).unwrap();
+        maid_manager.handle_post(account, from_authority, data, request)
+    }
+
     #[test]
     fn handle_put() {
         let mut maid_manager = MaidManager::new();
@@ -68,6 +190,27 @@ mod test {
         let value = ::routing::types::generate_random_vec_u8(1024);
         let data = ::routing::immutable_data::ImmutableData::new(
                        ::routing::immutable_data::ImmutableDataType::Normal, value);
+        let put_result = maid_manager.handle_put(&from, client.clone(),
+                                                 ::routing::data::Data::ImmutableData(data.clone()));
+        assert_eq!(put_result, vec![::types::MethodCall::NoAccount {
+            location: client.clone(),
+            data: ::routing::data::Data::ImmutableData(data.clone()),
+        }]);
+
+        assert!(post(&mut maid_manager, &from, client.clone(),
+                     WalletRequest::CreateAccount).is_empty());
+        // no space bought yet
+        match maid_manager.handle_put(&from, client.clone(),
+                                      ::routing::data::Data::ImmutableData(data.clone()))[0] {
+            ::types::MethodCall::LowBalance { .. } => (),
+            _ => panic!("Unexpected"),
+        }
+        let farmer = ::utils::random_name();
+        assert!(post(&mut maid_manager, &from,
+                     ::routing::authority::Authority::NodeManager(farmer.clone()),
+                     WalletRequest::Credit { sender: farmer, amount: 1 }).is_empty());
+        assert!(post(&mut maid_manager, &from, client.clone(),
+                     WalletRequest::BuySpace { amount: 1 }).is_empty());
         let put_result = maid_manager.handle_put(&from, client,
                                                  ::routing::data::Data::ImmutableData(data.clone()));
 
@@ -80,4 +223,66 @@ mod test {
             _ => panic!("Unexpected"),
         }
     }
+
+    #[test]
+    fn transfer() {
+        let (sender, recipient) = (::utils::random_name(), ::utils::random_name());
+        let sender_client = ::routing::authority::Authority::Client(
+                                sender, crypto::sign::gen_keypair().0);
+        let sender_managers = ::routing::authority::Authority::ClientManager(sender);
+        let mut maid_manager = MaidManager::new();
+
+        let credit = WalletRequest::Credit { sender: sender, amount: 50 };
+        let credit_data = credit.to_plain_data(::utils::random_name()).unwrap();
+        // no wallet is credited before its account is created, the amount is refunded instead
+        let payer = ::utils::random_name();
+        let payment = WalletRequest::Credit { sender: payer, amount: 50 };
+        let payment_data = payment.to_plain_data(::utils::random_name()).unwrap();
+        let refund = WalletRequest::Refund { recipient: sender, amount: 50 };
+        assert_eq!(maid_manager.handle_post(&sender,
+                                            ::routing::authority::Authority::ClientManager(payer),
+                                            payment_data.clone(), payment),
+                   vec![::types::MethodCall::Post {
+                       location: ::routing::authority::Authority::ClientManager(payer),
+                       content: refund.to_plain_data(payment_data.name()).unwrap(),
+                   }]);
+        assert!(post(&mut maid_manager, &sender, sender_client.clone(),
+                     WalletRequest::CreateAccount).is_empty());
+        assert!(maid_manager.handle_post(&sender, sender_managers.clone(), credit_data.clone(),
+                                         credit.clone()).is_empty());
+        // a client can't credit itself
+        assert_eq!(maid_manager.handle_post(&sender, sender_client.clone(), credit_data.clone(),
+                                            credit),
+                   vec![::types::MethodCall::InvalidRequest { data: credit_data }]);
+
+        let transfer = WalletRequest::Transfer { recipient: recipient, amount: 30 };
+        let transfer_data = transfer.to_plain_data(::utils::random_name()).unwrap();
+        let post_result = maid_manager.handle_post(&sender, sender_client.clone(),
+                                                   transfer_data.clone(), transfer.clone());
+        let expected = WalletRequest::Credit { sender: sender, amount: 30 }
+                           .to_plain_data(transfer_data.name()).unwrap();
+        assert_eq!(post_result, vec![::types::MethodCall::Post {
+            location: ::routing::authority::Authority::ClientManager(recipient),
+            content: expected,
+        }]);
+
+        // only 20 left
+        assert_eq!(maid_manager.handle_post(&sender, sender_client.clone(), transfer_data.clone(),
+                                            transfer),
+                   vec![::types::MethodCall::LowBalance { location: sender_client.clone(),
+                                                          data: transfer_data.clone(),
+                                                          balance: 20 }]);
+
+        // the unknown recipient refunds the 30, only from its own ClientManagers
+        let refund = WalletRequest::Refund { recipient: recipient, amount: 30 };
+        let refund_data = refund.to_plain_data(transfer_data.name()).unwrap();
+        assert_eq!(maid_manager.handle_post(&sender, sender_client.clone(), refund_data.clone(),
+                                            refund.clone()),
+                   vec![::types::MethodCall::InvalidRequest { data: refund_data.clone() }]);
+        assert!(maid_manager.handle_post(&sender,
+                                         ::routing::authority::Authority::ClientManager(recipient),
+                                         refund_data, refund).is_empty());
+        let transfer = WalletRequest::Transfer { recipient: recipient, amount: 50 };
+        assert_eq!(post(&mut maid_manager, &sender, sender_client, transfer).len(), 1);
+    }
 }
@@ -0,0 +1,50 @@
+// Copyright 2015 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+/// Wallet requests travel as the tagged payload of a PlainData posted to a ClientManager.
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Eq, Debug, Clone)]
+pub enum WalletRequest {
+    /// Posted by a client to its own ClientManagers to pay `amount` into the recipient's wallet
+    Transfer {
+        recipient: ::routing::NameType,
+        amount: u64,
+    },
+    /// Posted by the sender's ClientManagers, once the amount has been debited, to the
+    /// recipient's.  Farming rewards are posted by the NodeManagers of the farming PmidNode.
+    Credit {
+        sender: ::routing::NameType,
+        amount: u64,
+    },
+    /// Posted back by the recipient's ClientManagers to the sender's when the recipient has no
+    /// account, so that the debited amount isn't lost
+    Refund {
+        recipient: ::routing::NameType,
+        amount: u64,
+    },
+    /// Posted by a client to its own ClientManagers before it can buy space and put data
+    CreateAccount,
+    /// Posted by a client to spend `amount` of safecoin on space, at the fixed `BYTES_PER_COIN`
+    BuySpace {
+        amount: u64,
+    },
+}
+
+impl ::types::PlainPayload for WalletRequest {
+    fn type_tag() -> u64 {
+        ::types::payload_tags::WALLET_REQUEST_TAG
+    }
+}
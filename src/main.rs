@@ -98,21 +98,24 @@ extern crate env_logger;
 
 // Non-MaidSafe crates
 extern crate cbor;
+extern crate chan_signal;
+extern crate rand;
 extern crate rustc_serialize;
 extern crate sodiumoxide;
 extern crate tempdir;
 extern crate time;
-#[cfg(test)]
-extern crate rand;
 
 // MaidSafe crates
 extern crate lru_time_cache;
 extern crate routing;
 
 mod chunk_store;
+mod churn;
+mod config;
 mod data_manager;
 mod macros;
 mod maid_manager;
+mod mpid_manager;
 mod pmid_manager;
 mod pmid_node;
 mod sd_manager;
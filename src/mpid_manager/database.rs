@@ -0,0 +1,615 @@
+// Copyright 2015 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use std::collections;
+
+use super::messaging::{MpidHeader, MpidHeaderV0, MpidMessage};
+use transfer_parser::transfer_tags::MPID_MANAGER_ACCOUNT_TAG;
+
+pub type MpidNodeName = ::routing::NameType;
+
+pub static MAX_HEADER_METADATA_SIZE: u64 = 128;
+pub static MAX_INBOX_SIZE: u64 = 128 * 1024;
+
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Eq, Debug, Clone)]
+pub struct Account {
+    name: MpidNodeName,
+    value: AccountValue,
+}
+
+impl Account {
+    pub fn new(name: MpidNodeName, value: AccountValue) -> Account {
+        Account { name: name, value: value }
+    }
+
+    pub fn name(&self) -> &MpidNodeName {
+        &self.name
+    }
+
+    pub fn value(&self) -> &AccountValue {
+        &self.value
+    }
+}
+
+impl ::types::Refreshable for Account {
+    // Only the messages, headers and blocked senders reported by a majority of the group are kept,
+    // with the median expiry
+    fn merge(from_group: ::routing::NameType,
+             responses: Vec<Account>,
+             quorum: usize)
+             -> Result<Account, ::types::MergeError> {
+        let responses = try!(::types::responses_for(&from_group, responses, quorum,
+                                                    Account::name));
+        let mut outbox = Vec::<(MpidMessage, Vec<u64>)>::new();
+        let mut inbox = Vec::<(MpidHeader, Vec<u64>)>::new();
+        let mut blocked = Vec::<(MpidNodeName, Vec<u64>)>::new();
+        for response in responses {
+            for stored in response.value.outbox {
+                add_vote(&mut outbox, stored.message, stored.expires_at);
+            }
+            for stored in response.value.inbox {
+                add_vote(&mut inbox, stored.header, stored.expires_at);
+            }
+            for sender in response.value.blocked {
+                add_vote(&mut blocked, sender, 0);
+            }
+        }
+        let value = AccountValue {
+            outbox: outbox.into_iter()
+                          .filter(|a| a.1.len() >= quorum)
+                          .map(|(message, expiries)| StoredMessage {
+                              message: message,
+                              expires_at: ::utils::median(expiries),
+                          })
+                          .collect(),
+            inbox: inbox.into_iter()
+                        .filter(|a| a.1.len() >= quorum)
+                        .map(|(header, expiries)| StoredHeader {
+                            header: header,
+                            expires_at: ::utils::median(expiries),
+                        })
+                        .collect(),
+            blocked: blocked.into_iter().filter(|a| a.1.len() >= quorum).map(|a| a.0).collect(),
+        };
+        if value.is_empty() {
+            return Err(::types::MergeError::InsufficientQuorum);
+        }
+        Ok(Account::new(from_group, value))
+    }
+
+    // Headers of versions 0 and 1 have no nonce, they keep the names they were given by then
+    fn decode_version(version: u64, contents: &Vec<u8>) -> Option<Account> {
+        match version {
+            0 | 1 => ::routing::utils::decode::<AccountV0>(contents).ok().map(|account| {
+                Account::new(account.name, AccountValue {
+                    outbox: account.value.outbox.into_iter().map(|stored| StoredMessage {
+                        message: MpidMessage::new(stored.message.header.upgrade(),
+                                                  stored.message.body),
+                        expires_at: stored.expires_at,
+                    }).collect(),
+                    inbox: account.value.inbox.into_iter().map(|stored| StoredHeader {
+                        header: stored.header.upgrade(),
+                        expires_at: stored.expires_at,
+                    }).collect(),
+                    blocked: account.value.blocked,
+                })
+            }),
+            ::transfer_parser::FORMAT_VERSION => ::routing::utils::decode(contents).ok(),
+            _ => None,
+        }
+    }
+}
+
+// An account as transferred by versions 0 and 1 of the transfer format
+#[derive(RustcEncodable, RustcDecodable)]
+struct AccountV0 {
+    name: MpidNodeName,
+    value: AccountValueV0,
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+struct AccountValueV0 {
+    outbox: Vec<StoredMessageV0>,
+    inbox: Vec<StoredHeaderV0>,
+    blocked: Vec<MpidNodeName>,
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+struct StoredMessageV0 {
+    message: MpidMessageV0,
+    expires_at: u64,
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+struct MpidMessageV0 {
+    header: MpidHeaderV0,
+    body: Vec<u8>,
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+struct StoredHeaderV0 {
+    header: MpidHeaderV0,
+    expires_at: u64,
+}
+
+fn add_vote<T: PartialEq>(votes: &mut Vec<(T, Vec<u64>)>, item: T, expires_at: u64) {
+    let push_in_vec = match votes.iter_mut().find(|a| a.0 == item) {
+        Some(find_res) => {
+            find_res.1.push(expires_at);
+            false
+        }
+        None => true,
+    };
+    if push_in_vec {
+        votes.push((item, vec![expires_at]));
+    }
+}
+
+
+
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Eq, Debug, Clone)]
+struct StoredMessage {
+    message: MpidMessage,
+    // seconds since the epoch
+    expires_at: u64,
+}
+
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Eq, Debug, Clone)]
+struct StoredHeader {
+    header: MpidHeader,
+    // seconds since the epoch
+    expires_at: u64,
+}
+
+/// The configurable limits applied to every account held by an MpidManager.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Limits {
+    pub max_message_size: u64,
+    pub max_outbox_size: u64,
+    pub retention: u64,
+}
+
+impl Limits {
+    pub fn new(config: &::config::Config) -> Limits {
+        Limits {
+            max_message_size: config.mpid_message_size,
+            max_outbox_size: config.mpid_outbox_size,
+            retention: config.mpid_message_retention,
+        }
+    }
+}
+
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Eq, Debug, Clone)]
+pub struct AccountValue {
+    // messages sent by the account owner which haven't been retrieved or withdrawn yet
+    outbox: Vec<StoredMessage>,
+    // headers of messages waiting to be retrieved by the account owner
+    inbox: Vec<StoredHeader>,
+    // senders whose messages the account owner refuses to be alerted of
+    blocked: Vec<MpidNodeName>,
+}
+
+impl Default for AccountValue {
+    fn default() -> AccountValue {
+        AccountValue { outbox: Vec::new(), inbox: Vec::new(), blocked: Vec::new() }
+    }
+}
+
+impl AccountValue {
+    /// The headers of the undelivered messages in the outbox, allowing the owner to pick the ones
+    /// to withdraw.
+    pub fn outbox_headers(&self) -> Vec<MpidHeader> {
+        self.outbox.iter().map(|a| a.message.header().clone()).collect()
+    }
+
+    pub fn inbox(&self) -> Vec<MpidHeader> {
+        self.inbox.iter().map(|a| a.header.clone()).collect()
+    }
+
+    #[allow(dead_code)]
+    pub fn blocked(&self) -> &Vec<MpidNodeName> {
+        &self.blocked
+    }
+
+    pub fn outbox_size(&self) -> u64 {
+        self.outbox.iter().fold(0, |acc, stored| acc + message_size(&stored.message))
+    }
+
+    pub fn inbox_size(&self) -> u64 {
+        self.inbox.iter().fold(0, |acc, stored| acc + header_size(&stored.header))
+    }
+
+    pub fn put_message(&mut self, message: MpidMessage, limits: &Limits, now: u64) -> bool {
+        if message.body().len() as u64 > limits.max_message_size ||
+           message.header().metadata().len() as u64 > MAX_HEADER_METADATA_SIZE ||
+           self.outbox_size() + message_size(&message) > limits.max_outbox_size ||
+           self.outbox.iter().any(|a| a.message.name() == message.name()) {
+            return false;
+        }
+        self.outbox.push(StoredMessage { message: message, expires_at: now + limits.retention });
+        true
+    }
+
+    pub fn put_header(&mut self, header: MpidHeader, limits: &Limits, now: u64) -> bool {
+        if header.metadata().len() as u64 > MAX_HEADER_METADATA_SIZE ||
+           self.inbox_size() + header_size(&header) > MAX_INBOX_SIZE ||
+           self.blocked.contains(header.sender()) {
+            return false;
+        }
+        if !self.inbox.iter().any(|a| a.header == header) {
+            self.inbox.push(StoredHeader { header: header, expires_at: now + limits.retention });
+        }
+        true
+    }
+
+    pub fn get_message(&self, name: &::routing::NameType) -> Option<MpidMessage> {
+        self.outbox.iter().find(|a| a.message.name() == *name).map(|a| a.message.clone())
+    }
+
+    pub fn get_header(&self, name: &::routing::NameType) -> Option<MpidHeader> {
+        self.inbox.iter().find(|a| a.header.name() == *name).map(|a| a.header.clone())
+    }
+
+    pub fn remove_message(&mut self, name: &::routing::NameType) -> Option<MpidMessage> {
+        match self.outbox.iter().position(|a| a.message.name() == *name) {
+            Some(index) => Some(self.outbox.remove(index).message),
+            None => None,
+        }
+    }
+
+    pub fn remove_header(&mut self, name: &::routing::NameType) -> Option<MpidHeader> {
+        match self.inbox.iter().position(|a| a.header.name() == *name) {
+            Some(index) => Some(self.inbox.remove(index).header),
+            None => None,
+        }
+    }
+
+    /// Blocks the sender and drops the headers already received from it.  The messages themselves
+    /// stay in the sender's outbox until they expire or are withdrawn.
+    pub fn block(&mut self, sender: MpidNodeName) {
+        self.inbox.retain(|a| *a.header.sender() != sender);
+        if !self.blocked.contains(&sender) {
+            self.blocked.push(sender);
+        }
+    }
+
+    pub fn unblock(&mut self, sender: &MpidNodeName) {
+        self.blocked.retain(|a| a != sender);
+    }
+
+    fn purge_expired(&mut self, now: u64) {
+        self.outbox.retain(|a| a.expires_at > now);
+        self.inbox.retain(|a| a.expires_at > now);
+    }
+
+    fn is_empty(&self) -> bool {
+        self.outbox.is_empty() && self.inbox.is_empty() && self.blocked.is_empty()
+    }
+}
+
+fn message_size(message: &MpidMessage) -> u64 {
+    message.body().len() as u64 + header_size(message.header())
+}
+
+fn header_size(header: &MpidHeader) -> u64 {
+    // sender and recipient names plus the metadata
+    128 + header.metadata().len() as u64
+}
+
+fn now() -> u64 {
+    ::time::get_time().sec as u64
+}
+
+
+
+pub struct MpidManagerDatabase {
+    storage: collections::HashMap<MpidNodeName, AccountValue>,
+    limits: Limits,
+}
+
+impl MpidManagerDatabase {
+    pub fn new(limits: Limits) -> MpidManagerDatabase {
+        MpidManagerDatabase {
+            storage: collections::HashMap::with_capacity(10000),
+            limits: limits,
+        }
+    }
+
+    pub fn get(&mut self, name: &MpidNodeName) -> Option<&AccountValue> {
+        self.purge_expired(name);
+        self.storage.get(name)
+    }
+
+    pub fn put_message(&mut self, name: &MpidNodeName, message: MpidMessage) -> bool {
+        self.purge_expired(name);
+        let stored = {
+            let default: AccountValue = Default::default();
+            self.storage.entry(name.clone()).or_insert(default)
+                .put_message(message, &self.limits, now())
+        };
+        self.drop_if_empty(name);
+        stored
+    }
+
+    pub fn put_header(&mut self, name: &MpidNodeName, header: MpidHeader) -> bool {
+        self.purge_expired(name);
+        let stored = {
+            let default: AccountValue = Default::default();
+            self.storage.entry(name.clone()).or_insert(default)
+                .put_header(header, &self.limits, now())
+        };
+        self.drop_if_empty(name);
+        stored
+    }
+
+    pub fn remove_message(&mut self, name: &MpidNodeName, message_name: &::routing::NameType)
+                          -> Option<MpidMessage> {
+        let removed = match self.storage.get_mut(name) {
+            Some(value) => value.remove_message(message_name),
+            None => None,
+        };
+        self.drop_if_empty(name);
+        removed
+    }
+
+    pub fn remove_header(&mut self, name: &MpidNodeName, header_name: &::routing::NameType)
+                         -> Option<MpidHeader> {
+        let removed = match self.storage.get_mut(name) {
+            Some(value) => value.remove_header(header_name),
+            None => None,
+        };
+        self.drop_if_empty(name);
+        removed
+    }
+
+    pub fn block(&mut self, name: &MpidNodeName, sender: MpidNodeName) {
+        let default: AccountValue = Default::default();
+        self.storage.entry(name.clone()).or_insert(default).block(sender);
+    }
+
+    pub fn unblock(&mut self, name: &MpidNodeName, sender: &MpidNodeName) {
+        if let Some(value) = self.storage.get_mut(name) {
+            value.unblock(sender);
+        }
+        self.drop_if_empty(name);
+    }
+
+    pub fn handle_account_transfer(&mut self, merged_account: Account) {
+        let _ = self.storage.remove(merged_account.name());
+        let _ = self.storage.insert(*merged_account.name(), merged_account.value().clone());
+        info!("MpidManager updated account {:?}", merged_account.name());
+    }
+
+    pub fn retrieve_all_and_reset(&mut self) -> Vec<::types::MethodCall> {
+        let now = now();
+        let mut actions = Vec::with_capacity(self.storage.len());
+        for (key, value) in self.storage.iter_mut() {
+            value.purge_expired(now);
+            if let Some(action) = refresh(key, value) {
+                actions.push(action);
+            }
+        }
+        self.storage.clear();
+        actions
+    }
+
+    /// Refreshes the accounts whose group changed, dropping those we're no longer responsible for.
+    pub fn handle_churn(&mut self, churn: &::churn::Churn) -> Vec<::types::MethodCall> {
+        let now = now();
+        let mut actions = Vec::new();
+        let mut lost = Vec::new();
+        for (key, value) in self.storage.iter_mut() {
+            if !churn.affects(key) {
+                continue;
+            }
+            value.purge_expired(now);
+            if let Some(action) = refresh(key, value) {
+                actions.push(action);
+            }
+            if !churn.keeps(key) || value.is_empty() {
+                lost.push(key.clone());
+            }
+        }
+        for name in lost {
+            let _ = self.storage.remove(&name);
+        }
+        actions
+    }
+
+    fn purge_expired(&mut self, name: &MpidNodeName) {
+        if let Some(value) = self.storage.get_mut(name) {
+            value.purge_expired(now());
+        }
+        self.drop_if_empty(name);
+    }
+
+    fn drop_if_empty(&mut self, name: &MpidNodeName) {
+        let is_empty = self.storage.get(name).map(|value| value.is_empty()).unwrap_or(false);
+        if is_empty {
+            let _ = self.storage.remove(name);
+        }
+    }
+}
+
+// Empty accounts aren't worth transferring
+fn refresh(name: &MpidNodeName, value: &AccountValue) -> Option<::types::MethodCall> {
+    if value.is_empty() {
+        return None;
+    }
+    let account = Account::new(name.clone(), value.clone());
+    match ::transfer_parser::encode(MPID_MANAGER_ACCOUNT_TAG, &account) {
+        Some(payload) => Some(::types::MethodCall::Refresh {
+            type_tag: MPID_MANAGER_ACCOUNT_TAG,
+            our_authority: ::routing::Authority::ClientManager(name.clone()),
+            payload: payload
+        }),
+        None => None,
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use cbor;
+    use super::*;
+    use super::{AccountV0, AccountValueV0, StoredHeaderV0, StoredMessageV0, MpidMessageV0};
+    use super::super::messaging::{MpidHeader, MpidHeaderV0, MpidMessage};
+    use types::Refreshable;
+
+    fn new_message(sender: ::routing::NameType, body_size: usize) -> MpidMessage {
+        MpidMessage::new(MpidHeader::new(sender, ::utils::random_name(),
+                                         ::routing::types::generate_random_vec_u8(16)),
+                         ::routing::types::generate_random_vec_u8(body_size))
+    }
+
+    fn limits() -> Limits {
+        Limits::new(&Default::default())
+    }
+
+    #[test]
+    fn put_and_remove_message() {
+        let mut db = MpidManagerDatabase::new(limits());
+        let name = ::utils::random_name();
+        let message = new_message(name.clone(), 1024);
+        assert!(db.put_message(&name, message.clone()));
+        // duplicate
+        assert!(!db.put_message(&name, message.clone()));
+        assert_eq!(db.get(&name).unwrap().get_message(&message.name()), Some(message.clone()));
+        assert_eq!(db.get(&name).unwrap().outbox_headers(), vec![message.header().clone()]);
+        assert_eq!(db.remove_message(&name, &message.name()), Some(message.clone()));
+        assert!(db.get(&name).is_none());
+        assert!(db.remove_message(&name, &message.name()).is_none());
+    }
+
+    #[test]
+    fn configured_limits() {
+        let limits = Limits { max_message_size: 1024, max_outbox_size: 10 * 1024, retention: 60 };
+        let mut db = MpidManagerDatabase::new(limits.clone());
+        let name = ::utils::random_name();
+        assert!(!db.put_message(&name, new_message(name.clone(), 1025)));
+        assert!(db.get(&name).is_none());
+        let mut stored = 0;
+        while db.put_message(&name, new_message(name.clone(), 1024)) {
+            stored += 1;
+        }
+        assert_eq!(stored, limits.max_outbox_size / (limits.max_message_size + 144));
+        assert!(db.get(&name).unwrap().outbox_size() <= limits.max_outbox_size);
+
+        let header = MpidHeader::new(::utils::random_name(), name.clone(),
+                                     vec![0u8; MAX_HEADER_METADATA_SIZE as usize + 1]);
+        assert!(!db.put_header(&name, header));
+    }
+
+    #[test]
+    fn retention() {
+        let mut db = MpidManagerDatabase::new(Limits { retention: 0, ..limits() });
+        let name = ::utils::random_name();
+        assert!(db.put_message(&name, new_message(name.clone(), 10)));
+        assert!(db.put_header(&name, MpidHeader::new(::utils::random_name(), name.clone(),
+                                                     vec![])));
+        assert!(db.get(&name).is_none());
+        assert!(db.retrieve_all_and_reset().is_empty());
+    }
+
+    #[test]
+    fn block_sender() {
+        let mut db = MpidManagerDatabase::new(limits());
+        let name = ::utils::random_name();
+        let (spammer, friend) = (::utils::random_name(), ::utils::random_name());
+        assert!(db.put_header(&name, MpidHeader::new(spammer.clone(), name.clone(), vec![])));
+        let header = MpidHeader::new(friend.clone(), name.clone(), vec![]);
+        assert!(db.put_header(&name, header.clone()));
+
+        db.block(&name, spammer.clone());
+        assert_eq!(db.get(&name).unwrap().inbox(), vec![header.clone()]);
+        assert!(!db.put_header(&name, MpidHeader::new(spammer.clone(), name.clone(), vec![1])));
+
+        db.unblock(&name, &spammer);
+        assert!(db.get(&name).unwrap().blocked().is_empty());
+        assert!(db.put_header(&name, MpidHeader::new(spammer, name.clone(), vec![1])));
+    }
+
+    #[test]
+    fn merge() {
+        let name = ::utils::random_name();
+        let agreed = new_message(name.clone(), 10);
+        let disputed = new_message(name.clone(), 10);
+        let blocked = ::utils::random_name();
+        let mut responses = Vec::new();
+        for i in 0..::routing::types::GROUP_SIZE {
+            let mut value: AccountValue = Default::default();
+            assert!(value.put_message(agreed.clone(), &limits(), 100 + i as u64));
+            if i == 0 {
+                assert!(value.put_message(disputed.clone(), &limits(), 100));
+            } else {
+                value.block(blocked.clone());
+            }
+            responses.push(Account::new(name.clone(), value));
+        }
+        let merged = Account::merge(name.clone(), responses, ::types::default_quorum()).unwrap();
+        assert_eq!(merged.value().outbox_headers(), vec![agreed.header().clone()]);
+        assert!(merged.value().inbox().is_empty());
+        assert_eq!(*merged.value().blocked(), vec![blocked]);
+    }
+
+    #[test]
+    fn mpid_manager_account_serialisation() {
+        let name = ::utils::random_name();
+        let mut value: AccountValue = Default::default();
+        assert!(value.put_message(new_message(name.clone(), 64), &limits(), 0));
+        let obj_before = Account::new(name, value);
+
+        let mut e = cbor::Encoder::from_memory();
+        e.encode(&[&obj_before]).unwrap();
+
+        let mut d = cbor::Decoder::from_bytes(e.as_bytes());
+        let obj_after: Account = d.decode().next().unwrap().unwrap();
+
+        assert_eq!(obj_before, obj_after);
+    }
+
+    #[test]
+    fn headers_without_nonce() {
+        let (name, sender) = (::utils::random_name(), ::utils::random_name());
+        let header = || MpidHeaderV0 { sender: sender, recipient: name, metadata: vec![1, 2] };
+        let old_name = ::routing::NameType::new(::sodiumoxide::crypto::hash::sha512::hash(
+                           &::routing::utils::encode(&header()).unwrap()[..]).0);
+        let old = AccountV0 {
+            name: name,
+            value: AccountValueV0 {
+                outbox: vec![StoredMessageV0 {
+                    message: MpidMessageV0 { header: header(), body: vec![3] },
+                    expires_at: 10,
+                }],
+                inbox: vec![StoredHeaderV0 { header: header(), expires_at: 20 }],
+                blocked: vec![],
+            },
+        };
+        let contents = ::routing::utils::encode(&old).unwrap();
+        for version in 0..2 {
+            let account = Account::decode_version(version, &contents).unwrap();
+            assert_eq!(account.value().get_message(&old_name),
+                       Some(MpidMessage::new(header().upgrade(), vec![3])));
+            assert_eq!(account.value().get_header(&old_name), Some(header().upgrade()));
+        }
+        // new headers always have a nonce, so never take the name of an old one
+        let new_header = MpidHeader::new(sender, name, vec![1, 2]);
+        assert!(new_header.name() != old_name);
+        assert!(new_header != header().upgrade());
+    }
+}
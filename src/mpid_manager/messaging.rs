@@ -0,0 +1,148 @@
+// Copyright 2015 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use sodiumoxide::crypto;
+
+/// The part of a message which is sent on to the recipient's MpidManagers as an alert.
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Eq, Debug, Clone)]
+pub struct MpidHeader {
+    sender: ::routing::NameType,
+    recipient: ::routing::NameType,
+    metadata: Vec<u8>,
+    // random, so that messages with the same metadata to the same recipient have distinct names;
+    // zero for the headers of vaults predating it
+    nonce: u64,
+}
+
+impl MpidHeader {
+    pub fn new(sender: ::routing::NameType,
+               recipient: ::routing::NameType,
+               metadata: Vec<u8>)
+               -> MpidHeader {
+        MpidHeader {
+            sender: sender,
+            recipient: recipient,
+            metadata: metadata,
+            nonce: match ::rand::random::<u64>() {
+                0 => 1,
+                nonce => nonce,
+            },
+        }
+    }
+
+    /// The name of a header, and of the message it belongs to, is the hash of the serialised
+    /// header, which includes a random nonce.  Headers without one keep the name they were given
+    /// before the nonce was added.
+    pub fn name(&self) -> ::routing::NameType {
+        let encoded = if self.nonce == 0 {
+            ::routing::utils::encode(&MpidHeaderV0 {
+                sender: self.sender,
+                recipient: self.recipient,
+                metadata: self.metadata.clone(),
+            })
+        } else {
+            ::routing::utils::encode(&self)
+        };
+        ::routing::NameType::new(crypto::hash::sha512::hash(&encoded.unwrap_or(vec![])[..]).0)
+    }
+
+    pub fn sender(&self) -> &::routing::NameType {
+        &self.sender
+    }
+
+    pub fn recipient(&self) -> &::routing::NameType {
+        &self.recipient
+    }
+
+    pub fn metadata(&self) -> &Vec<u8> {
+        &self.metadata
+    }
+}
+
+/// A header as written by the vaults predating the nonce, up to version 1 of the transfer format.
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Eq, Debug, Clone)]
+pub struct MpidHeaderV0 {
+    pub sender: ::routing::NameType,
+    pub recipient: ::routing::NameType,
+    pub metadata: Vec<u8>,
+}
+
+impl MpidHeaderV0 {
+    pub fn upgrade(self) -> MpidHeader {
+        MpidHeader {
+            sender: self.sender,
+            recipient: self.recipient,
+            metadata: self.metadata,
+            nonce: 0,
+        }
+    }
+}
+
+/// A message held in the sender's outbox until the recipient retrieves it or the sender withdraws
+/// it.  Each message targets exactly one recipient.
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Eq, Debug, Clone)]
+pub struct MpidMessage {
+    header: MpidHeader,
+    body: Vec<u8>,
+}
+
+impl MpidMessage {
+    pub fn new(header: MpidHeader, body: Vec<u8>) -> MpidMessage {
+        MpidMessage { header: header, body: body }
+    }
+
+    pub fn name(&self) -> ::routing::NameType {
+        self.header.name()
+    }
+
+    pub fn header(&self) -> &MpidHeader {
+        &self.header
+    }
+
+    pub fn body(&self) -> &Vec<u8> {
+        &self.body
+    }
+}
+
+/// Messaging requests travel as the tagged payload of a PlainData addressed to a ClientManager.
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Eq, Debug, Clone)]
+pub enum MpidMessageWrapper {
+    /// Put by a client to its own MpidManagers to send a message
+    PutMessage(MpidMessage),
+    /// Put by the sender's MpidManagers to the recipient's MpidManagers
+    PutHeader(MpidHeader),
+    /// Posted by the sender to withdraw a message, or by the recipient's MpidManagers once the
+    /// message has been retrieved
+    DeleteMessage(::routing::NameType),
+    /// Posted by the recipient once a message has been retrieved, or by the sender's MpidManagers
+    /// once the message has been withdrawn
+    DeleteHeader(::routing::NameType),
+    /// Posted by the sender to list the messages still held in its outbox
+    GetOutboxHeaders,
+    /// Sent back by the sender's MpidManagers in reply to `GetOutboxHeaders`
+    OutboxHeaders(Vec<MpidHeader>),
+    /// Posted by the recipient to stop being alerted of messages from the given sender
+    Block(::routing::NameType),
+    /// Posted by the recipient to accept messages from a previously blocked sender again
+    Unblock(::routing::NameType),
+}
+
+impl ::types::PlainPayload for MpidMessageWrapper {
+    fn type_tag() -> u64 {
+        ::types::payload_tags::MPID_MESSAGE_TAG
+    }
+}
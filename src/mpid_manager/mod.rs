@@ -0,0 +1,426 @@
+// Copyright 2015 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+mod database;
+mod messaging;
+
+pub use self::database::Account;
+pub use self::messaging::{MpidHeader, MpidMessage, MpidMessageWrapper};
+
+use types::PlainPayload;
+
+/// MpidManager is a ClientManager persona holding the outbox and inbox of each MPID account close to
+/// it, as described in docs/mpid_messaging.md.
+pub struct MpidManager {
+    database: database::MpidManagerDatabase,
+}
+
+impl MpidManager {
+    pub fn new(config: &::config::Config) -> MpidManager {
+        MpidManager {
+            database: database::MpidManagerDatabase::new(database::Limits::new(config)),
+        }
+    }
+
+    pub fn handle_put(&mut self,
+                      account: &::routing::NameType,
+                      from_authority: ::routing::authority::Authority,
+                      data: ::routing::data::Data,
+                      wrapper: MpidMessageWrapper)
+                      -> Vec<::types::MethodCall> {
+        match (wrapper, from_authority.clone()) {
+            (MpidMessageWrapper::PutMessage(message),
+             ::routing::authority::Authority::Client(_, _)) => {
+                if *message.header().sender() != *account {
+                    return vec![::types::MethodCall::InvalidRequest { data: data }];
+                }
+                let header = message.header().clone();
+                if !self.database.put_message(account, message) {
+                    return vec![::types::MethodCall::FailedPut { location: from_authority,
+                                                                 data: data }];
+                }
+                match MpidMessageWrapper::PutHeader(header.clone()).to_plain_data(header.name()) {
+                    Some(content) => vec![::types::MethodCall::Put {
+                        location: ::routing::authority::Authority::ClientManager(
+                                      *header.recipient()),
+                        content: content,
+                    }],
+                    None => vec![],
+                }
+            }
+            (MpidMessageWrapper::PutHeader(header),
+             ::routing::authority::Authority::ClientManager(sender)) => {
+                if *header.sender() != sender || *header.recipient() != *account {
+                    return vec![::types::MethodCall::InvalidRequest { data: data }];
+                }
+                if self.database.put_header(account, header) {
+                    vec![]
+                } else {
+                    vec![::types::MethodCall::FailedPut { location: from_authority, data: data }]
+                }
+            }
+            _ => vec![::types::MethodCall::InvalidRequest { data: data }],
+        }
+    }
+
+    /// A header stored by the recipient's MpidManagers was rejected, so the sender's MpidManagers
+    /// drop the undeliverable message from the outbox.
+    pub fn handle_put_failure(&mut self,
+                              account: &::routing::NameType,
+                              data: ::routing::data::Data) {
+        if let Some(MpidMessageWrapper::PutHeader(header)) =
+               MpidMessageWrapper::from_plain_data(&data) {
+            let _ = self.database.remove_message(account, &header.name());
+        }
+    }
+
+    /// Requests for the account's own name return the headers in its inbox.  Requests for the name
+    /// of a message are either forwarded to the sender's MpidManagers (when coming from the
+    /// recipient) or answered from the outbox (when coming from the recipient's MpidManagers).
+    /// Clients other than the owner of the account are refused.
+    pub fn handle_get(&mut self,
+                      account: &::routing::NameType,
+                      from_authority: &::routing::authority::Authority,
+                      name: &::routing::NameType)
+                      -> Vec<::types::MethodCall> {
+        let account_value = match self.database.get(account) {
+            Some(account_value) => account_value,
+            None => return vec![],
+        };
+        match *from_authority {
+            ::routing::authority::Authority::Client(_, ref client_key) => {
+                // only the owner of the account may read its inbox
+                if ::utils::client_name(client_key) != *account {
+                    return vec![::types::MethodCall::GetFailed {
+                        name: *name,
+                        reason: ::types::GetFailureReason::Unauthorised,
+                    }];
+                }
+                if *name == *account {
+                    return match ::routing::utils::encode(&account_value.inbox()) {
+                        Ok(encoded) => vec![::types::MethodCall::Reply {
+                            data: ::routing::data::Data::PlainData(
+                                      ::routing::plain_data::PlainData::new(*account, encoded)),
+                        }],
+                        Err(_) => vec![],
+                    };
+                }
+                match account_value.get_header(name) {
+                    Some(header) => vec![::types::MethodCall::Get {
+                        location: ::routing::authority::Authority::ClientManager(
+                                      *header.sender()),
+                        data_request: ::routing::data::DataRequest::PlainData(*name),
+                    }],
+                    None => vec![],
+                }
+            }
+            ::routing::authority::Authority::ClientManager(recipient) => {
+                match account_value.get_message(name) {
+                    Some(ref message) if *message.header().recipient() == recipient => {
+                        match ::routing::utils::encode(message) {
+                            Ok(encoded) => vec![::types::MethodCall::Reply {
+                                data: ::routing::data::Data::PlainData(
+                                          ::routing::plain_data::PlainData::new(*name, encoded)),
+                            }],
+                            Err(_) => vec![],
+                        }
+                    }
+                    _ => vec![],
+                }
+            }
+            _ => vec![],
+        }
+    }
+
+    pub fn handle_post(&mut self,
+                       account: &::routing::NameType,
+                       from_authority: ::routing::authority::Authority,
+                       wrapper: MpidMessageWrapper)
+                       -> Vec<::types::MethodCall> {
+        match (wrapper, from_authority) {
+            // The sender withdraws a message, so the recipient's alert is removed as well
+            (MpidMessageWrapper::DeleteMessage(name),
+             ::routing::authority::Authority::Client(_, _)) => {
+                match self.database.remove_message(account, &name) {
+                    Some(message) => {
+                        notify(*message.header().recipient(),
+                               MpidMessageWrapper::DeleteHeader(name), name)
+                    }
+                    None => vec![],
+                }
+            }
+            // The recipient's MpidManagers report that the message has been retrieved
+            (MpidMessageWrapper::DeleteMessage(name),
+             ::routing::authority::Authority::ClientManager(recipient)) => {
+                let delivered = self.database
+                                    .get(account)
+                                    .and_then(|account_value| account_value.get_message(&name))
+                                    .map(|message| *message.header().recipient() == recipient)
+                                    .unwrap_or(false);
+                if delivered {
+                    let _ = self.database.remove_message(account, &name);
+                }
+                vec![]
+            }
+            // The recipient has retrieved the message, so the sender can drop it
+            (MpidMessageWrapper::DeleteHeader(name),
+             ::routing::authority::Authority::Client(_, _)) => {
+                match self.database.remove_header(account, &name) {
+                    Some(header) => {
+                        notify(*header.sender(), MpidMessageWrapper::DeleteMessage(name), name)
+                    }
+                    None => vec![],
+                }
+            }
+            // The sender lists its undelivered messages, e.g. to choose which ones to withdraw
+            (MpidMessageWrapper::GetOutboxHeaders,
+             ::routing::authority::Authority::Client(client, key)) => {
+                let headers = self.database
+                                  .get(account)
+                                  .map(|account_value| account_value.outbox_headers())
+                                  .unwrap_or(vec![]);
+                match MpidMessageWrapper::OutboxHeaders(headers).to_plain_data(*account) {
+                    Some(data) => vec![::types::MethodCall::Notify {
+                        location: ::routing::authority::Authority::Client(client, key),
+                        data: data,
+                    }],
+                    None => vec![],
+                }
+            }
+            (MpidMessageWrapper::Block(sender), ::routing::authority::Authority::Client(_, _)) => {
+                self.database.block(account, sender);
+                vec![]
+            }
+            (MpidMessageWrapper::Unblock(sender),
+             ::routing::authority::Authority::Client(_, _)) => {
+                self.database.unblock(account, &sender);
+                vec![]
+            }
+            // The sender's MpidManagers report that the message has been withdrawn
+            (MpidMessageWrapper::DeleteHeader(name),
+             ::routing::authority::Authority::ClientManager(sender)) => {
+                let withdrawn = self.database
+                                    .get(account)
+                                    .and_then(|account_value| account_value.get_header(&name))
+                                    .map(|header| *header.sender() == sender)
+                                    .unwrap_or(false);
+                if withdrawn {
+                    let _ = self.database.remove_header(account, &name);
+                }
+                vec![]
+            }
+            _ => vec![],
+        }
+    }
+
+    pub fn handle_account_transfer(&mut self, merged_account: Account) {
+        self.database.handle_account_transfer(merged_account);
+    }
+
+    pub fn retrieve_all_and_reset(&mut self) -> Vec<::types::MethodCall> {
+        self.database.retrieve_all_and_reset()
+    }
+
+    pub fn handle_churn(&mut self, churn: &::churn::Churn) -> Vec<::types::MethodCall> {
+        self.database.handle_churn(churn)
+    }
+}
+
+fn notify(account: ::routing::NameType,
+          wrapper: MpidMessageWrapper,
+          name: ::routing::NameType)
+          -> Vec<::types::MethodCall> {
+    match wrapper.to_plain_data(name) {
+        Some(content) => vec![::types::MethodCall::Post {
+            location: ::routing::authority::Authority::ClientManager(account),
+            content: content,
+        }],
+        None => vec![],
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use sodiumoxide::crypto;
+    use types::PlainPayload;
+
+    use super::*;
+
+    fn client(name: &::routing::NameType) -> ::routing::authority::Authority {
+        ::routing::authority::Authority::Client(name.clone(), crypto::sign::gen_keypair().0)
+    }
+
+    // A client whose account name is derived from its key, as required to read its inbox
+    fn owner() -> (::routing::NameType, ::routing::authority::Authority) {
+        let key = crypto::sign::gen_keypair().0;
+        (::utils::client_name(&key),
+         ::routing::authority::Authority::Client(::utils::random_name(), key))
+    }
+
+    fn put_message(mpid_manager: &mut MpidManager, message: &MpidMessage)
+                   -> Vec<::types::MethodCall> {
+        let wrapper = MpidMessageWrapper::PutMessage(message.clone());
+        let data = wrapper.to_plain_data(message.name()).unwrap();
+        mpid_manager.handle_put(message.header().sender(), client(message.header().sender()),
+                                data, wrapper)
+    }
+
+    #[test]
+    fn send_and_retrieve() {
+        let sender = ::utils::random_name();
+        let (recipient, recipient_client) = owner();
+        let message = MpidMessage::new(MpidHeader::new(sender.clone(), recipient.clone(), vec![]),
+                                       ::routing::types::generate_random_vec_u8(1024));
+        let mut sender_managers = MpidManager::new(&Default::default());
+        let mut recipient_managers = MpidManager::new(&Default::default());
+
+        // The sender's managers store the message and alert the recipient's managers
+        let mut put_result = put_message(&mut sender_managers, &message);
+        assert_eq!(put_result.len(), 1);
+        let (location, content) = match put_result.remove(0) {
+            ::types::MethodCall::Put { location, content } => (location, content),
+            _ => panic!("Unexpected"),
+        };
+        assert_eq!(location, ::routing::authority::Authority::ClientManager(recipient.clone()));
+        let wrapper = MpidMessageWrapper::from_plain_data(&content).unwrap();
+        assert_eq!(wrapper, MpidMessageWrapper::PutHeader(message.header().clone()));
+        assert!(recipient_managers.handle_put(&recipient,
+            ::routing::authority::Authority::ClientManager(sender.clone()), content,
+            wrapper).is_empty());
+
+        // The recipient lists its headers and fetches the message via the sender's managers
+        match recipient_managers.handle_get(&recipient, &recipient_client, &recipient)[0] {
+            ::types::MethodCall::Reply { ref data } => {
+                assert_eq!(data.name(), recipient);
+            }
+            _ => panic!("Unexpected"),
+        }
+        assert_eq!(recipient_managers.handle_get(&recipient, &recipient_client, &message.name()),
+                   vec![::types::MethodCall::Get {
+                            location: ::routing::authority::Authority::ClientManager(
+                                          sender.clone()),
+                            data_request: ::routing::data::DataRequest::PlainData(
+                                              message.name()),
+                        }]);
+        let recipient_managers_authority =
+            ::routing::authority::Authority::ClientManager(recipient.clone());
+        assert_eq!(sender_managers.handle_get(&sender, &recipient_managers_authority,
+                                              &message.name()).len(), 1);
+
+        // Once retrieved, both the header and the message are removed
+        let delete_result = recipient_managers.handle_post(&recipient, recipient_client.clone(),
+            MpidMessageWrapper::DeleteHeader(message.name()));
+        assert_eq!(delete_result.len(), 1);
+        assert!(sender_managers.handle_post(&sender, recipient_managers_authority.clone(),
+            MpidMessageWrapper::DeleteMessage(message.name())).is_empty());
+        assert!(sender_managers.handle_get(&sender, &recipient_managers_authority,
+                                           &message.name()).is_empty());
+        assert!(recipient_managers.handle_get(&recipient, &recipient_client,
+                                              &message.name()).is_empty());
+    }
+
+    #[test]
+    fn withdraw() {
+        let (sender, recipient) = (::utils::random_name(), ::utils::random_name());
+        let message = MpidMessage::new(MpidHeader::new(sender.clone(), recipient.clone(), vec![]),
+                                       ::routing::types::generate_random_vec_u8(1024));
+        let mut sender_managers = MpidManager::new(&Default::default());
+        assert_eq!(put_message(&mut sender_managers, &message).len(), 1);
+        let sender_client = client(&sender);
+        let list_result = sender_managers.handle_post(&sender, sender_client.clone(),
+                                                      MpidMessageWrapper::GetOutboxHeaders);
+        let expected = MpidMessageWrapper::OutboxHeaders(vec![message.header().clone()])
+                           .to_plain_data(sender.clone()).unwrap();
+        assert_eq!(list_result, vec![::types::MethodCall::Notify { location: sender_client,
+                                                                   data: expected }]);
+        let withdraw_result = sender_managers.handle_post(&sender, client(&sender),
+            MpidMessageWrapper::DeleteMessage(message.name()));
+        assert_eq!(withdraw_result.len(), 1);
+        match withdraw_result[0] {
+            ::types::MethodCall::Post { ref location, .. } => {
+                assert_eq!(*location, ::routing::authority::Authority::ClientManager(recipient));
+            }
+            _ => panic!("Unexpected"),
+        }
+    }
+
+    #[test]
+    fn block_sender() {
+        let (sender, recipient) = (::utils::random_name(), ::utils::random_name());
+        let header = MpidHeader::new(sender.clone(), recipient.clone(), vec![]);
+        let wrapper = MpidMessageWrapper::PutHeader(header.clone());
+        let data = wrapper.to_plain_data(header.name()).unwrap();
+        let sender_managers = ::routing::authority::Authority::ClientManager(sender.clone());
+        let mut recipient_managers = MpidManager::new(&Default::default());
+        assert!(recipient_managers.handle_post(&recipient, client(&recipient),
+                                               MpidMessageWrapper::Block(sender)).is_empty());
+        assert_eq!(recipient_managers.handle_put(&recipient, sender_managers.clone(),
+                                                 data.clone(), wrapper),
+                   vec![::types::MethodCall::FailedPut { location: sender_managers,
+                                                         data: data }]);
+    }
+
+    #[test]
+    fn reject_forged_sender() {
+        let message = MpidMessage::new(MpidHeader::new(::utils::random_name(),
+                                                       ::utils::random_name(), vec![]),
+                                       vec![]);
+        let wrapper = MpidMessageWrapper::PutMessage(message.clone());
+        let data = wrapper.to_plain_data(message.name()).unwrap();
+        let mut mpid_manager = MpidManager::new(&Default::default());
+        let account = ::utils::random_name();
+        assert_eq!(mpid_manager.handle_put(&account, client(&account), data.clone(), wrapper),
+                   vec![::types::MethodCall::InvalidRequest { data: data }]);
+    }
+
+    #[test]
+    fn same_metadata() {
+        let (sender, recipient) = (::utils::random_name(), ::utils::random_name());
+        let first = MpidMessage::new(MpidHeader::new(sender.clone(), recipient.clone(), vec![]),
+                                     vec![]);
+        let second = MpidMessage::new(MpidHeader::new(sender.clone(), recipient.clone(), vec![]),
+                                      vec![]);
+        assert!(first.name() != second.name());
+        let mut sender_managers = MpidManager::new(&Default::default());
+        assert_eq!(put_message(&mut sender_managers, &first).len(), 1);
+        assert_eq!(put_message(&mut sender_managers, &second).len(), 1);
+        let sender_client = client(&sender);
+        let expected = MpidMessageWrapper::OutboxHeaders(vec![first.header().clone(),
+                                                              second.header().clone()])
+                           .to_plain_data(sender.clone()).unwrap();
+        assert_eq!(sender_managers.handle_post(&sender, sender_client.clone(),
+                                               MpidMessageWrapper::GetOutboxHeaders),
+                   vec![::types::MethodCall::Notify { location: sender_client, data: expected }]);
+    }
+
+    #[test]
+    fn reject_foreign_inbox_get() {
+        let (account, owner_client) = owner();
+        let mut mpid_manager = MpidManager::new(&Default::default());
+        match mpid_manager.handle_get(&account, &owner_client, &account)[0] {
+            ::types::MethodCall::Reply { .. } => {}
+            _ => panic!("Unexpected"),
+        }
+        assert_eq!(mpid_manager.handle_get(&account, &client(&account), &account),
+                   vec![::types::MethodCall::GetFailed {
+                            name: account,
+                            reason: ::types::GetFailureReason::Unauthorised,
+                        }]);
+    }
+}
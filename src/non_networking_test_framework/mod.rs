@@ -27,6 +27,19 @@ use routing::immutable_data::ImmutableDataType;
 use routing::{ExternalRequest, ExternalResponse, NameType};
 use routing::error::{InterfaceError, ResponseError, RoutingError};
 
+// A client signs what it sends, as the real client would
+fn signed_token(client_keys: &(crypto::sign::PublicKey, crypto::sign::SecretKey),
+                data: &Data)
+                -> Option<::routing::SignedToken> {
+    match ::routing::utils::encode(data) {
+        Ok(serialised_request) => Some(::routing::SignedToken {
+            signature: crypto::sign::sign_detached(&serialised_request, &client_keys.1),
+            serialised_request: serialised_request,
+        }),
+        Err(_) => None,
+    }
+}
+
 #[derive(Clone)]
 pub struct MockRouting {
     sender: ::std::sync::mpsc::Sender<Event>,
@@ -82,10 +95,12 @@ impl MockRouting {
 
     pub fn client_put(&mut self,
                       client_address: ::routing::NameType,
-                      client_pub_key: crypto::sign::PublicKey,
+                      client_keys: &(crypto::sign::PublicKey, crypto::sign::SecretKey),
                       data: Data) {
         let delay_ms = self.network_delay_ms;
         let cloned_sender = self.sender.clone();
+        let client_pub_key = client_keys.0;
+        let response_token = signed_token(client_keys, &data);
         let _ = ::std::thread::spawn(move || {
                                               ::std::thread::sleep_ms(delay_ms);
                                               let _ = cloned_sender.send(Event::Request{
@@ -93,17 +108,19 @@ impl MockRouting {
                 our_authority: ::routing::authority::Authority::ClientManager(client_address),
                 from_authority: ::routing::authority::Authority::Client(client_address,
                                                                         client_pub_key),
-                response_token: None
+                response_token: response_token
             });
                                           });
     }
 
     pub fn client_post(&mut self,
                        client_address: ::routing::NameType,
-                       client_pub_key: crypto::sign::PublicKey,
+                       client_keys: &(crypto::sign::PublicKey, crypto::sign::SecretKey),
                        data: Data) {
         let delay_ms = self.network_delay_ms;
         let cloned_sender = self.sender.clone();
+        let client_pub_key = client_keys.0;
+        let response_token = signed_token(client_keys, &data);
         let _ = ::std::thread::spawn(move || {
                                               ::std::thread::sleep_ms(delay_ms);
                                               let _ = cloned_sender.send(Event::Request{
@@ -111,7 +128,26 @@ impl MockRouting {
                 our_authority: ::routing::authority::Authority::NaeManager(data.name()),
                 from_authority: ::routing::authority::Authority::Client(client_address,
                                                                         client_pub_key),
-                response_token: None });
+                response_token: response_token });
+                                          });
+    }
+
+    pub fn client_account_post(&mut self,
+                               client_address: ::routing::NameType,
+                               client_keys: &(crypto::sign::PublicKey, crypto::sign::SecretKey),
+                               data: Data) {
+        let delay_ms = self.network_delay_ms;
+        let cloned_sender = self.sender.clone();
+        let client_pub_key = client_keys.0;
+        let response_token = signed_token(client_keys, &data);
+        let _ = ::std::thread::spawn(move || {
+                                              ::std::thread::sleep_ms(delay_ms);
+                                              let _ = cloned_sender.send(Event::Request{
+                request: ExternalRequest::Post(data),
+                our_authority: ::routing::authority::Authority::ClientManager(client_address),
+                from_authority: ::routing::authority::Authority::Client(client_address,
+                                                                        client_pub_key),
+                response_token: response_token });
                                           });
     }
 
@@ -122,6 +158,40 @@ impl MockRouting {
                                           });
     }
 
+    pub fn disconnect_event(&mut self) {
+        let _ = self.sender.send(Event::Disconnected);
+    }
+
+    pub fn connect_event(&mut self) {
+        let _ = self.sender.send(Event::Connected);
+    }
+
+    pub fn failed_request_event(&mut self,
+                                request: ExternalRequest,
+                                our_authority: Option<Authority>,
+                                location: Authority,
+                                interface_error: InterfaceError) {
+        let _ = self.sender.send(Event::FailedRequest {
+            request: request,
+            our_authority: our_authority,
+            location: location,
+            interface_error: interface_error,
+        });
+    }
+
+    pub fn failed_response_event(&mut self,
+                                 response: ExternalResponse,
+                                 our_authority: Option<Authority>,
+                                 location: Authority,
+                                 interface_error: InterfaceError) {
+        let _ = self.sender.send(Event::FailedResponse {
+            response: response,
+            our_authority: our_authority,
+            location: location,
+            interface_error: interface_error,
+        });
+    }
+
     // -----------  the above methods are for testing purpose only   ------------- //
 
     // -----------  the following methods are expected to be API functions   ------------- //
@@ -137,7 +207,8 @@ impl MockRouting {
         let cloned_client_sender = self.client_sender.clone();
         let _ = ::std::thread::spawn(move || {
             match location.clone() {
-                ::routing::authority::Authority::NaeManager(_) => {
+                ::routing::authority::Authority::NaeManager(_) |
+                ::routing::authority::Authority::ClientManager(_) => {
                     let _ = cloned_sender.send(Event::Response{
                         response: ExternalResponse::Get(data.clone(), data_request, response_token),
                         our_authority: location, from_authority: our_authority
@@ -182,6 +253,20 @@ impl MockRouting {
                                           });
     }
 
+    pub fn post_request(&self, our_authority: Authority, location: Authority, data: Data) {
+        let delay_ms = self.network_delay_ms;
+        let cloned_sender = self.sender.clone();
+        let _ = ::std::thread::spawn(move || {
+                                              ::std::thread::sleep_ms(delay_ms);
+                                              let _ = cloned_sender.send(Event::Request{
+                request: ExternalRequest::Post(data.clone()),
+                our_authority: location,
+                from_authority: our_authority,
+                response_token: None
+            });
+                                          });
+    }
+
     pub fn put_response(&self,
                         our_authority: Authority,
                         location: Authority,
@@ -199,6 +284,10 @@ impl MockRouting {
                                           });
     }
 
+    pub fn stop(&mut self) {
+        let _ = self.sender.send(Event::Terminated);
+    }
+
     pub fn refresh_request(&self,
                            type_tag: u64,
                            our_authority: ::routing::Authority,
@@ -210,7 +299,7 @@ impl MockRouting {
         let _ = ::std::thread::spawn(move || {
             ::std::thread::sleep_ms(delay_ms);
             let mut refresh_contents = vec![content.clone()];
-            for _ in 2..::data_manager::PARALLELISM {
+            for _ in 1..::types::default_quorum() {
                 refresh_contents.push(content.clone());
             }
             let _ = cloned_sender.send(Event::Refresh(type_tag, our_authority, refresh_contents));
@@ -15,7 +15,6 @@
 // Please review the Licences for the specific language governing permissions and limitations
 // relating to use of the SAFE Network Software.
 
-use cbor;
 use rustc_serialize::{Decoder, Encodable, Encoder};
 use std::collections;
 
@@ -24,6 +23,15 @@ use utils;
 
 pub type PmidNodeName = ::routing::NameType;
 
+/// Nodes ranked below this stop being chosen to hold new data, and their chunks are migrated
+pub static RANK_THRESHOLD: u64 = 50;
+/// Rank given to a node with a clean record which hasn't been up long enough to earn a bonus
+pub static INITIAL_RANK: u64 = 100;
+// at most this many points are earned for uptime, one per hour since the node registered
+static MAX_UPTIME_BONUS: u64 = 20;
+// points lost for each chunk the node failed to return when asked for it
+static FAILED_GET_PENALTY: u64 = 5;
+
 #[derive(RustcEncodable, RustcDecodable, PartialEq, Eq, Debug, Clone)]
 pub struct Account {
     name: PmidNodeName,
@@ -45,32 +53,114 @@ impl Account {
 }
 
 impl ::types::Refreshable for Account {
-    fn merge(from_group: ::routing::NameType, responses: Vec<Account>) -> Option<Account> {
+    fn merge(from_group: ::routing::NameType,
+             responses: Vec<Account>,
+             quorum: usize)
+             -> Result<Account, ::types::MergeError> {
+        let accounts = try!(::types::responses_for(&from_group, responses, quorum, Account::name));
         let mut stored_total_size: Vec<u64> = Vec::new();
         let mut lost_total_size: Vec<u64> = Vec::new();
         let mut offered_space: Vec<u64> = Vec::new();
-        for response in responses {
-            let account =
-                match ::routing::utils::decode::<Account>(&response.serialised_contents()) {
-                    Ok(result) => {
-                        if *result.name() != from_group {
-                            continue;
-                        }
-                        result
-                    }
-                    Err(_) => continue,
-                };
+        let mut failed_gets: Vec<u64> = Vec::new();
+        let mut registered_at: Vec<u64> = Vec::new();
+        let mut farmer_wallets: Vec<(PmidNodeName, usize)> = Vec::new();
+        let mut chunks = collections::HashMap::<(::routing::NameType, u64), usize>::new();
+        for account in accounts {
+            for (chunk_name, size) in account.value().chunks.iter() {
+                *chunks.entry((*chunk_name, *size)).or_insert(0) += 1;
+            }
             stored_total_size.push(account.value().stored_total_size());
             lost_total_size.push(account.value().lost_total_size());
             offered_space.push(account.value().offered_space());
+            failed_gets.push(account.value().failed_gets());
+            registered_at.push(account.value().registered_at());
+            if let Some(farmer_wallet) = account.value().farmer_wallet() {
+                let push_in_vec = match farmer_wallets.iter_mut().find(|a| a.0 == farmer_wallet) {
+                    Some(find_res) => {
+                        find_res.1 += 1;
+                        false
+                    }
+                    None => true,
+                };
+                if push_in_vec {
+                    farmer_wallets.push((farmer_wallet, 1));
+                }
+            }
+        }
+        let mut value = AccountValue::new(utils::median(stored_total_size),
+                                          utils::median(lost_total_size),
+                                          utils::median(offered_space));
+        value.failed_gets = utils::median(failed_gets);
+        value.registered_at = utils::median(registered_at);
+        // the farmer wallet is only kept if a quorum of the group agrees on it
+        value.farmer_wallet = farmer_wallets.into_iter()
+                                            .find(|a| a.1 >= quorum)
+                                            .map(|a| a.0);
+        // as are the chunks held, with their sizes
+        value.chunks = chunks.into_iter()
+                             .filter(|a| a.1 >= quorum)
+                             .map(|a| a.0)
+                             .collect();
+        Ok(Account::new(from_group, value))
+    }
+
+    // Accounts of version 0 predate the ranking and the farmer wallet, so the node is taken as
+    // never having registered nor failed a Get, and without a wallet.  Neither version 0 nor 1
+    // recorded the chunks held, which are taken as unknown.
+    fn decode_version(version: u64, contents: &Vec<u8>) -> Option<Account> {
+        match version {
+            0 => ::routing::utils::decode::<AccountV0>(contents).ok().map(|account| {
+                Account::new(account.name,
+                             AccountValue::new(account.value.stored_total_size,
+                                               account.value.lost_total_size,
+                                               account.value.offered_space))
+            }),
+            1 => ::routing::utils::decode::<AccountV1>(contents).ok().map(|account| {
+                let mut value = AccountValue::new(account.value.stored_total_size,
+                                                  account.value.lost_total_size,
+                                                  account.value.offered_space);
+                value.failed_gets = account.value.failed_gets;
+                value.registered_at = account.value.registered_at;
+                value.farmer_wallet = account.value.farmer_wallet;
+                Account::new(account.name, value)
+            }),
+            ::transfer_parser::FORMAT_VERSION => ::routing::utils::decode(contents).ok(),
+            _ => None,
         }
-        Some(Account::new(from_group,
-                          AccountValue::new(utils::median(stored_total_size),
-                                            utils::median(lost_total_size),
-                                            utils::median(offered_space))))
     }
 }
 
+// An account as transferred by version 0 of the transfer format
+#[derive(RustcDecodable)]
+struct AccountV0 {
+    name: PmidNodeName,
+    value: AccountValueV0,
+}
+
+#[derive(RustcDecodable)]
+struct AccountValueV0 {
+    stored_total_size: u64,
+    lost_total_size: u64,
+    offered_space: u64,
+}
+
+// An account as transferred by version 1 of the transfer format
+#[derive(RustcEncodable, RustcDecodable)]
+struct AccountV1 {
+    name: PmidNodeName,
+    value: AccountValueV1,
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+struct AccountValueV1 {
+    stored_total_size: u64,
+    lost_total_size: u64,
+    offered_space: u64,
+    failed_gets: u64,
+    registered_at: u64,
+    farmer_wallet: Option<::routing::NameType>,
+}
+
 
 
 #[derive(RustcEncodable, RustcDecodable, PartialEq, Eq, Debug, Clone)]
@@ -78,13 +168,29 @@ pub struct AccountValue {
     stored_total_size: u64,
     lost_total_size: u64,
     offered_space: u64,
+    failed_gets: u64,
+    // seconds since the epoch at which the node first registered, zero if it never did
+    registered_at: u64,
+    // the ClientManagers of this wallet are credited with the farming rewards of the node
+    farmer_wallet: Option<::routing::NameType>,
+    // the chunks the node was given to hold, with their sizes; only their DataManagers may report
+    // them as farmed or lost
+    chunks: collections::HashMap<::routing::NameType, u64>,
 }
 
 impl Default for AccountValue {
-    // FIXME: to bypass the AccountCreation process for simple network, capacity is assumed
-    // automatically
+    // FIXME: until every PmidNode registers its offered space, nodes which haven't registered yet
+    // are assumed to offer 1GiB
     fn default() -> AccountValue {
-        AccountValue { stored_total_size: 0, lost_total_size: 0, offered_space: 1073741824 }
+        AccountValue {
+            stored_total_size: 0,
+            lost_total_size: 0,
+            offered_space: 1073741824,
+            failed_gets: 0,
+            registered_at: 0,
+            farmer_wallet: None,
+            chunks: collections::HashMap::new(),
+        }
     }
 }
 
@@ -94,52 +200,85 @@ impl AccountValue {
             stored_total_size: stored_total_size,
             lost_total_size: lost_total_size,
             offered_space: offered_space,
+            failed_gets: 0,
+            registered_at: 0,
+            farmer_wallet: None,
+            chunks: collections::HashMap::new(),
         }
     }
 
-  // TODO: Always return true to allow pmid_node carry out removal of Sacrificial copies
-  //       Otherwise AccountValue need to remember storage info of Primary, Backup and Sacrificial
-  //       copies separately to trigger an early alert
-    pub fn put_data(&mut self, size: u64) -> bool {
-        // if (self.stored_total_size + size) > self.offered_space {
-        //   return false;
-        // }
+    pub fn put_data(&mut self, chunk_name: ::routing::NameType, size: u64) -> bool {
+        if self.chunks.contains_key(&chunk_name) {
+            return true;
+        }
+        if self.stored_total_size + size > self.offered_space {
+            return false;
+        }
         self.stored_total_size += size;
+        let _ = self.chunks.insert(chunk_name, size);
         true
     }
 
-    pub fn delete_data(&mut self, size: u64) {
-        if self.stored_total_size < size {
-            self.stored_total_size = 0;
-        } else {
-            self.stored_total_size -= size;
-        }
+    /// Removes the chunk, which is accounted with its recorded size, or with `size` if it isn't
+    /// recorded, e.g. as it was given before the chunks were.  Returns the size accounted.
+    pub fn delete_data(&mut self, chunk_name: &::routing::NameType, size: u64) -> u64 {
+        let size = self.chunks.remove(chunk_name).unwrap_or(size);
+        self.stored_total_size = self.stored_total_size.saturating_sub(size);
+        size
     }
 
-    #[allow(dead_code)]
-    pub fn handle_lost_data(&mut self, size: u64) {
-        self.delete_data(size);
+    /// Accounts a chunk the node was given as lost, returning its size.  Chunks it isn't known to
+    /// hold are ignored.
+    pub fn handle_lost_data(&mut self, chunk_name: &::routing::NameType) -> Option<u64> {
+        let size = match self.chunks.get(chunk_name) {
+            Some(size) => *size,
+            None => return None,
+        };
+        let _ = self.delete_data(chunk_name, size);
         self.lost_total_size += size;
+        Some(size)
+    }
+
+    pub fn handle_falure(&mut self, chunk_name: &::routing::NameType) {
+        let _ = self.handle_lost_data(chunk_name);
+        self.failed_gets += 1;
     }
 
-    #[allow(dead_code)]
-    pub fn handle_falure(&mut self, size: u64) {
-        self.handle_lost_data(size);
+    pub fn chunk_size(&self, chunk_name: &::routing::NameType) -> Option<u64> {
+        self.chunks.get(chunk_name).cloned()
+    }
+
+    pub fn chunk_names(&self) -> Vec<::routing::NameType> {
+        self.chunks.keys().cloned().collect()
     }
 
-    #[allow(dead_code)]
     pub fn set_available_size(&mut self, available_size: u64) {
         self.offered_space = available_size;
     }
 
-    #[allow(dead_code)]
-    pub fn update_account(&mut self, diff_size: u64) {
-        if self.stored_total_size < diff_size {
-            self.stored_total_size = 0;
-        } else {
-            self.stored_total_size -= diff_size;
+    pub fn register(&mut self, offered_space: u64, now: u64) {
+        self.set_available_size(offered_space);
+        if self.registered_at == 0 {
+            self.registered_at = now;
         }
-        self.lost_total_size += diff_size;
+    }
+
+    /// Reputation of the node: the percentage of the bytes it was given which it still holds,
+    /// plus a point per hour of uptime (up to `MAX_UPTIME_BONUS`), less a penalty for each failed
+    /// get.
+    pub fn rank(&self, now: u64) -> u64 {
+        let handled = self.stored_total_size + self.lost_total_size;
+        let reliability = if handled == 0 {
+            INITIAL_RANK
+        } else {
+            INITIAL_RANK * self.stored_total_size / handled
+        };
+        let uptime_bonus = if self.registered_at == 0 || now < self.registered_at {
+            0
+        } else {
+            ::std::cmp::min((now - self.registered_at) / 3600, MAX_UPTIME_BONUS)
+        };
+        (reliability + uptime_bonus).saturating_sub(self.failed_gets * FAILED_GET_PENALTY)
     }
 
     pub fn stored_total_size(&self) -> u64 {
@@ -153,6 +292,22 @@ impl AccountValue {
     pub fn offered_space(&self) -> u64 {
         self.offered_space
     }
+
+    pub fn failed_gets(&self) -> u64 {
+        self.failed_gets
+    }
+
+    pub fn registered_at(&self) -> u64 {
+        self.registered_at
+    }
+
+    pub fn farmer_wallet(&self) -> Option<::routing::NameType> {
+        self.farmer_wallet
+    }
+
+    pub fn set_farmer_wallet(&mut self, farmer_wallet: ::routing::NameType) {
+        self.farmer_wallet = Some(farmer_wallet);
+    }
 }
 
 pub struct PmidManagerDatabase {
@@ -164,16 +319,70 @@ impl PmidManagerDatabase {
         PmidManagerDatabase { storage: collections::HashMap::with_capacity(10000) }
     }
 
-    pub fn put_data(&mut self, name: &PmidNodeName, size: u64) -> bool {
+    pub fn put_data(&mut self,
+                    name: &PmidNodeName,
+                    chunk_name: ::routing::NameType,
+                    size: u64)
+                    -> bool {
         let default: AccountValue = Default::default();
         let entry = self.storage.entry(name.clone()).or_insert(default);
-        entry.put_data(size)
+        entry.put_data(chunk_name, size)
     }
 
-    pub fn delete_data(&mut self, name: &PmidNodeName, size: u64) {
+    pub fn register(&mut self, name: &PmidNodeName, offered_space: u64) {
+        let default: AccountValue = Default::default();
+        self.storage
+            .entry(name.clone())
+            .or_insert(default)
+            .register(offered_space, ::time::get_time().sec as u64);
+    }
+
+    pub fn handle_lost_data(&mut self, name: &PmidNodeName, chunk_name: &::routing::NameType)
+                            -> Option<u64> {
+        self.storage.get_mut(name).and_then(|value| value.handle_lost_data(chunk_name))
+    }
+
+    pub fn handle_failed_get(&mut self, name: &PmidNodeName, chunk_name: &::routing::NameType) {
+        let default: AccountValue = Default::default();
+        self.storage.entry(name.clone()).or_insert(default).handle_falure(chunk_name);
+    }
+
+    pub fn chunk_size(&self, name: &PmidNodeName, chunk_name: &::routing::NameType)
+                      -> Option<u64> {
+        self.storage.get(name).and_then(|value| value.chunk_size(chunk_name))
+    }
+
+    pub fn chunk_names(&self, name: &PmidNodeName) -> Vec<::routing::NameType> {
+        self.storage.get(name).map(|value| value.chunk_names()).unwrap_or(vec![])
+    }
+
+    pub fn rank(&self, name: &PmidNodeName) -> u64 {
+        match self.storage.get(name) {
+            Some(value) => value.rank(::time::get_time().sec as u64),
+            None => INITIAL_RANK,
+        }
+    }
+
+    pub fn ranks(&self) -> collections::HashMap<PmidNodeName, u64> {
+        let now = ::time::get_time().sec as u64;
+        self.storage.iter().map(|(name, value)| (name.clone(), value.rank(now))).collect()
+    }
+
+    pub fn delete_data(&mut self, name: &PmidNodeName, chunk_name: &::routing::NameType,
+                       size: u64)
+                       -> u64 {
         let default: AccountValue = Default::default();
         let entry = self.storage.entry(name.clone()).or_insert(default);
-        entry.delete_data(size)
+        entry.delete_data(chunk_name, size)
+    }
+
+    pub fn farmer_wallet(&self, name: &PmidNodeName) -> Option<::routing::NameType> {
+        self.storage.get(name).and_then(|value| value.farmer_wallet())
+    }
+
+    pub fn set_farmer_wallet(&mut self, name: &PmidNodeName, farmer_wallet: ::routing::NameType) {
+        let default: AccountValue = Default::default();
+        self.storage.entry(name.clone()).or_insert(default).set_farmer_wallet(farmer_wallet);
     }
 
     pub fn handle_account_transfer(&mut self, merged_account: Account) {
@@ -189,20 +398,50 @@ impl PmidManagerDatabase {
         let mut actions = Vec::with_capacity(self.storage.len());
         for (key, value) in self.storage.iter() {
             if close_group.iter().find(|a| **a == *key).is_some() {
-                let account = Account::new((*key).clone(), (*value).clone());
-                let mut encoder = cbor::Encoder::from_memory();
-                if encoder.encode(&[account.clone()]).is_ok() {
-                    actions.push(::types::MethodCall::Refresh {
-                        type_tag: PMID_MANAGER_ACCOUNT_TAG,
-                        our_authority: ::routing::Authority::NodeManager(*account.name()),
-                        payload: encoder.as_bytes().to_vec()
-                    });
+                if let Some(action) = refresh(key, value) {
+                    actions.push(action);
                 }
             }
         }
         self.storage.clear();
         actions
     }
+
+    /// Refreshes the accounts whose group changed, dropping those we're no longer responsible for.
+    /// As before, only the accounts of nodes in the close group are refreshed.
+    pub fn handle_churn(&mut self, churn: &::churn::Churn) -> Vec<::types::MethodCall> {
+        let mut actions = Vec::new();
+        let mut lost = Vec::new();
+        for (key, value) in self.storage.iter() {
+            if !churn.affects(key) {
+                continue;
+            }
+            if churn.new_close_group().iter().find(|a| **a == *key).is_some() {
+                if let Some(action) = refresh(key, value) {
+                    actions.push(action);
+                }
+            }
+            if !churn.keeps(key) {
+                lost.push(key.clone());
+            }
+        }
+        for name in lost {
+            let _ = self.storage.remove(&name);
+        }
+        actions
+    }
+}
+
+fn refresh(name: &PmidNodeName, value: &AccountValue) -> Option<::types::MethodCall> {
+    let account = Account::new(name.clone(), value.clone());
+    match ::transfer_parser::encode(PMID_MANAGER_ACCOUNT_TAG, &account) {
+        Some(payload) => Some(::types::MethodCall::Refresh {
+            type_tag: PMID_MANAGER_ACCOUNT_TAG,
+            our_authority: ::routing::Authority::NodeManager(name.clone()),
+            payload: payload
+        }),
+        None => None,
+    }
 }
 
 
@@ -217,30 +456,40 @@ mod test {
         let mut db = PmidManagerDatabase::new();
         let name = ::utils::random_name();
         assert!(!db.storage.contains_key(&name));
-        db.put_data(&name, 1024);
+        assert!(db.put_data(&name, ::utils::random_name(), 1024));
         assert!(db.storage.contains_key(&name));
     }
 
-    // #[test]
-    // fn put_data() {
-    //     let mut db = PmidManagerDatabase::new();
-    //     let name = ::utils::random_name();
-    //     assert_eq!(db.put_data(&name, 0), true);
-    //     assert_eq!(db.exist(&name), true);
-    //     assert_eq!(db.put_data(&name, 1), true);
-    //     assert_eq!(db.put_data(&name, 1073741823), true);
-    //     assert_eq!(db.put_data(&name, 1), false);
-    //     assert_eq!(db.put_data(&name, 1), false);
-    //     assert_eq!(db.put_data(&name, 0), true);
-    //     assert_eq!(db.put_data(&name, 1), false);
-    //     assert_eq!(db.exist(&name), true);
-    // }
+    #[test]
+    fn put_data() {
+        let mut db = PmidManagerDatabase::new();
+        let name = ::utils::random_name();
+        assert_eq!(db.put_data(&name, ::utils::random_name(), 0), true);
+        assert!(db.storage.contains_key(&name));
+        let chunk_name = ::utils::random_name();
+        assert_eq!(db.put_data(&name, chunk_name, 1), true);
+        assert_eq!(db.put_data(&name, ::utils::random_name(), 1073741823), true);
+        assert_eq!(db.put_data(&name, ::utils::random_name(), 1), false);
+        assert_eq!(db.put_data(&name, ::utils::random_name(), 0), true);
+        // a chunk already held isn't accounted twice
+        assert_eq!(db.put_data(&name, chunk_name, 1), true);
+        assert_eq!(db.chunk_size(&name, &chunk_name), Some(1));
+
+        db.register(&name, 2147483648);
+        assert_eq!(db.put_data(&name, ::utils::random_name(), 1073741824), true);
+        assert_eq!(db.put_data(&name, ::utils::random_name(), 1), false);
+
+        // deleting a chunk frees its recorded size
+        assert_eq!(db.delete_data(&name, &chunk_name, 1024), 1);
+        assert_eq!(db.chunk_size(&name, &chunk_name), None);
+        assert_eq!(db.put_data(&name, ::utils::random_name(), 1), true);
+    }
 
     #[test]
     fn handle_account_transfer() {
         let mut db = PmidManagerDatabase::new();
         let name = ::utils::random_name();
-        assert!(db.put_data(&name, 1024));
+        assert!(db.put_data(&name, ::utils::random_name(), 1024));
         assert!(db.storage.contains_key(&name));
 
         let account_value = AccountValue::new(::rand::random::<u64>(),
@@ -251,6 +500,106 @@ mod test {
         assert_eq!(db.storage[&name], account_value);
     }
 
+    #[test]
+    fn rank() {
+        let mut value = AccountValue::new(0, 0, 1024);
+        assert_eq!(value.rank(0), INITIAL_RANK);
+        value.register(1024, 1000);
+        value.register(2048, 5000);
+        assert_eq!(value.registered_at(), 1000);
+        assert_eq!(value.rank(1000 + 3 * 3600), INITIAL_RANK + 3);
+        assert_eq!(value.rank(1000 + 1000 * 3600), INITIAL_RANK + MAX_UPTIME_BONUS);
+
+        let (lost, failed) = (::utils::random_name(), ::utils::random_name());
+        assert!(value.put_data(lost, 300));
+        assert!(value.put_data(failed, 300));
+        assert_eq!(value.handle_lost_data(&lost), Some(300));
+        // a chunk can only be lost once
+        assert!(value.handle_lost_data(&lost).is_none());
+        assert_eq!(value.rank(1000), INITIAL_RANK / 2);
+        value.handle_falure(&failed);
+        assert_eq!(value.failed_gets(), 1);
+        assert_eq!(value.rank(1000), 0);
+
+        let mut db = PmidManagerDatabase::new();
+        let name = ::utils::random_name();
+        assert_eq!(db.rank(&name), INITIAL_RANK);
+        let chunk_name = ::utils::random_name();
+        assert!(db.put_data(&name, chunk_name, 1024));
+        db.handle_failed_get(&name, &chunk_name);
+        assert!(db.rank(&name) < RANK_THRESHOLD);
+        assert_eq!(db.ranks().get(&name), Some(&db.rank(&name)));
+    }
+
+    #[test]
+    fn merge_farmer_wallet() {
+        use types::Refreshable;
+        let name = ::utils::random_name();
+        let wallet = ::utils::random_name();
+        let mut responses = Vec::new();
+        for i in 0..::routing::types::GROUP_SIZE {
+            let mut account_value = AccountValue::new(0, 0, 1024);
+            if i == 0 {
+                account_value.set_farmer_wallet(::utils::random_name());
+            } else {
+                account_value.set_farmer_wallet(wallet.clone());
+            }
+            responses.push(Account::new(name.clone(), account_value));
+        }
+        let quorum = ::types::default_quorum();
+        let merged = Account::merge(name.clone(), responses, quorum).unwrap();
+        assert_eq!(merged.value().farmer_wallet(), Some(wallet));
+
+        let merged = Account::merge(name.clone(), vec![Account::new(name.clone(),
+            AccountValue::new(0, 0, 1024))], 1).unwrap();
+        assert!(merged.value().farmer_wallet().is_none());
+    }
+
+    #[test]
+    fn version_1() {
+        use types::Refreshable;
+        let (name, wallet) = (::utils::random_name(), ::utils::random_name());
+        let old = super::AccountV1 {
+            name: name,
+            value: super::AccountValueV1 {
+                stored_total_size: 1,
+                lost_total_size: 2,
+                offered_space: 3,
+                failed_gets: 4,
+                registered_at: 5,
+                farmer_wallet: Some(wallet),
+            },
+        };
+        let account = Account::decode_version(1, &::routing::utils::encode(&old).unwrap())
+                          .unwrap();
+        assert_eq!(*account.name(), name);
+        assert_eq!(account.value().stored_total_size(), 1);
+        assert_eq!(account.value().lost_total_size(), 2);
+        assert_eq!(account.value().offered_space(), 3);
+        assert_eq!(account.value().failed_gets(), 4);
+        assert_eq!(account.value().registered_at(), 5);
+        assert_eq!(account.value().farmer_wallet(), Some(wallet));
+        assert!(account.value().chunks.is_empty());
+    }
+
+    #[test]
+    fn merge_chunks() {
+        use types::Refreshable;
+        let name = ::utils::random_name();
+        let (agreed, disputed) = (::utils::random_name(), ::utils::random_name());
+        let mut responses = Vec::new();
+        for i in 0..::routing::types::GROUP_SIZE {
+            let mut account_value = AccountValue::new(0, 0, 1024);
+            assert!(account_value.put_data(agreed, 10));
+            // the size of a chunk has to be agreed on too
+            assert!(account_value.put_data(disputed, i as u64));
+            responses.push(Account::new(name.clone(), account_value));
+        }
+        let merged = Account::merge(name.clone(), responses, ::types::default_quorum()).unwrap();
+        assert_eq!(merged.value().chunk_size(&agreed), Some(10));
+        assert!(merged.value().chunk_size(&disputed).is_none());
+    }
+
     #[test]
     fn pmid_manager_account_serialisation() {
         let obj_before = Account::new(::routing::NameType([1u8; 64]),
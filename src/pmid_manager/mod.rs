@@ -16,29 +16,52 @@
 // relating to use of the SAFE Network Software.
 
 mod database;
+mod request;
 
-pub use self::database::Account;
+pub use self::database::{Account, AccountValue, INITIAL_RANK, RANK_THRESHOLD};
+pub use self::request::PmidManagerRequest;
+
+use types::PlainPayload;
+
+/// Safecoin credited to the farmer wallet of a PmidNode for each farming attempt it wins
+pub static FARMING_REWARD: u64 = 1;
+static LRU_CACHE_SIZE: usize = 1000;
+// seconds before the chunks of a node still ranked too low are migrated again
+static MIGRATION_TIMEOUT: i64 = 600;
 
 pub struct PmidManager {
     database: database::PmidManagerDatabase,
+    // nodes ranked below the threshold whose chunks are being migrated, with the starting time
+    migrations: ::lru_time_cache::LruCache<::routing::NameType, ::time::SteadyTime>,
 }
 
 impl PmidManager {
     pub fn new() -> PmidManager {
-        PmidManager { database: database::PmidManagerDatabase::new() }
+        PmidManager {
+            database: database::PmidManagerDatabase::new(),
+            migrations: ::lru_time_cache::LruCache::with_expiry_duration_and_capacity(
+                            ::time::Duration::seconds(MIGRATION_TIMEOUT), LRU_CACHE_SIZE),
+        }
     }
 
     pub fn handle_put(&mut self,
                       pmid_node: ::routing::NameType,
                       data: ::routing::data::Data)
                       -> Vec<::types::MethodCall> {
-        if self.database.put_data(&pmid_node, data.payload_size() as u64) {
+        // A node ranked below the threshold is given no new data
+        if self.database.rank(&pmid_node) >= RANK_THRESHOLD &&
+           self.database.put_data(&pmid_node, data.name(), data.payload_size() as u64) {
             vec![::types::MethodCall::Put {
                      location: ::routing::authority::Authority::ManagedNode(pmid_node.clone()),
                      content: data
                  }]
         } else {
-            vec![]
+            // The node would exceed its offered space or is ranked too low, the DataManagers pick
+            // another one
+            vec![::types::MethodCall::FailedPut {
+                location: ::routing::authority::Authority::NaeManager(data.name()),
+                data: data,
+            }]
         }
     }
 
@@ -48,14 +71,15 @@ impl PmidManager {
                                -> Vec<::types::MethodCall> {
         match response {
             ::routing::error::ResponseError::FailedRequestForData(data) => {
-                self.database.delete_data(from_address, data.payload_size() as u64);
+                let _ = self.database.delete_data(from_address, &data.name(),
+                                                  data.payload_size() as u64);
                 return vec![::types::MethodCall::FailedPut {
                                 location: ::routing::authority::Authority::NaeManager(data.name()),
                                 data: data
                             }];
             }
             ::routing::error::ResponseError::HadToClearSacrificial(name, size) => {
-                self.database.delete_data(from_address, size as u64);
+                let size = self.database.delete_data(from_address, &name, size as u64);
                 return vec![::types::MethodCall::ClearSacrificial {
                     location: ::routing::authority::Authority::NaeManager(name),
                     name: name,
@@ -73,11 +97,142 @@ impl PmidManager {
                                            -> Vec<::types::MethodCall> {
         match response {
             ::routing::error::ResponseError::FailedRequestForData(data) => {
-                self.database.delete_data(from_address, data.payload_size() as u64);
+                self.database.handle_failed_get(from_address, &data.name());
+                info!("PmidManager failed to get {:?} from {:?}, its rank is now {:?}",
+                      data.name(), from_address, self.database.rank(from_address));
+                self.migrate_chunks_from(from_address)
             }
-            _ => {}
+            _ => vec![],
         }
-        vec![]
+    }
+
+    pub fn rank(&self, pmid_node: &::routing::NameType) -> u64 {
+        self.database.rank(pmid_node)
+    }
+
+    /// Ranks of all the nodes managed by this vault, unknown nodes have `INITIAL_RANK`
+    pub fn ranks(&self) -> ::std::collections::HashMap<::routing::NameType, u64> {
+        self.database.ranks()
+    }
+
+    pub fn handle_post(&mut self,
+                       pmid_node: ::routing::NameType,
+                       from_authority: ::routing::authority::Authority,
+                       data: ::routing::data::Data,
+                       request: PmidManagerRequest)
+                       -> Vec<::types::MethodCall> {
+        match (request, from_authority) {
+            // The reward is paid into the farmer's wallet, if the node has chosen one.  Only the
+            // DataManagers of a chunk the node holds may claim it.
+            (PmidManagerRequest::FarmingReward,
+             ::routing::authority::Authority::NaeManager(chunk_name)) => {
+                if chunk_name != data.name() ||
+                   self.database.chunk_size(&pmid_node, &chunk_name).is_none() {
+                    return vec![::types::MethodCall::InvalidRequest { data: data }];
+                }
+                let farmer_wallet = match self.database.farmer_wallet(&pmid_node) {
+                    Some(farmer_wallet) => farmer_wallet,
+                    None => {
+                        info!("PmidManager dropping farming reward of {:?}, no wallet chosen",
+                              pmid_node);
+                        return vec![];
+                    }
+                };
+                let credit = ::maid_manager::WalletRequest::Credit {
+                    sender: pmid_node,
+                    amount: FARMING_REWARD,
+                };
+                match credit.to_plain_data(data.name()) {
+                    Some(content) => vec![::types::MethodCall::Post {
+                        location: ::routing::authority::Authority::ClientManager(farmer_wallet),
+                        content: content,
+                    }],
+                    None => vec![],
+                }
+            }
+            // The DataManagers of a chunk found the node no longer holds it
+            (PmidManagerRequest::LostData,
+             ::routing::authority::Authority::NaeManager(chunk_name)) => {
+                if chunk_name != data.name() {
+                    return vec![::types::MethodCall::InvalidRequest { data: data }];
+                }
+                match self.database.handle_lost_data(&pmid_node, &chunk_name) {
+                    Some(size) => {
+                        info!("PmidManager accounted {:?} bytes lost by {:?}, its rank is now {:?}",
+                              size, pmid_node, self.database.rank(&pmid_node));
+                        self.migrate_chunks_from(&pmid_node)
+                    }
+                    None => vec![::types::MethodCall::InvalidRequest { data: data }],
+                }
+            }
+            (PmidManagerRequest::Register { offered_space },
+             ::routing::authority::Authority::ManagedNode(node)) => {
+                if node == pmid_node {
+                    info!("PmidManager registered {:?} offering {:?} bytes", pmid_node,
+                          offered_space);
+                    self.database.register(&pmid_node, offered_space);
+                    vec![]
+                } else {
+                    vec![::types::MethodCall::InvalidRequest { data: data }]
+                }
+            }
+            // The DataManagers of each evicted sacrificial chunk are told about it one by one, as
+            // they are a different group for each chunk
+            (PmidManagerRequest::ChunksRemoved { sacrificial, corrupt },
+             ::routing::authority::Authority::ManagedNode(node)) => {
+                if node != pmid_node {
+                    return vec![::types::MethodCall::InvalidRequest { data: data }];
+                }
+                for (name, _) in corrupt {
+                    info!("PmidManager accounting corrupt chunk {:?} of {:?} as lost", name,
+                          pmid_node);
+                    let _ = self.database.handle_lost_data(&pmid_node, &name);
+                }
+                sacrificial.into_iter()
+                           .map(|(name, size)| {
+                               let size = self.database.delete_data(&pmid_node, &name, size);
+                               ::types::MethodCall::ClearSacrificial {
+                                   location: ::routing::authority::Authority::NaeManager(name),
+                                   name: name,
+                                   size: size,
+                               }
+                           })
+                           .collect()
+            }
+            (PmidManagerRequest::SetFarmerWallet(farmer_wallet),
+             ::routing::authority::Authority::ManagedNode(node)) => {
+                if node == pmid_node {
+                    self.database.set_farmer_wallet(&pmid_node, farmer_wallet);
+                    vec![]
+                } else {
+                    vec![::types::MethodCall::InvalidRequest { data: data }]
+                }
+            }
+            _ => vec![::types::MethodCall::InvalidRequest { data: data }],
+        }
+    }
+
+    // Once a node falls below the rank threshold, the DataManagers of each chunk recorded in its
+    // account are told to migrate it.  This is done once per node until MIGRATION_TIMEOUT passes,
+    // however many more failures are reported meanwhile.
+    fn migrate_chunks_from(&mut self, pmid_node: &::routing::NameType)
+                           -> Vec<::types::MethodCall> {
+        if self.database.rank(pmid_node) >= RANK_THRESHOLD ||
+           self.migrations.contains_key(pmid_node) {
+            return vec![];
+        }
+        self.migrations.add(pmid_node.clone(), ::time::SteadyTime::now());
+        let chunk_names = self.database.chunk_names(pmid_node);
+        info!("PmidManager migrating {:?} chunks away from {:?}", chunk_names.len(), pmid_node);
+        chunk_names.into_iter()
+                   .filter_map(|chunk_name| {
+                       ::data_manager::HeldChunk::Migrate.to_plain_data(chunk_name.clone())
+                           .map(|content| ::types::MethodCall::Post {
+                               location: ::routing::authority::Authority::NaeManager(chunk_name),
+                               content: content,
+                           })
+                   })
+                   .collect()
     }
 
     pub fn handle_account_transfer(&mut self, merged_account: Account) {
@@ -89,11 +244,16 @@ impl PmidManager {
                                   -> Vec<::types::MethodCall> {
         self.database.retrieve_all_and_reset(close_group)
     }
+
+    pub fn handle_churn(&mut self, churn: &::churn::Churn) -> Vec<::types::MethodCall> {
+        self.database.handle_churn(churn)
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use types::PlainPayload;
 
     #[test]
     fn handle_put() {
@@ -113,4 +273,211 @@ mod test {
             _ => panic!("Unexpected"),
         }
     }
+
+    #[test]
+    fn offered_space() {
+        let mut pmid_manager = PmidManager::new();
+        let pmid_node = ::utils::random_name();
+        let register = PmidManagerRequest::Register { offered_space: 1024 };
+        let register_data = register.to_plain_data(pmid_node.clone()).unwrap();
+        assert!(pmid_manager.handle_post(pmid_node.clone(),
+                                         ::routing::authority::Authority::ManagedNode(pmid_node),
+                                         register_data, register).is_empty());
+
+        let data = ::routing::data::Data::ImmutableData(
+                       ::routing::immutable_data::ImmutableData::new(
+                           ::routing::immutable_data::ImmutableDataType::Normal,
+                           ::routing::types::generate_random_vec_u8(1000)));
+        match pmid_manager.handle_put(pmid_node.clone(), data.clone())[0] {
+            ::types::MethodCall::Put { .. } => (),
+            _ => panic!("Unexpected"),
+        }
+        let data = ::routing::data::Data::ImmutableData(
+                       ::routing::immutable_data::ImmutableData::new(
+                           ::routing::immutable_data::ImmutableDataType::Normal,
+                           ::routing::types::generate_random_vec_u8(100)));
+        assert_eq!(pmid_manager.handle_put(pmid_node, data.clone()),
+                   vec![::types::MethodCall::FailedPut {
+                       location: ::routing::authority::Authority::NaeManager(data.name()),
+                       data: data,
+                   }]);
+    }
+
+    #[test]
+    fn low_rank() {
+        let mut pmid_manager = PmidManager::new();
+        let pmid_node = ::utils::random_name();
+        let data = ::routing::data::Data::ImmutableData(
+                       ::routing::immutable_data::ImmutableData::new(
+                           ::routing::immutable_data::ImmutableDataType::Normal,
+                           ::routing::types::generate_random_vec_u8(1024)));
+        match pmid_manager.handle_put(pmid_node.clone(), data.clone())[0] {
+            ::types::MethodCall::Put { .. } => (),
+            _ => panic!("Unexpected"),
+        }
+        assert!(pmid_manager.handle_get_failure_notification(&pmid_node,
+            ::routing::error::ResponseError::FailedRequestForData(data.clone())).is_empty());
+        assert!(pmid_manager.rank(&pmid_node) < RANK_THRESHOLD);
+        assert_eq!(pmid_manager.ranks().get(&pmid_node), Some(&pmid_manager.rank(&pmid_node)));
+        assert_eq!(pmid_manager.handle_put(pmid_node, data.clone()),
+                   vec![::types::MethodCall::FailedPut {
+                       location: ::routing::authority::Authority::NaeManager(data.name()),
+                       data: data,
+                   }]);
+    }
+
+    #[test]
+    fn lost_data() {
+        let mut pmid_manager = PmidManager::new();
+        let pmid_node = ::utils::random_name();
+        let data = ::routing::data::Data::ImmutableData(
+                       ::routing::immutable_data::ImmutableData::new(
+                           ::routing::immutable_data::ImmutableDataType::Normal,
+                           ::routing::types::generate_random_vec_u8(1024)));
+        let _ = pmid_manager.handle_put(pmid_node.clone(), data.clone());
+        let lost_data = PmidManagerRequest::LostData;
+        let lost_data_plain = lost_data.to_plain_data(data.name()).unwrap();
+        // only the DataManagers of the chunk may report it lost
+        let invalid = vec![::types::MethodCall::InvalidRequest { data: lost_data_plain.clone() }];
+        assert_eq!(pmid_manager.handle_post(pmid_node.clone(),
+                                            ::routing::authority::Authority::ManagedNode(pmid_node),
+                                            lost_data_plain.clone(), lost_data.clone()),
+                   invalid);
+        assert_eq!(pmid_manager.handle_post(pmid_node.clone(),
+                                            ::routing::authority::Authority::NaeManager(
+                                                ::utils::random_name()),
+                                            lost_data_plain.clone(), lost_data.clone()),
+                   invalid);
+        // nor of a chunk the node doesn't hold
+        let other_chunk = ::utils::random_name();
+        let other_plain = lost_data.to_plain_data(other_chunk).unwrap();
+        assert_eq!(pmid_manager.handle_post(pmid_node.clone(),
+                                            ::routing::authority::Authority::NaeManager(
+                                                other_chunk),
+                                            other_plain.clone(), lost_data.clone()),
+                   vec![::types::MethodCall::InvalidRequest { data: other_plain }]);
+        assert_eq!(pmid_manager.rank(&pmid_node), INITIAL_RANK);
+        assert!(pmid_manager.handle_post(pmid_node.clone(),
+                                         ::routing::authority::Authority::NaeManager(data.name()),
+                                         lost_data_plain.clone(), lost_data.clone()).is_empty());
+        assert!(pmid_manager.rank(&pmid_node) < RANK_THRESHOLD);
+        // a chunk is only lost once
+        assert_eq!(pmid_manager.handle_post(pmid_node.clone(),
+                                            ::routing::authority::Authority::NaeManager(
+                                                data.name()),
+                                            lost_data_plain.clone(), lost_data),
+                   invalid);
+    }
+
+    #[test]
+    fn migration() {
+        let mut pmid_manager = PmidManager::new();
+        let pmid_node = ::utils::random_name();
+        let lost = ::routing::data::Data::ImmutableData(
+                       ::routing::immutable_data::ImmutableData::new(
+                           ::routing::immutable_data::ImmutableDataType::Normal,
+                           ::routing::types::generate_random_vec_u8(2048)));
+        let held = ::routing::data::Data::ImmutableData(
+                       ::routing::immutable_data::ImmutableData::new(
+                           ::routing::immutable_data::ImmutableDataType::Normal,
+                           ::routing::types::generate_random_vec_u8(1024)));
+        let _ = pmid_manager.handle_put(pmid_node.clone(), lost.clone());
+        let _ = pmid_manager.handle_put(pmid_node.clone(), held.clone());
+
+        // losing most of the data it held puts the node below the threshold, so the DataManagers
+        // of each chunk still held are told to migrate it...
+        let lost_data = PmidManagerRequest::LostData;
+        assert_eq!(pmid_manager.handle_post(pmid_node.clone(),
+                                            ::routing::authority::Authority::NaeManager(
+                                                lost.name()),
+                                            lost_data.to_plain_data(lost.name()).unwrap(),
+                                            lost_data.clone()),
+                   vec![::types::MethodCall::Post {
+                       location: ::routing::authority::Authority::NaeManager(held.name()),
+                       content: ::data_manager::HeldChunk::Migrate.to_plain_data(held.name())
+                                                                 .unwrap(),
+                   }]);
+        assert!(pmid_manager.rank(&pmid_node) < RANK_THRESHOLD);
+        // ...only once, however many failures follow
+        assert!(pmid_manager.handle_get_failure_notification(&pmid_node,
+            ::routing::error::ResponseError::FailedRequestForData(lost)).is_empty());
+    }
+
+    #[test]
+    fn chunks_removed() {
+        let mut pmid_manager = PmidManager::new();
+        let pmid_node = ::utils::random_name();
+        let new_chunk = || ::routing::data::Data::ImmutableData(
+                               ::routing::immutable_data::ImmutableData::new(
+                                   ::routing::immutable_data::ImmutableDataType::Normal,
+                                   ::routing::types::generate_random_vec_u8(1024)));
+        let (evicted, corrupt) = (new_chunk(), new_chunk());
+        let _ = pmid_manager.handle_put(pmid_node.clone(), evicted.clone());
+        let _ = pmid_manager.handle_put(pmid_node.clone(), corrupt.clone());
+        // the recorded sizes are used rather than the reported ones
+        let request = PmidManagerRequest::ChunksRemoved {
+            sacrificial: vec![(evicted.name(), 1)],
+            corrupt: vec![(corrupt.name(), 1)],
+        };
+        let request_data = request.to_plain_data(pmid_node.clone()).unwrap();
+        assert_eq!(pmid_manager.handle_post(pmid_node.clone(),
+                                            ::routing::authority::Authority::ManagedNode(
+                                                ::utils::random_name()),
+                                            request_data.clone(), request.clone()),
+                   vec![::types::MethodCall::InvalidRequest { data: request_data.clone() }]);
+        assert_eq!(pmid_manager.handle_post(pmid_node.clone(),
+                                            ::routing::authority::Authority::ManagedNode(pmid_node),
+                                            request_data, request),
+                   vec![::types::MethodCall::ClearSacrificial {
+                       location: ::routing::authority::Authority::NaeManager(evicted.name()),
+                       name: evicted.name(),
+                       size: 1024,
+                   }]);
+        assert!(pmid_manager.rank(&pmid_node) < INITIAL_RANK);
+    }
+
+    #[test]
+    fn farming_reward() {
+        let mut pmid_manager = PmidManager::new();
+        let pmid_node = ::utils::random_name();
+        let farmer_wallet = ::utils::random_name();
+        let data = ::routing::data::Data::ImmutableData(
+                       ::routing::immutable_data::ImmutableData::new(
+                           ::routing::immutable_data::ImmutableDataType::Normal,
+                           ::routing::types::generate_random_vec_u8(1024)));
+        let chunk_name = data.name();
+        let data_managers = ::routing::authority::Authority::NaeManager(chunk_name.clone());
+        let reward = PmidManagerRequest::FarmingReward;
+        let reward_data = reward.to_plain_data(chunk_name.clone()).unwrap();
+        // only the DataManagers of a chunk the node holds may claim a reward
+        assert_eq!(pmid_manager.handle_post(pmid_node.clone(), data_managers.clone(),
+                                            reward_data.clone(), reward.clone()),
+                   vec![::types::MethodCall::InvalidRequest { data: reward_data.clone() }]);
+        let _ = pmid_manager.handle_put(pmid_node.clone(), data);
+        assert_eq!(pmid_manager.handle_post(pmid_node.clone(),
+                                            ::routing::authority::Authority::NaeManager(
+                                                ::utils::random_name()),
+                                            reward_data.clone(), reward.clone()),
+                   vec![::types::MethodCall::InvalidRequest { data: reward_data.clone() }]);
+        // no wallet chosen yet
+        assert!(pmid_manager.handle_post(pmid_node.clone(), data_managers.clone(),
+                                         reward_data.clone(), reward.clone()).is_empty());
+
+        let set_wallet = PmidManagerRequest::SetFarmerWallet(farmer_wallet.clone());
+        let set_wallet_data = set_wallet.to_plain_data(pmid_node.clone()).unwrap();
+        assert!(pmid_manager.handle_post(pmid_node.clone(),
+                                         ::routing::authority::Authority::ManagedNode(pmid_node),
+                                         set_wallet_data, set_wallet).is_empty());
+
+        let credit = ::maid_manager::WalletRequest::Credit {
+            sender: pmid_node,
+            amount: FARMING_REWARD,
+        };
+        assert_eq!(pmid_manager.handle_post(pmid_node.clone(), data_managers, reward_data,
+                                            reward),
+                   vec![::types::MethodCall::Post {
+                       location: ::routing::authority::Authority::ClientManager(farmer_wallet),
+                       content: credit.to_plain_data(chunk_name).unwrap(),
+                   }]);
+    }
 }
@@ -0,0 +1,45 @@
+// Copyright 2015 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+/// Requests to a NodeManager group travel as the tagged payload of a PlainData posted to it.
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Eq, Debug, Clone)]
+pub enum PmidManagerRequest {
+    /// Posted by the DataManagers of a chunk when the PmidNode won a farming attempt for serving
+    /// it; the NodeManagers decide the amount
+    FarmingReward,
+    /// Posted by the PmidNode on joining the network, and whenever its configured max disk space
+    /// changes, to advertise the space it offers
+    Register {
+        offered_space: u64,
+    },
+    /// Posted by the DataManagers of a chunk when the PmidNode failed to prove it still holds it
+    LostData,
+    /// Posted by the PmidNode after removing chunks to make room for a normal one: the sacrificial
+    /// chunks it evicted and the chunks it found corrupt, with their sizes
+    ChunksRemoved {
+        sacrificial: Vec<(::routing::NameType, u64)>,
+        corrupt: Vec<(::routing::NameType, u64)>,
+    },
+    /// Posted by the PmidNode to choose the wallet its farming rewards are paid into
+    SetFarmerWallet(::routing::NameType),
+}
+
+impl ::types::PlainPayload for PmidManagerRequest {
+    fn type_tag() -> u64 {
+        ::types::payload_tags::PMID_MANAGER_REQUEST_TAG
+    }
+}
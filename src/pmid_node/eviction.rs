@@ -0,0 +1,95 @@
+// Copyright 2015 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+/// Order in which a PmidNode evicts sacrificial chunks to make room for a normal one.
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Eq, Debug, Clone, Copy)]
+pub enum EvictionPolicy {
+    /// The fewest chunks are evicted, and so the fewest notifications sent
+    LargestFirst,
+    /// Chunks never read since being stored go first
+    LeastRecentlyRead,
+    OldestFirst,
+}
+
+impl Default for EvictionPolicy {
+    fn default() -> EvictionPolicy {
+        EvictionPolicy::LargestFirst
+    }
+}
+
+/// A sacrificial chunk which may be evicted.  `stored` and `read` are ticks of the PmidNode's
+/// clock, zero meaning unknown (e.g. the chunk was never read).
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Candidate {
+    pub name: ::routing::NameType,
+    // bytes the chunk takes in the chunk store
+    pub stored_size: usize,
+    // size accounted by the PmidManagers
+    pub payload_size: u64,
+    pub stored: u64,
+    pub read: u64,
+}
+
+impl EvictionPolicy {
+    /// Sorts the candidates so that the ones to evict first come first
+    pub fn sort(&self, candidates: &mut Vec<Candidate>) {
+        match *self {
+            EvictionPolicy::LargestFirst =>
+                candidates.sort_by(|a, b| b.stored_size.cmp(&a.stored_size)),
+            EvictionPolicy::LeastRecentlyRead => candidates.sort_by(|a, b| a.read.cmp(&b.read)),
+            EvictionPolicy::OldestFirst => candidates.sort_by(|a, b| a.stored.cmp(&b.stored)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn candidate(stored_size: usize, stored: u64, read: u64) -> Candidate {
+        Candidate {
+            name: ::utils::random_name(),
+            stored_size: stored_size,
+            payload_size: stored_size as u64,
+            stored: stored,
+            read: read,
+        }
+    }
+
+    #[test]
+    fn sort() {
+        let small_new_unread = candidate(10, 3, 0);
+        let large_old_read = candidate(30, 1, 5);
+        let medium_read = candidate(20, 2, 4);
+        let candidates = vec![small_new_unread.clone(), large_old_read.clone(),
+                              medium_read.clone()];
+
+        let mut sorted = candidates.clone();
+        EvictionPolicy::LargestFirst.sort(&mut sorted);
+        assert_eq!(sorted, vec![large_old_read.clone(), medium_read.clone(),
+                                small_new_unread.clone()]);
+
+        let mut sorted = candidates.clone();
+        EvictionPolicy::LeastRecentlyRead.sort(&mut sorted);
+        assert_eq!(sorted, vec![small_new_unread.clone(), medium_read.clone(),
+                                large_old_read.clone()]);
+
+        let mut sorted = candidates.clone();
+        EvictionPolicy::OldestFirst.sort(&mut sorted);
+        assert_eq!(sorted, vec![large_old_read, medium_read, small_new_unread]);
+    }
+}
@@ -15,22 +15,90 @@
 // Please review the Licences for the specific language governing permissions and limitations
 // relating to use of the SAFE Network Software.
 
+mod eviction;
+
 use chunk_store::ChunkStore;
+use std::collections::HashMap;
+use types::PlainPayload;
+
+pub use self::eviction::EvictionPolicy;
+
+// chunks announced to their DataManagers on each tick after restarting
+static ANNOUNCEMENTS_PER_TICK: usize = 100;
 
 pub struct PmidNode {
     chunk_store_: ChunkStore,
+    // learnt once the vault has joined the network
+    our_name: Option<::routing::NameType>,
+    eviction_policy: EvictionPolicy,
+    // ticked on every put and get, so that chunks can be ordered by when they were stored or read
+    clock: u64,
+    // key is chunk_name, value is the tick at which it was stored
+    stored_at: HashMap<::routing::NameType, u64>,
+    // key is chunk_name, value is the tick at which it was last read
+    read_at: HashMap<::routing::NameType, u64>,
+    // chunks found in the chunk store on joining, which are yet to be announced
+    pending_announcements: Vec<::routing::NameType>,
+    // the wallet last chosen for our farming rewards
+    farmer_wallet: Option<::routing::NameType>,
 }
 
 impl PmidNode {
-    pub fn new() -> PmidNode {
-        PmidNode { chunk_store_: ChunkStore::new(1073741824) } // TODO adjustable max_disk_space
+    pub fn new(config: &::config::Config) -> PmidNode {
+        PmidNode {
+            chunk_store_: ChunkStore::new(config.max_disk_space as usize),
+            our_name: None,
+            eviction_policy: config.eviction_policy,
+            clock: 0,
+            stored_at: HashMap::new(),
+            read_at: HashMap::new(),
+            pending_announcements: Vec::new(),
+            farmer_wallet: None,
+        }
     }
 
-    pub fn handle_get(&self, name: ::routing::NameType) -> Vec<::types::MethodCall> {
+    /// Advertises the offered space, and the wallet farming rewards are paid into if one is
+    /// configured, to our NodeManagers when we first learn our name, or later when either has
+    /// changed.
+    pub fn register(&mut self,
+                    our_name: ::routing::NameType,
+                    max_disk_space: u64,
+                    farmer_wallet: Option<::routing::NameType>)
+                    -> Vec<::types::MethodCall> {
+        let renamed = self.our_name != Some(our_name);
+        let mut requests = Vec::new();
+        if renamed || self.chunk_store_.max_disk_usage() as u64 != max_disk_space {
+            if self.our_name.is_none() {
+                self.pending_announcements = self.chunk_store_.names();
+            }
+            self.our_name = Some(our_name);
+            self.chunk_store_.set_max_disk_usage(max_disk_space as usize);
+            requests.push(::pmid_manager::PmidManagerRequest::Register {
+                offered_space: max_disk_space,
+            });
+        }
+        if let Some(wallet) = farmer_wallet {
+            if renamed || self.farmer_wallet != farmer_wallet {
+                requests.push(::pmid_manager::PmidManagerRequest::SetFarmerWallet(wallet));
+            }
+        }
+        self.farmer_wallet = farmer_wallet;
+        requests.into_iter()
+                .filter_map(|request| request.to_plain_data(our_name))
+                .map(|content| ::types::MethodCall::Post {
+                    location: ::routing::authority::Authority::NodeManager(our_name),
+                    content: content,
+                })
+                .collect()
+    }
+
+    pub fn handle_get(&mut self, name: ::routing::NameType) -> Vec<::types::MethodCall> {
         let data = self.chunk_store_.get(name);
         if data.len() == 0 {
             return vec![];
         }
+        self.clock += 1;
+        let _ = self.read_at.insert(name, self.clock);
         let sd: ::routing::immutable_data::ImmutableData = match ::routing::utils::decode(&data) {
             Ok(data) => data,
             Err(_) => return vec![],
@@ -38,6 +106,71 @@ impl PmidNode {
         vec![::types::MethodCall::Reply { data: ::routing::data::Data::ImmutableData(sd) }]
     }
 
+    pub fn our_name(&self) -> Option<::routing::NameType> {
+        self.our_name
+    }
+
+    pub fn flush(&self) -> ::std::io::Result<()> {
+        self.chunk_store_.flush()
+    }
+
+    /// Announces a batch of the chunks held since before joining the network to their
+    /// DataManagers, which may have dropped us as a holder in the meantime.  Only chunks found in
+    /// the chunk store are announced, and it doesn't outlive the vault yet, so there are none
+    /// after a restart until it does.
+    pub fn announcements(&mut self) -> Vec<::types::MethodCall> {
+        let batch_size = ::std::cmp::min(self.pending_announcements.len(), ANNOUNCEMENTS_PER_TICK);
+        let remaining = self.pending_announcements.split_off(batch_size);
+        let batch = ::std::mem::replace(&mut self.pending_announcements, remaining);
+        batch.into_iter()
+             .filter_map(|chunk_name| {
+                 ::data_manager::HeldChunk::Announce.to_plain_data(chunk_name).map(|content| {
+                     ::types::MethodCall::Post {
+                         location: ::routing::authority::Authority::NaeManager(chunk_name),
+                         content: content,
+                     }
+                 })
+             })
+             .collect()
+    }
+
+    /// Answers a challenge from the DataManagers of a chunk with the hash of the nonce followed by
+    /// the content we hold.  Nothing is sent back for a chunk we don't hold.
+    pub fn handle_challenge(&self,
+                            chunk_name: ::routing::NameType,
+                            nonce: Vec<u8>)
+                            -> Vec<::types::MethodCall> {
+        let data = self.chunk_store_.get(chunk_name.clone());
+        if data.len() == 0 {
+            return vec![];
+        }
+        let chunk: ::routing::immutable_data::ImmutableData =
+            match ::routing::utils::decode(&data) {
+                Ok(chunk) => chunk,
+                Err(_) => return vec![],
+            };
+        let proof = ::data_manager::StorageChallenge::Proof {
+            proof: ::data_manager::compute_proof(&nonce, &chunk.value()[..]),
+        };
+        match proof.to_plain_data(chunk_name.clone()) {
+            Some(content) => vec![::types::MethodCall::Post {
+                location: ::routing::authority::Authority::NaeManager(chunk_name),
+                content: content,
+            }],
+            None => vec![],
+        }
+    }
+
+    /// Removes a chunk its DataManagers no longer track, so it stops taking up space.  Nothing is
+    /// sent back, as no account records it.
+    pub fn handle_reclaim(&mut self, chunk_name: ::routing::NameType) -> Vec<::types::MethodCall> {
+        if self.chunk_store_.has_chunk(chunk_name.clone()) {
+            info!("pmid_node reclaiming untracked chunk {:?}", chunk_name);
+            self.forget(chunk_name);
+        }
+        vec![]
+    }
+
     pub fn handle_put(&mut self,
                       pmid_node: ::routing::NameType,
                       incoming_data: ::routing::data::Data)
@@ -61,7 +194,7 @@ impl PmidNode {
         };
         if self.chunk_store_.has_disk_space(data.len()) {
             // the type_tag needs to be stored as well
-            self.chunk_store_.put(data_name_and_remove_sacrificial.0, data);
+            self.store(data_name_and_remove_sacrificial.0, data);
             return vec![];
         }
         if !data_name_and_remove_sacrificial.1 {
@@ -70,48 +203,105 @@ impl PmidNode {
             return vec![::types::MethodCall::ClearSacrificial {
                 location: ::routing::authority::Authority::NodeManager(pmid_node),
                 name: incoming_data.name(),
-                size: incoming_data.payload_size() as u32
+                size: incoming_data.payload_size() as u64
             }];
         }
-        let required_space = data.len() -
-                             (self.chunk_store_.max_disk_usage() -
-                              self.chunk_store_.current_disk_usage());
-        let names = self.chunk_store_.names();
+        let mut required_space = (self.chunk_store_.current_disk_usage() + data.len())
+                                     .saturating_sub(self.chunk_store_.max_disk_usage());
+
+        // Corrupt chunks are of no use to anyone, their space is reclaimed first
+        let (mut candidates, corrupt) = self.eviction_candidates();
+        for &(ref name, size) in corrupt.iter() {
+            info!("pmid_node {:?} removing corrupt chunk {:?}", pmid_node, name);
+            self.forget(name.clone());
+            required_space = required_space.saturating_sub(size as usize);
+        }
+
+        // Sacrificial chunks are only evicted if enough space can be freed for the normal one
+        self.eviction_policy.sort(&mut candidates);
+        let mut evicted = Vec::new();
+        for candidate in candidates {
+            if required_space == 0 {
+                break;
+            }
+            required_space = required_space.saturating_sub(candidate.stored_size);
+            evicted.push(candidate);
+        }
+        let mut removed = Vec::new();
+        if required_space == 0 {
+            for candidate in evicted {
+                self.forget(candidate.name.clone());
+                removed.push((candidate.name, candidate.payload_size));
+            }
+            self.store(data_name_and_remove_sacrificial.0, data);
+        }
+
+        // The NodeManagers update the account and tell the DataManagers of each sacrificial
+        // chunk, DataManagers need to adjust their farming rate, replication shall not be carried
+        // out for it
         let mut returned_calls = vec![];
-        let mut emptied_space = 0;
-        for name in names.iter() {
+        if !removed.is_empty() || !corrupt.is_empty() {
+            let request = ::pmid_manager::PmidManagerRequest::ChunksRemoved {
+                sacrificial: removed,
+                corrupt: corrupt,
+            };
+            if let Some(content) = request.to_plain_data(pmid_node.clone()) {
+                returned_calls.push(::types::MethodCall::Post {
+                    location: ::routing::authority::Authority::NodeManager(pmid_node.clone()),
+                    content: content,
+                });
+            }
+        }
+        if required_space > 0 {
+            // Reduplication needs to be carried out
+            returned_calls.push(::types::MethodCall::FailedPut {
+                location: ::routing::authority::Authority::NodeManager(pmid_node),
+                data: incoming_data
+            });
+        }
+        returned_calls
+    }
+
+    fn store(&mut self, name: ::routing::NameType, data: Vec<u8>) {
+        self.chunk_store_.put(name.clone(), data);
+        self.clock += 1;
+        let _ = self.stored_at.insert(name, self.clock);
+    }
+
+    fn forget(&mut self, name: ::routing::NameType) {
+        self.chunk_store_.delete(name.clone());
+        let _ = self.stored_at.remove(&name);
+        let _ = self.read_at.remove(&name);
+    }
+
+    // The sacrificial chunks held, and the chunks which fail to decode along with their size
+    fn eviction_candidates(&self) -> (Vec<eviction::Candidate>, Vec<(::routing::NameType, u64)>) {
+        let mut candidates = Vec::new();
+        let mut corrupt = Vec::new();
+        for name in self.chunk_store_.names() {
             let fetched_data = self.chunk_store_.get(name.clone());
             let parsed_data: ::routing::immutable_data::ImmutableData =
                 match ::routing::utils::decode(&fetched_data) {
                     Ok(data) => data,
-                    Err(_) => return vec![],
+                    Err(_) => {
+                        corrupt.push((name, fetched_data.len() as u64));
+                        continue;
+                    }
                 };
             match *parsed_data.get_type_tag() {
                 ::routing::immutable_data::ImmutableDataType::Sacrificial => {
-                    emptied_space += fetched_data.len();
-                    self.chunk_store_.delete(name.clone());
-                    // For sacrifized data, just notify PmidManager to update the account and
-                    // DataManager need to adjust its farming rate, replication shall not be carried
-                    // out for it
-                    returned_calls.push(::types::MethodCall::ClearSacrificial {
-                        location: ::routing::authority::Authority::NodeManager(pmid_node.clone()),
-                        name: parsed_data.name(),
-                        size: parsed_data.payload_size() as u32
+                    candidates.push(eviction::Candidate {
+                        name: name.clone(),
+                        stored_size: fetched_data.len(),
+                        payload_size: parsed_data.payload_size() as u64,
+                        stored: self.stored_at.get(&name).cloned().unwrap_or(0),
+                        read: self.read_at.get(&name).cloned().unwrap_or(0),
                     });
-                    if emptied_space > required_space {
-                        self.chunk_store_.put(data_name_and_remove_sacrificial.0, data);
-                        return returned_calls;
-                    }
                 }
                 _ => {}
             }
         }
-        // Reduplication needs to be carried out
-        returned_calls.push(::types::MethodCall::FailedPut {
-            location: ::routing::authority::Authority::NodeManager(pmid_node),
-            data: incoming_data
-        });
-        returned_calls
+        (candidates, corrupt)
     }
 
 }
@@ -119,10 +309,222 @@ impl PmidNode {
 #[cfg(test)]
 mod test {
     use super::*;
+    use types::PlainPayload;
+
+    #[test]
+    fn register() {
+        let mut pmid_node = PmidNode::new(&Default::default());
+        let our_name = ::utils::random_name();
+        let request = ::pmid_manager::PmidManagerRequest::Register { offered_space: 1024 };
+        let expected = vec![::types::MethodCall::Post {
+            location: ::routing::authority::Authority::NodeManager(our_name),
+            content: request.to_plain_data(our_name).unwrap(),
+        }];
+        assert_eq!(pmid_node.register(our_name, 1024, None), expected);
+        assert!(pmid_node.register(our_name, 1024, None).is_empty());
+        assert_eq!(pmid_node.register(our_name, 2048, None).len(), 1);
+        assert!(!pmid_node.chunk_store_.has_disk_space(2049));
+
+        // the configured farmer wallet is chosen once, and again whenever it changes
+        let wallet = ::utils::random_name();
+        let set_wallet = ::pmid_manager::PmidManagerRequest::SetFarmerWallet(wallet);
+        assert_eq!(pmid_node.register(our_name, 2048, Some(wallet)),
+                   vec![::types::MethodCall::Post {
+                       location: ::routing::authority::Authority::NodeManager(our_name),
+                       content: set_wallet.to_plain_data(our_name).unwrap(),
+                   }]);
+        assert!(pmid_node.register(our_name, 2048, Some(wallet)).is_empty());
+        assert_eq!(pmid_node.register(our_name, 2048, Some(::utils::random_name())).len(), 1);
+        // and with the registration under a new name
+        assert_eq!(pmid_node.register(::utils::random_name(), 2048, Some(wallet)).len(), 2);
+    }
+
+    #[test]
+    fn announce_stored_chunks() {
+        let mut pmid_node = PmidNode::new(&Default::default());
+        let names: Vec<::routing::NameType> =
+            (0..ANNOUNCEMENTS_PER_TICK + 1).map(|_| ::utils::random_name()).collect();
+        for name in names.iter() {
+            pmid_node.chunk_store_.put(name.clone(), vec![0u8; 8]);
+        }
+        assert!(pmid_node.announcements().is_empty());
+
+        let _ = pmid_node.register(::utils::random_name(), 1024, None);
+        let mut announced = pmid_node.announcements();
+        assert_eq!(announced.len(), ANNOUNCEMENTS_PER_TICK);
+        announced.extend(pmid_node.announcements().into_iter());
+        assert!(pmid_node.announcements().is_empty());
+        for name in names.iter() {
+            assert!(announced.contains(&::types::MethodCall::Post {
+                location: ::routing::authority::Authority::NaeManager(name.clone()),
+                content: ::data_manager::HeldChunk::Announce.to_plain_data(name.clone()).unwrap(),
+            }));
+        }
+
+        // chunks are only announced once
+        let _ = pmid_node.register(::utils::random_name(), 2048, None);
+        assert!(pmid_node.announcements().is_empty());
+    }
+
+    #[test]
+    fn handle_challenge() {
+        let mut pmid_node = PmidNode::new(&Default::default());
+        let im_data = ::routing::immutable_data::ImmutableData::new(
+                          ::routing::immutable_data::ImmutableDataType::Normal,
+                          ::routing::types::generate_random_vec_u8(1024));
+        let nonce = ::routing::types::generate_random_vec_u8(32);
+        assert!(pmid_node.handle_challenge(im_data.name(), nonce.clone()).is_empty());
+
+        let _ = pmid_node.handle_put(::utils::random_name(),
+                                     ::routing::data::Data::ImmutableData(im_data.clone()));
+        let proof = ::data_manager::StorageChallenge::Proof {
+            proof: ::data_manager::compute_proof(&nonce, &im_data.value()[..]),
+        };
+        assert_eq!(pmid_node.handle_challenge(im_data.name(), nonce),
+                   vec![::types::MethodCall::Post {
+                       location: ::routing::authority::Authority::NaeManager(im_data.name()),
+                       content: proof.to_plain_data(im_data.name()).unwrap(),
+                   }]);
+    }
+
+    #[test]
+    fn handle_reclaim() {
+        let mut pmid_node = PmidNode::new(&Default::default());
+        let im_data = ::routing::immutable_data::ImmutableData::new(
+                          ::routing::immutable_data::ImmutableDataType::Normal,
+                          ::routing::types::generate_random_vec_u8(1024));
+        let _ = pmid_node.handle_put(::utils::random_name(),
+                                     ::routing::data::Data::ImmutableData(im_data.clone()));
+        assert!(pmid_node.chunk_store_.has_chunk(im_data.name()));
+
+        // the chunk is removed without telling anyone, and reclaiming it again does nothing
+        assert!(pmid_node.handle_reclaim(im_data.name()).is_empty());
+        assert!(!pmid_node.chunk_store_.has_chunk(im_data.name()));
+        assert_eq!(pmid_node.chunk_store_.current_disk_usage(), 0);
+        assert!(pmid_node.handle_reclaim(im_data.name()).is_empty());
+        assert!(pmid_node.handle_get(im_data.name()).is_empty());
+    }
+
+    fn chunk(type_tag: ::routing::immutable_data::ImmutableDataType)
+             -> ::routing::immutable_data::ImmutableData {
+        ::routing::immutable_data::ImmutableData::new(type_tag,
+            ::routing::types::generate_random_vec_u8(1024))
+    }
+
+    // Holds room for exactly three chunks of the given size
+    fn full_pmid_node(policy: EvictionPolicy,
+                      chunk_size: usize,
+                      sacrificial: &Vec<::routing::immutable_data::ImmutableData>)
+                      -> PmidNode {
+        let config = ::config::Config {
+            max_disk_space: 3 * chunk_size as u64,
+            eviction_policy: policy,
+            ..Default::default()
+        };
+        let mut pmid_node = PmidNode::new(&config);
+        for data in sacrificial.iter() {
+            assert!(pmid_node.handle_put(::utils::random_name(),
+                        ::routing::data::Data::ImmutableData(data.clone())).is_empty());
+        }
+        pmid_node
+    }
+
+    fn chunks_removed(pmid_node: ::routing::NameType,
+                      sacrificial: Vec<(::routing::NameType, u64)>,
+                      corrupt: Vec<(::routing::NameType, u64)>)
+                      -> ::types::MethodCall {
+        let request = ::pmid_manager::PmidManagerRequest::ChunksRemoved {
+            sacrificial: sacrificial,
+            corrupt: corrupt,
+        };
+        ::types::MethodCall::Post {
+            location: ::routing::authority::Authority::NodeManager(pmid_node),
+            content: request.to_plain_data(pmid_node).unwrap(),
+        }
+    }
+
+    #[test]
+    fn evict_sacrificial() {
+        let sacrificial: Vec<::routing::immutable_data::ImmutableData> =
+            (0..3).map(|_| chunk(::routing::immutable_data::ImmutableDataType::Sacrificial))
+                  .collect();
+        let chunk_size = ::routing::utils::encode(&sacrificial[0]).unwrap().len();
+        let normal = chunk(::routing::immutable_data::ImmutableDataType::Normal);
+        let pmid_name = ::utils::random_name();
+
+        // exactly one chunk, the oldest, is evicted
+        let mut pmid_node = full_pmid_node(EvictionPolicy::OldestFirst, chunk_size, &sacrificial);
+        assert_eq!(pmid_node.handle_put(pmid_name.clone(),
+                                        ::routing::data::Data::ImmutableData(normal.clone())),
+                   vec![chunks_removed(pmid_name.clone(), vec![(sacrificial[0].name(), 1024)],
+                                       vec![])]);
+        assert!(pmid_node.chunk_store_.has_chunk(normal.name()));
+        assert!(pmid_node.chunk_store_.has_chunk(sacrificial[1].name()));
+
+        // chunks read recently are kept
+        let mut pmid_node = full_pmid_node(EvictionPolicy::LeastRecentlyRead, chunk_size,
+                                           &sacrificial);
+        let _ = pmid_node.handle_get(sacrificial[0].name());
+        let _ = pmid_node.handle_get(sacrificial[2].name());
+        assert_eq!(pmid_node.handle_put(pmid_name.clone(),
+                                        ::routing::data::Data::ImmutableData(normal.clone())),
+                   vec![chunks_removed(pmid_name.clone(), vec![(sacrificial[1].name(), 1024)],
+                                       vec![])]);
+
+        // nothing is evicted if the normal chunk can't fit anyway
+        let mut pmid_node = full_pmid_node(EvictionPolicy::LargestFirst, chunk_size,
+                                           &sacrificial[..2].to_vec());
+        let _ = pmid_node.handle_put(pmid_name.clone(),
+                                     ::routing::data::Data::ImmutableData(normal.clone()));
+        let large = ::routing::data::Data::ImmutableData(
+                        ::routing::immutable_data::ImmutableData::new(
+                            ::routing::immutable_data::ImmutableDataType::Normal,
+                            ::routing::types::generate_random_vec_u8(3000)));
+        assert_eq!(pmid_node.handle_put(pmid_name.clone(), large.clone()),
+                   vec![::types::MethodCall::FailedPut {
+                       location: ::routing::authority::Authority::NodeManager(pmid_name),
+                       data: large,
+                   }]);
+        assert!(pmid_node.chunk_store_.has_chunk(sacrificial[0].name()));
+        assert!(pmid_node.chunk_store_.has_chunk(sacrificial[1].name()));
+    }
+
+    #[test]
+    fn remove_corrupt() {
+        let sacrificial = vec![chunk(::routing::immutable_data::ImmutableDataType::Sacrificial)];
+        let chunk_size = ::routing::utils::encode(&sacrificial[0]).unwrap().len();
+        let mut pmid_node = full_pmid_node(EvictionPolicy::LargestFirst, chunk_size, &sacrificial);
+        let corrupt_name = ::utils::random_name();
+        pmid_node.chunk_store_.put(corrupt_name.clone(), vec![0u8; chunk_size]);
+        pmid_node.chunk_store_.put(::utils::random_name(), vec![1u8; chunk_size]);
+
+        // removing the corrupt chunks frees enough space, no sacrificial chunk is evicted
+        let pmid_name = ::utils::random_name();
+        let normal = chunk(::routing::immutable_data::ImmutableDataType::Normal);
+        let result = pmid_node.handle_put(pmid_name.clone(),
+                                          ::routing::data::Data::ImmutableData(normal.clone()));
+        assert_eq!(result.len(), 1);
+        match result[0] {
+            ::types::MethodCall::Post { ref content, .. } => {
+                match ::pmid_manager::PmidManagerRequest::from_plain_data(content) {
+                    Some(::pmid_manager::PmidManagerRequest::ChunksRemoved { sacrificial,
+                                                                             corrupt }) => {
+                        assert!(sacrificial.is_empty());
+                        assert_eq!(corrupt.len(), 2);
+                        assert!(corrupt.contains(&(corrupt_name, chunk_size as u64)));
+                    }
+                    _ => panic!("Unexpected"),
+                }
+            }
+            _ => panic!("Unexpected"),
+        }
+        assert!(pmid_node.chunk_store_.has_chunk(normal.name()));
+        assert!(pmid_node.chunk_store_.has_chunk(sacrificial[0].name()));
+    }
 
     #[test]
     fn handle_put_get() {
-        let mut pmid_node = PmidNode::new();
+        let mut pmid_node = PmidNode::new(&Default::default());
         let value = ::routing::types::generate_random_vec_u8(1024);
         let im_data = ::routing::immutable_data::ImmutableData::new(
                           ::routing::immutable_data::ImmutableDataType::Normal, value);
@@ -0,0 +1,152 @@
+// Copyright 2015 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+/// A StructuredData as transferred on churn, along with its version so that the group can agree
+/// on the latest one.
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Eq, Debug, Clone)]
+pub struct Account {
+    name: ::routing::NameType,
+    // the serialised StructuredData
+    data: Vec<u8>,
+    version: u64,
+}
+
+impl Account {
+    pub fn new(structured_data: &::routing::structured_data::StructuredData) -> Option<Account> {
+        match ::routing::utils::encode(structured_data) {
+            Ok(data) => Some(Account {
+                name: structured_data.name(),
+                data: data,
+                version: structured_data.get_version(),
+            }),
+            Err(_) => None,
+        }
+    }
+
+    pub fn name(&self) -> &::routing::NameType {
+        &self.name
+    }
+
+    pub fn data(&self) -> &Vec<u8> {
+        &self.data
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    // The name and version claimed must be those of the data carried
+    fn is_valid(&self) -> bool {
+        match ::routing::utils::decode::<::routing::structured_data::StructuredData>(&self.data) {
+            Ok(structured_data) => structured_data.name() == self.name &&
+                                   structured_data.get_version() == self.version,
+            Err(_) => false,
+        }
+    }
+}
+
+impl ::types::Refreshable for Account {
+    // The highest version of the data reported identically by a quorum of the group is kept.
+    fn merge(from_group: ::routing::NameType,
+             responses: Vec<Account>,
+             quorum: usize)
+             -> Result<Account, ::types::MergeError> {
+        let responses = try!(::types::responses_for(&from_group, responses, quorum,
+                                                    Account::name));
+        let mut votes = Vec::<(Account, usize)>::new();
+        for response in responses {
+            if !response.is_valid() {
+                continue;
+            }
+            let push_in_vec = match votes.iter_mut().find(|a| a.0 == response) {
+                Some(find_res) => {
+                    find_res.1 += 1;
+                    false
+                }
+                None => true,
+            };
+            if push_in_vec {
+                votes.push((response, 1));
+            }
+        }
+        let mut agreed = votes.into_iter()
+                              .filter(|a| a.1 >= quorum)
+                              .map(|(account, _)| account)
+                              .collect::<Vec<_>>();
+        agreed.sort_by(|lhs, rhs| rhs.version.cmp(&lhs.version));
+        agreed.into_iter().next().ok_or(::types::MergeError::InsufficientQuorum)
+    }
+
+    // Version 0 transferred the StructuredData itself
+    fn decode_version(version: u64, contents: &Vec<u8>) -> Option<Account> {
+        match version {
+            0 => ::routing::utils::decode::<::routing::structured_data::StructuredData>(contents)
+                     .ok()
+                     .and_then(|structured_data| Account::new(&structured_data)),
+            1 | ::transfer_parser::FORMAT_VERSION => ::routing::utils::decode(contents).ok(),
+            _ => None,
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use sodiumoxide::crypto;
+    use types::Refreshable;
+
+    fn structured_data(name: ::routing::NameType,
+                       version: u64,
+                       keys: &(crypto::sign::PublicKey, crypto::sign::SecretKey))
+                       -> ::routing::structured_data::StructuredData {
+        let value = ::routing::types::generate_random_vec_u8(64);
+        ::routing::structured_data::StructuredData::new(0, name, version, value, vec![keys.0],
+                                                        vec![], Some(&keys.1)).ok().unwrap()
+    }
+
+    #[test]
+    fn merge() {
+        let keys = crypto::sign::gen_keypair();
+        let sd_name = ::routing::NameType([3u8; 64]);
+        let old = Account::new(&structured_data(sd_name, 0, &keys)).unwrap();
+        let new = Account::new(&structured_data(sd_name, 1, &keys)).unwrap();
+        let name = *old.name();
+        let majority = ::types::default_quorum();
+
+        // a single peer can't impose its version
+        let mut responses = vec![new.clone()];
+        for _ in 0..majority {
+            responses.push(old.clone());
+        }
+        assert_eq!(Account::merge(name, responses.clone(), majority), Ok(old.clone()));
+
+        // the highest version agreed by a majority wins
+        for _ in 1..majority {
+            responses.push(new.clone());
+        }
+        assert_eq!(Account::merge(name, responses.clone(), majority), Ok(new.clone()));
+
+        // responses for another group, or whose data doesn't match the claimed version, are ignored
+        assert_eq!(Account::merge(::utils::random_name(), responses, majority),
+                   Err(::types::MergeError::NameMismatch));
+        let forged = Account { version: 5, ..new.clone() };
+        assert_eq!(Account::merge(name, vec![forged; majority], majority),
+                   Err(::types::MergeError::InsufficientQuorum));
+    }
+}
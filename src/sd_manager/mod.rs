@@ -15,32 +15,55 @@
 // Please review the Licences for the specific language governing permissions and limitations
 // relating to use of the SAFE Network Software.
 
+mod account;
+mod subscription;
+mod type_tag;
+
 use chunk_store::ChunkStore;
+use types::PlainPayload;
 use transfer_parser::transfer_tags::SD_MANAGER_ACCOUNT_TAG;
 
+pub use self::account::Account;
+pub use self::subscription::Account as SubscriptionAccount;
+
 pub struct StructuredDataManager {
     // TODO: This is assuming ChunkStore has the ability of handling mutable(SDV)
     // data, and put is overwritable
     // If such assumption becomes in-valid, LruCache or Sqlite based persona specific
     // database shall be used
     chunk_store_: ChunkStore,
+    type_tag_registry: type_tag::TypeTagRegistry,
+    subscriptions: subscription::Subscriptions,
 }
 
 impl StructuredDataManager {
     pub fn new() -> StructuredDataManager {
         // TODO adjustable max_disk_space
-        StructuredDataManager { chunk_store_: ChunkStore::new(1073741824) }
+        StructuredDataManager {
+            chunk_store_: ChunkStore::new(1073741824),
+            type_tag_registry: type_tag::TypeTagRegistry::new(),
+            subscriptions: subscription::Subscriptions::new(),
+        }
     }
 
     pub fn handle_get(&self, name: ::routing::NameType) -> Vec<::types::MethodCall> {
         let data = self.chunk_store_.get(name);
         if data.len() == 0 {
-            return vec![];
+            return vec![::types::MethodCall::GetFailed {
+                name: name,
+                reason: ::types::GetFailureReason::NoSuchData,
+            }];
         }
         let sd: ::routing::structured_data::StructuredData =
             match ::routing::utils::decode(&data) {
                 Ok(data) => data,
-                Err(_) => return vec![],
+                Err(_) => {
+                    error!("StructuredData {:?} stored by the SdManager can't be parsed", name);
+                    return vec![::types::MethodCall::GetFailed {
+                        name: name,
+                        reason: ::types::GetFailureReason::Corrupt,
+                    }];
+                }
             };
         vec![::types::MethodCall::Reply { data: ::routing::data::Data::StructuredData(sd) }]
     }
@@ -81,50 +104,138 @@ impl StructuredDataManager {
                             data: ::routing::data::Data::StructuredData(in_coming_data)
                         }];
         }
-        if let Ok(mut sd) =
+        if let Ok(sd) =
                ::routing::utils::decode::<::routing::structured_data::StructuredData>(&data) {
             debug!("sd_manager updating {:?} to {:?}", sd, in_coming_data);
-            match sd.replace_with_other(in_coming_data.clone()) {
-                Ok(_) => {}
+            // The rules for accepting an update depend on the type_tag of the stored data
+            let sd = match self.type_tag_registry.handle_post(&sd, in_coming_data.clone()) {
+                Ok(updated) => updated,
                 Err(_) => {
                     return vec![::types::MethodCall::InvalidRequest {
                                     data: ::routing::data::Data::StructuredData(in_coming_data)
                                 }]
                 }
-            }
+            };
             if let Ok(serialised_data) = ::routing::utils::encode(&sd) {
                 self.chunk_store_.put(in_coming_data.name(), serialised_data);
+                return self.subscriptions
+                           .subscribers(&sd.name())
+                           .into_iter()
+                           .map(|subscriber| {
+                               ::types::MethodCall::Notify {
+                                   location: subscriber,
+                                   data: ::routing::data::Data::StructuredData(sd.clone()),
+                               }
+                           })
+                           .collect();
             }
         }
         vec![]
     }
 
-    pub fn handle_account_transfer(&mut self, in_coming_sd: Vec<u8>) {
-        let sd: ::routing::structured_data::StructuredData =
-            match ::routing::utils::decode(&in_coming_sd) {
-                Ok(result) => {
-                    result
+    // Clients (un)subscribe to changes of a StructuredData by posting a PlainData carrying a
+    // SubscriptionRequest to the data's NaeManager.  Subscribing to data we don't hold, or to
+    // data which has too many subscribers already, is rejected.
+    pub fn handle_subscription(&mut self,
+                               from_authority: ::routing::authority::Authority,
+                               request: ::routing::data::Data)
+                               -> Vec<::types::MethodCall> {
+        match from_authority {
+            ::routing::authority::Authority::Client(_, _) => {}
+            _ => return vec![::types::MethodCall::InvalidRequest { data: request }],
+        }
+        match subscription::SubscriptionRequest::from_plain_data(&request) {
+            Some(subscription_request) => {
+                debug!("sd_manager handling {:?} from {:?} for {:?}", subscription_request,
+                       from_authority, request.name());
+                if let subscription::SubscriptionRequest::Subscribe(_) = subscription_request {
+                    if !self.chunk_store_.has_chunk(request.name()) {
+                        return vec![::types::MethodCall::InvalidRequest { data: request }];
+                    }
                 }
-                Err(_) => return,
-            };
-        info!("SdManager transferred structured_data {:?} in", sd.name());
-        self.chunk_store_.delete(sd.name());
-        self.chunk_store_.put(sd.name(), in_coming_sd);
+                if self.subscriptions.handle_request(request.name(), from_authority,
+                                                     subscription_request) {
+                    vec![]
+                } else {
+                    vec![::types::MethodCall::InvalidRequest { data: request }]
+                }
+            }
+            None => vec![::types::MethodCall::InvalidRequest { data: request }],
+        }
+    }
+
+    pub fn handle_subscriptions_transfer(&mut self, merged_account: SubscriptionAccount) {
+        self.subscriptions.handle_account_transfer(merged_account);
+    }
+
+    /// The data transferred in replaces our copy, unless ours is of a higher version: the group
+    /// may have agreed on the data before an update reached us all.
+    pub fn handle_account_transfer(&mut self, merged_account: Account) {
+        let stored = self.chunk_store_.get(*merged_account.name());
+        if let Ok(sd) =
+               ::routing::utils::decode::<::routing::structured_data::StructuredData>(&stored) {
+            if sd.get_version() > merged_account.version() {
+                info!("SdManager keeping structured_data {:?} version {:?} over version {:?}",
+                      merged_account.name(), sd.get_version(), merged_account.version());
+                return;
+            }
+        }
+        info!("SdManager transferred structured_data {:?} version {:?} in", merged_account.name(),
+              merged_account.version());
+        self.chunk_store_.delete(*merged_account.name());
+        self.chunk_store_.put(*merged_account.name(), merged_account.data().clone());
+    }
+
+    pub fn flush(&self) -> ::std::io::Result<()> {
+        self.chunk_store_.flush()
     }
 
     pub fn retrieve_all_and_reset(&mut self) -> Vec<::types::MethodCall> {
-        let names = self.chunk_store_.names();
-        let mut actions = Vec::with_capacity(names.len());
-        for name in names {
-            let data = self.chunk_store_.get(name.clone());
-            actions.push(::types::MethodCall::Refresh {
+        let mut actions = self.chunk_store_
+                              .names()
+                              .into_iter()
+                              .filter_map(|name| self.refresh(name))
+                              .collect::<Vec<_>>();
+        self.chunk_store_ = ChunkStore::new(1073741824);
+        actions.extend(self.subscriptions.retrieve_all_and_reset().into_iter());
+        actions
+    }
+
+    /// Refreshes the data whose group changed, dropping that we're no longer responsible for.
+    pub fn handle_churn(&mut self, churn: &::churn::Churn) -> Vec<::types::MethodCall> {
+        let mut actions = Vec::new();
+        for name in self.chunk_store_.names() {
+            if !churn.affects(&name) {
+                continue;
+            }
+            if let Some(action) = self.refresh(name.clone()) {
+                actions.push(action);
+            }
+            if !churn.keeps(&name) {
+                self.chunk_store_.delete(name);
+            }
+        }
+        actions.extend(self.subscriptions.handle_churn(churn).into_iter());
+        actions
+    }
+
+    fn refresh(&self, name: ::routing::NameType) -> Option<::types::MethodCall> {
+        let data = self.chunk_store_.get(name.clone());
+        let account = match ::routing::utils::decode(&data) {
+            Ok(sd) => match Account::new(&sd) {
+                Some(account) => account,
+                None => return None,
+            },
+            Err(_) => return None,
+        };
+        match ::transfer_parser::encode(SD_MANAGER_ACCOUNT_TAG, &account) {
+            Some(payload) => Some(::types::MethodCall::Refresh {
                 type_tag: SD_MANAGER_ACCOUNT_TAG,
                 our_authority: ::routing::Authority::NaeManager(name),
-                payload: data
-            });
+                payload: payload
+            }),
+            None => None,
         }
-        self.chunk_store_ = ChunkStore::new(1073741824);
-        actions
     }
 
 }
@@ -135,6 +246,7 @@ impl StructuredDataManager {
 mod test {
     use super::*;
     use sodiumoxide::crypto;
+    use types::PlainPayload;
 
     #[test]
     fn handle_put_get() {
@@ -180,6 +292,21 @@ mod test {
                 _ => panic!("Unexpected"),
             }
         }
+        {
+            let missing = ::utils::random_name();
+            assert_eq!(sd_manager.handle_get(missing.clone()),
+                       vec![::types::MethodCall::GetFailed {
+                           name: missing,
+                           reason: ::types::GetFailureReason::NoSuchData,
+                       }]);
+            let corrupt = ::utils::random_name();
+            sd_manager.chunk_store_.put(corrupt.clone(), vec![1, 2, 3]);
+            assert_eq!(sd_manager.handle_get(corrupt.clone()),
+                       vec![::types::MethodCall::GetFailed {
+                           name: corrupt,
+                           reason: ::types::GetFailureReason::Corrupt,
+                       }]);
+        }
     }
 
     #[test]
@@ -251,6 +378,40 @@ mod test {
         }
     }
 
+    #[test]
+    fn handle_subscription() {
+        let mut sd_manager = StructuredDataManager::new();
+        let name = ::routing::NameType([3u8; 64]);
+        let value = ::routing::types::generate_random_vec_u8(1024);
+        let keys = crypto::sign::gen_keypair();
+        let sdv = ::routing::structured_data::StructuredData::new(0, name, 0, value.clone(),
+                                                                  vec![keys.0], vec![],
+                                                                  Some(&keys.1)).ok().unwrap();
+        let _ = sd_manager.handle_put(sdv.clone());
+
+        let subscriber = ::routing::authority::Authority::Client(::utils::random_name(),
+                                                                 keys.0);
+        let request = super::subscription::SubscriptionRequest::Subscribe(60)
+                          .to_plain_data(sdv.name()).unwrap();
+        assert!(sd_manager.handle_subscription(subscriber.clone(), request).is_empty());
+
+        // data we don't hold can't be subscribed to
+        let missing = super::subscription::SubscriptionRequest::Subscribe(60)
+                          .to_plain_data(::utils::random_name()).unwrap();
+        assert_eq!(sd_manager.handle_subscription(subscriber.clone(), missing.clone()),
+                   vec![::types::MethodCall::InvalidRequest { data: missing }]);
+
+        let sdv_new = ::routing::structured_data::StructuredData::new(0, name, 1, value.clone(),
+                                                                      vec![keys.0], vec![],
+                                                                      Some(&keys.1)).ok()
+                                                                      .unwrap();
+        assert_eq!(sd_manager.handle_post(sdv_new.clone()),
+                   vec![::types::MethodCall::Notify {
+                            location: subscriber,
+                            data: ::routing::data::Data::StructuredData(sdv_new),
+                        }]);
+    }
+
     #[test]
     fn handle_account_transfer() {
         let name = ::routing::NameType([3u8; 64]);
@@ -261,14 +422,25 @@ mod test {
                                                                   .unwrap();
 
         let mut sd_manager = StructuredDataManager::new();
-        let serialised_data = match ::routing::utils::encode(&sdv) {
-            Ok(result) => result,
-            Err(_) => panic!("Unexpected"),
-        };
-        sd_manager.handle_account_transfer(serialised_data);
+        sd_manager.handle_account_transfer(Account::new(&sdv).unwrap());
         assert!(sd_manager.chunk_store_.has_chunk(
             ::routing::structured_data::StructuredData::compute_name(0,
                 &::routing::NameType([3u8; 64]))));
+
+        // a higher version transferred in replaces ours, a lower one doesn't
+        let version = |sd_manager: &StructuredDataManager| {
+            ::routing::utils::decode::<::routing::structured_data::StructuredData>(
+                &sd_manager.chunk_store_.get(sdv.name())).unwrap().get_version()
+        };
+        let sdv_new = ::routing::structured_data::StructuredData::new(0, name, 1,
+                                                                      sdv.get_data().clone(),
+                                                                      vec![keys.0], vec![],
+                                                                      Some(&keys.1)).ok()
+                                                                      .unwrap();
+        sd_manager.handle_account_transfer(Account::new(&sdv_new).unwrap());
+        assert_eq!(version(&sd_manager), 1);
+        sd_manager.handle_account_transfer(Account::new(&sdv).unwrap());
+        assert_eq!(version(&sd_manager), 1);
     }
 
 }
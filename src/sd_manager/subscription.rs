@@ -0,0 +1,363 @@
+// Copyright 2015 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use std::collections::HashMap;
+
+use transfer_parser::transfer_tags::SD_MANAGER_SUBSCRIPTIONS_TAG;
+use utils;
+
+/// Lease granted when a client doesn't ask for a specific one, and the upper bound of any lease.
+pub const DEFAULT_LEASE_SECONDS: u64 = 600;
+pub const MAX_LEASE_SECONDS: u64 = 3600;
+/// Most clients subscribed to a single StructuredData at a time.
+pub const MAX_SUBSCRIBERS: usize = 100;
+
+/// Sent by a client as the value of a PlainData posted to the NaeManager of a StructuredData.
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Eq, Debug, Clone)]
+pub enum SubscriptionRequest {
+    Subscribe(u64),
+    Unsubscribe,
+}
+
+impl ::types::PlainPayload for SubscriptionRequest {
+    fn type_tag() -> u64 {
+        ::types::payload_tags::SUBSCRIPTION_REQUEST_TAG
+    }
+}
+
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Eq, Debug, Clone)]
+pub struct Subscription {
+    subscriber: ::routing::authority::Authority,
+    // seconds since the epoch
+    expires_at: u64,
+}
+
+impl Subscription {
+    pub fn new(subscriber: ::routing::authority::Authority, expires_at: u64) -> Subscription {
+        Subscription { subscriber: subscriber, expires_at: expires_at }
+    }
+}
+
+/// All subscriptions held for a single StructuredData, as transferred on churn.
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Eq, Debug, Clone)]
+pub struct Account {
+    name: ::routing::NameType,
+    subscriptions: Vec<Subscription>,
+}
+
+impl Account {
+    pub fn new(name: ::routing::NameType, subscriptions: Vec<Subscription>) -> Account {
+        Account { name: name, subscriptions: subscriptions }
+    }
+
+    pub fn name(&self) -> &::routing::NameType {
+        &self.name
+    }
+
+    pub fn subscriptions(&self) -> &Vec<Subscription> {
+        &self.subscriptions
+    }
+}
+
+impl ::types::Refreshable for Account {
+    // A subscriber is kept if it was reported by a quorum of the group, with the median expiry.
+    fn merge(from_group: ::routing::NameType,
+             responses: Vec<Account>,
+             quorum: usize)
+             -> Result<Account, ::types::MergeError> {
+        let responses = try!(::types::responses_for(&from_group, responses, quorum,
+                                                    Account::name));
+        let mut expiries = Vec::<(::routing::authority::Authority, Vec<u64>)>::new();
+        for response in responses {
+            for subscription in response.subscriptions {
+                let push_in_vec = match expiries.iter_mut()
+                                                .find(|a| a.0 == subscription.subscriber) {
+                    Some(find_res) => {
+                        find_res.1.push(subscription.expires_at);
+                        false
+                    }
+                    None => true,
+                };
+                if push_in_vec {
+                    expiries.push((subscription.subscriber, vec![subscription.expires_at]));
+                }
+            }
+        }
+        let subscriptions = expiries.into_iter()
+                                    .filter(|a| a.1.len() >= quorum)
+                                    .map(|(subscriber, expiries)| {
+                                        Subscription::new(subscriber, utils::median(expiries))
+                                    })
+                                    .collect::<Vec<_>>();
+        if subscriptions.is_empty() {
+            return Err(::types::MergeError::InsufficientQuorum);
+        }
+        Ok(Account::new(from_group, subscriptions))
+    }
+}
+
+
+
+pub struct Subscriptions {
+    storage: HashMap<::routing::NameType, Vec<Subscription>>,
+}
+
+impl Subscriptions {
+    pub fn new() -> Subscriptions {
+        Subscriptions { storage: HashMap::new() }
+    }
+
+    /// Returns false if a new subscriber is refused, as `name` already has `MAX_SUBSCRIBERS`.  A
+    /// subscriber renewing its lease, or unsubscribing, is never refused.
+    pub fn handle_request(&mut self,
+                          name: ::routing::NameType,
+                          subscriber: ::routing::authority::Authority,
+                          request: SubscriptionRequest)
+                          -> bool {
+        let lease = match request {
+            SubscriptionRequest::Subscribe(0) => DEFAULT_LEASE_SECONDS,
+            SubscriptionRequest::Subscribe(lease) => ::std::cmp::min(lease, MAX_LEASE_SECONDS),
+            SubscriptionRequest::Unsubscribe => {
+                let emptied = match self.storage.get_mut(&name) {
+                    Some(subscriptions) => {
+                        subscriptions.retain(|a| a.subscriber != subscriber);
+                        subscriptions.is_empty()
+                    }
+                    None => false,
+                };
+                if emptied {
+                    let _ = self.storage.remove(&name);
+                }
+                return true;
+            }
+        };
+        let now = now();
+        let subscriptions = self.storage.entry(name).or_insert(vec![]);
+        subscriptions.retain(|a| a.subscriber != subscriber && a.expires_at > now);
+        if subscriptions.len() >= MAX_SUBSCRIBERS {
+            return false;
+        }
+        subscriptions.push(Subscription::new(subscriber, now + lease));
+        true
+    }
+
+    /// Returns the authorities whose leases on `name` are still valid, dropping the expired ones.
+    pub fn subscribers(&mut self, name: &::routing::NameType)
+                       -> Vec<::routing::authority::Authority> {
+        self.purge_expired();
+        match self.storage.get(name) {
+            Some(subscriptions) => subscriptions.iter().map(|a| a.subscriber.clone()).collect(),
+            None => vec![],
+        }
+    }
+
+    /// The subscriptions transferred in are added to ours, a subscriber held by both keeping the
+    /// later of its two expiries.
+    pub fn handle_account_transfer(&mut self, merged_account: Account) {
+        let subscriptions = self.storage.entry(*merged_account.name()).or_insert(vec![]);
+        for merged in merged_account.subscriptions().iter() {
+            let push_in_vec = match subscriptions.iter_mut()
+                                                 .find(|a| a.subscriber == merged.subscriber) {
+                Some(find_res) => {
+                    find_res.expires_at = ::std::cmp::max(find_res.expires_at, merged.expires_at);
+                    false
+                }
+                None => true,
+            };
+            if push_in_vec {
+                subscriptions.push(merged.clone());
+            }
+        }
+        info!("SdManager updated subscriptions of {:?}", merged_account.name());
+    }
+
+    pub fn retrieve_all_and_reset(&mut self) -> Vec<::types::MethodCall> {
+        self.purge_expired();
+        let actions = self.storage
+                          .iter()
+                          .filter_map(|(key, value)| refresh(key, value))
+                          .collect();
+        self.storage.clear();
+        actions
+    }
+
+    /// Refreshes the subscriptions to data whose group changed, dropping those we're no longer
+    /// responsible for.
+    pub fn handle_churn(&mut self, churn: &::churn::Churn) -> Vec<::types::MethodCall> {
+        self.purge_expired();
+        let mut actions = Vec::new();
+        let mut lost = Vec::new();
+        for (key, value) in self.storage.iter() {
+            if !churn.affects(key) {
+                continue;
+            }
+            if let Some(action) = refresh(key, value) {
+                actions.push(action);
+            }
+            if !churn.keeps(key) {
+                lost.push(key.clone());
+            }
+        }
+        for name in lost {
+            let _ = self.storage.remove(&name);
+        }
+        actions
+    }
+
+    fn purge_expired(&mut self) {
+        let now = now();
+        for (_, subscriptions) in self.storage.iter_mut() {
+            subscriptions.retain(|a| a.expires_at > now);
+        }
+        let expired_names = self.storage.iter()
+                                        .filter(|&(_, subscriptions)| subscriptions.is_empty())
+                                        .map(|(name, _)| name.clone())
+                                        .collect::<Vec<_>>();
+        for name in expired_names {
+            let _ = self.storage.remove(&name);
+        }
+    }
+}
+
+fn now() -> u64 {
+    ::time::get_time().sec as u64
+}
+
+fn refresh(name: &::routing::NameType,
+           subscriptions: &Vec<Subscription>)
+           -> Option<::types::MethodCall> {
+    let account = Account::new(name.clone(), subscriptions.clone());
+    match ::transfer_parser::encode(SD_MANAGER_SUBSCRIPTIONS_TAG, &account) {
+        Some(payload) => Some(::types::MethodCall::Refresh {
+            type_tag: SD_MANAGER_SUBSCRIPTIONS_TAG,
+            our_authority: ::routing::Authority::NaeManager(name.clone()),
+            payload: payload
+        }),
+        None => None,
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use sodiumoxide::crypto;
+    use types::Refreshable;
+
+    fn client() -> ::routing::authority::Authority {
+        ::routing::authority::Authority::Client(::utils::random_name(),
+                                                crypto::sign::gen_keypair().0)
+    }
+
+    #[test]
+    fn subscribe_and_unsubscribe() {
+        let mut subscriptions = Subscriptions::new();
+        let name = ::utils::random_name();
+        let (first, second) = (client(), client());
+        assert!(subscriptions.handle_request(name, first.clone(),
+                                             SubscriptionRequest::Subscribe(0)));
+        assert!(subscriptions.handle_request(name, second.clone(),
+                                             SubscriptionRequest::Subscribe(10)));
+        assert_eq!(subscriptions.subscribers(&name), vec![first.clone(), second.clone()]);
+
+        assert!(subscriptions.handle_request(name, first, SubscriptionRequest::Unsubscribe));
+        assert_eq!(subscriptions.subscribers(&name), vec![second]);
+        assert!(subscriptions.subscribers(&::utils::random_name()).is_empty());
+    }
+
+    #[test]
+    fn subscriber_cap() {
+        let mut subscriptions = Subscriptions::new();
+        let name = ::utils::random_name();
+        let subscribers = (0..MAX_SUBSCRIBERS).map(|_| client()).collect::<Vec<_>>();
+        for subscriber in subscribers.iter() {
+            assert!(subscriptions.handle_request(name, subscriber.clone(),
+                                                 SubscriptionRequest::Subscribe(0)));
+        }
+
+        // a new subscriber is refused once the cap is reached, while the others may renew
+        let refused = client();
+        assert!(!subscriptions.handle_request(name, refused.clone(),
+                                              SubscriptionRequest::Subscribe(0)));
+        assert!(subscriptions.handle_request(name, subscribers[0].clone(),
+                                             SubscriptionRequest::Subscribe(10)));
+        assert_eq!(subscriptions.subscribers(&name).len(), MAX_SUBSCRIBERS);
+        assert!(!subscriptions.subscribers(&name).contains(&refused));
+
+        // until one of them leaves
+        assert!(subscriptions.handle_request(name, subscribers[1].clone(),
+                                             SubscriptionRequest::Unsubscribe));
+        assert!(subscriptions.handle_request(name, refused.clone(),
+                                             SubscriptionRequest::Subscribe(0)));
+        assert!(subscriptions.subscribers(&name).contains(&refused));
+
+        // and unsubscribing from a name without subscribers leaves nothing behind
+        let other = ::utils::random_name();
+        assert!(subscriptions.handle_request(other, refused, SubscriptionRequest::Unsubscribe));
+        assert!(!subscriptions.storage.contains_key(&other));
+    }
+
+    #[test]
+    fn expired_lease() {
+        let mut subscriptions = Subscriptions::new();
+        let name = ::utils::random_name();
+        let subscriber = client();
+        subscriptions.handle_account_transfer(
+            Account::new(name, vec![Subscription::new(subscriber, super::now() - 1)]));
+        assert!(subscriptions.subscribers(&name).is_empty());
+        assert!(subscriptions.retrieve_all_and_reset().is_empty());
+    }
+
+    #[test]
+    fn account_transfer() {
+        let mut subscriptions = Subscriptions::new();
+        let name = ::utils::random_name();
+        let (local, renewed, transferred) = (client(), client(), client());
+        assert!(subscriptions.handle_request(name, local.clone(),
+                                             SubscriptionRequest::Subscribe(0)));
+        assert!(subscriptions.handle_request(name, renewed.clone(),
+                                             SubscriptionRequest::Subscribe(MAX_LEASE_SECONDS)));
+        let expires_at = subscriptions.storage[&name][1].expires_at;
+
+        // the subscribers transferred in join ours, each keeping its later expiry
+        subscriptions.handle_account_transfer(
+            Account::new(name, vec![Subscription::new(renewed.clone(), expires_at - 10),
+                                    Subscription::new(transferred.clone(), expires_at)]));
+        assert_eq!(subscriptions.subscribers(&name), vec![local, renewed, transferred]);
+        assert_eq!(subscriptions.storage[&name][1].expires_at, expires_at);
+    }
+
+    #[test]
+    fn merge() {
+        let name = ::utils::random_name();
+        let (agreed, disputed) = (client(), client());
+        let mut responses = Vec::new();
+        for i in 0..::routing::types::GROUP_SIZE {
+            let mut subscriptions = vec![Subscription::new(agreed.clone(), 100 + i as u64)];
+            if i == 0 {
+                subscriptions.push(Subscription::new(disputed.clone(), 100));
+            }
+            responses.push(Account::new(name, subscriptions));
+        }
+        let merged = Account::merge(name, responses, ::types::default_quorum()).unwrap();
+        assert_eq!(merged.subscriptions().len(), 1);
+        assert_eq!(merged.subscriptions()[0].subscriber, agreed);
+        assert_eq!(Account::merge(name, vec![], 1),
+                   Err(::types::MergeError::InsufficientQuorum));
+    }
+}
@@ -0,0 +1,275 @@
+// Copyright 2015 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use sodiumoxide::crypto;
+use std::collections::HashMap;
+
+type StructuredData = ::routing::structured_data::StructuredData;
+
+/// Reserved type tags which the StructuredDataManager treats specially.  Any tag not listed here is
+/// handled with the same rules as `PLAIN_TYPE_TAG`.
+pub mod reserved_tags {
+    /// Versioned data: every post must increment the version by exactly one.
+    pub const PLAIN_TYPE_TAG: u64 = 0;
+    /// Unversioned data: any post signed by the current owners with a higher version replaces the
+    /// stored copy, the version may skip.
+    pub const UNVERSIONED_TYPE_TAG: u64 = 1;
+    /// Appendable data: non-owners may append signed entries, owners may post as usual.
+    pub const APPENDABLE_TYPE_TAG: u64 = 2;
+}
+
+/// A single entry appended to an appendable StructuredData by any (possibly non-owning) client.
+/// The signature covers the name of the data and the version the entry is appended in, so that the
+/// entry can't be replayed into another StructuredData or a later version.
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Eq, Debug, Clone)]
+pub struct AppendedEntry {
+    pub sign_key: crypto::sign::PublicKey,
+    pub data: Vec<u8>,
+    pub signature: crypto::sign::Signature,
+}
+
+impl AppendedEntry {
+    pub fn verify(&self, name: ::routing::NameType, version: u64) -> bool {
+        crypto::sign::verify_detached(&self.signature,
+                                      &entry_to_sign(name, version, &self.data)[..],
+                                      &self.sign_key)
+    }
+}
+
+fn entry_to_sign(name: ::routing::NameType, version: u64, data: &Vec<u8>) -> Vec<u8> {
+    ::routing::utils::encode(&(name, version, data.clone())).unwrap_or(vec![])
+}
+
+/// Decides whether an incoming StructuredData may replace the stored copy.  On success the value
+/// to be stored is returned.
+pub trait TypeTagHandler {
+    fn handle_post(&self, existing: &StructuredData, incoming: StructuredData)
+                   -> Result<StructuredData, ()>;
+}
+
+/// The default rules: `StructuredData::replace_with_other`, which requires the version to be
+/// bumped by exactly one and the change to be signed by the current owners.
+pub struct VersionedHandler;
+
+impl TypeTagHandler for VersionedHandler {
+    fn handle_post(&self, existing: &StructuredData, incoming: StructuredData)
+                   -> Result<StructuredData, ()> {
+        let mut updated = existing.clone();
+        match updated.replace_with_other(incoming) {
+            Ok(_) => Ok(updated),
+            Err(_) => Err(()),
+        }
+    }
+}
+
+/// Last writer wins: the version needn't be bumped by exactly one, but it must increase so that the
+/// group can agree on the latest copy when it is refreshed on churn.
+pub struct UnversionedHandler;
+
+impl TypeTagHandler for UnversionedHandler {
+    fn handle_post(&self, existing: &StructuredData, incoming: StructuredData)
+                   -> Result<StructuredData, ()> {
+        if !same_identity(existing, &incoming) ||
+           incoming.get_version() <= existing.get_version() ||
+           !signed_by_majority(&incoming, existing.get_owner_keys()) {
+            return Err(());
+        }
+        Ok(incoming)
+    }
+}
+
+/// The data field holds a serialised `Vec<AppendedEntry>`.  Owners may update it under the
+/// versioned rules; anyone else may only append entries signed for this data and the new version,
+/// leaving the ownership untouched and bumping the version by one.
+pub struct AppendableHandler;
+
+impl TypeTagHandler for AppendableHandler {
+    fn handle_post(&self, existing: &StructuredData, incoming: StructuredData)
+                   -> Result<StructuredData, ()> {
+        if let Ok(updated) = VersionedHandler.handle_post(existing, incoming.clone()) {
+            return Ok(updated);
+        }
+        if !same_identity(existing, &incoming) ||
+           incoming.get_version() != existing.get_version() + 1 ||
+           incoming.get_owner_keys() != existing.get_owner_keys() ||
+           incoming.get_previous_owner_keys() != existing.get_previous_owner_keys() {
+            return Err(());
+        }
+        let existing_entries = try!(decode_entries(existing.get_data()));
+        let incoming_entries = try!(decode_entries(incoming.get_data()));
+        if incoming_entries.len() <= existing_entries.len() ||
+           incoming_entries[..existing_entries.len()] != existing_entries[..] {
+            return Err(());
+        }
+        let (name, version) = (incoming.name(), incoming.get_version());
+        let new_entries = &incoming_entries[existing_entries.len()..];
+        if new_entries.iter().all(|entry| entry.verify(name.clone(), version)) {
+            Ok(incoming)
+        } else {
+            Err(())
+        }
+    }
+}
+
+/// Maps type tags to their handlers, falling back to the versioned rules for unreserved tags.
+pub struct TypeTagRegistry {
+    handlers: HashMap<u64, Box<TypeTagHandler>>,
+    default_handler: Box<TypeTagHandler>,
+}
+
+impl TypeTagRegistry {
+    pub fn new() -> TypeTagRegistry {
+        let mut registry = TypeTagRegistry {
+            handlers: HashMap::new(),
+            default_handler: Box::new(VersionedHandler),
+        };
+        registry.register(reserved_tags::PLAIN_TYPE_TAG, Box::new(VersionedHandler));
+        registry.register(reserved_tags::UNVERSIONED_TYPE_TAG, Box::new(UnversionedHandler));
+        registry.register(reserved_tags::APPENDABLE_TYPE_TAG, Box::new(AppendableHandler));
+        registry
+    }
+
+    pub fn register(&mut self, type_tag: u64, handler: Box<TypeTagHandler>) {
+        let _ = self.handlers.insert(type_tag, handler);
+    }
+
+    pub fn handle_post(&self, existing: &StructuredData, incoming: StructuredData)
+                       -> Result<StructuredData, ()> {
+        match self.handlers.get(&existing.get_type_tag()) {
+            Some(handler) => handler.handle_post(existing, incoming),
+            None => self.default_handler.handle_post(existing, incoming),
+        }
+    }
+}
+
+fn same_identity(existing: &StructuredData, incoming: &StructuredData) -> bool {
+    existing.get_type_tag() == incoming.get_type_tag() &&
+    existing.get_identifier() == incoming.get_identifier()
+}
+
+// More than half of `owner_keys` must have produced a valid signature over the incoming data.
+fn signed_by_majority(incoming: &StructuredData, owner_keys: &Vec<crypto::sign::PublicKey>)
+                      -> bool {
+    let data_to_sign = match incoming.data_to_sign() {
+        Ok(data) => data,
+        Err(_) => return false,
+    };
+    let valid_signers = owner_keys.iter().filter(|key| {
+        incoming.get_signatures().iter().any(|signature| {
+            crypto::sign::verify_detached(signature, &data_to_sign[..], key)
+        })
+    }).count();
+    valid_signers * 2 > owner_keys.len()
+}
+
+fn decode_entries(data: &Vec<u8>) -> Result<Vec<AppendedEntry>, ()> {
+    if data.is_empty() {
+        return Ok(vec![]);
+    }
+    ::routing::utils::decode::<Vec<AppendedEntry>>(data).map_err(|_| ())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use sodiumoxide::crypto;
+
+    fn new_sd(type_tag: u64,
+              version: u64,
+              data: Vec<u8>,
+              keys: &(crypto::sign::PublicKey, crypto::sign::SecretKey))
+              -> ::routing::structured_data::StructuredData {
+        ::routing::structured_data::StructuredData::new(type_tag, ::routing::NameType([3u8; 64]),
+                                                        version, data, vec![keys.0], vec![],
+                                                        Some(&keys.1)).ok().unwrap()
+    }
+
+    // Entries are signed by the clients appending them, the vault only verifies them
+    fn appended_entry(name: ::routing::NameType,
+                      version: u64,
+                      data: Vec<u8>,
+                      keys: &(crypto::sign::PublicKey, crypto::sign::SecretKey))
+                      -> AppendedEntry {
+        let signature =
+            crypto::sign::sign_detached(&super::entry_to_sign(name, version, &data)[..], &keys.1);
+        AppendedEntry { sign_key: keys.0, data: data, signature: signature }
+    }
+
+    #[test]
+    fn versioned() {
+        let registry = TypeTagRegistry::new();
+        let keys = crypto::sign::gen_keypair();
+        let existing = new_sd(reserved_tags::PLAIN_TYPE_TAG, 0, vec![1], &keys);
+        assert!(registry.handle_post(&existing,
+            new_sd(reserved_tags::PLAIN_TYPE_TAG, 2, vec![2], &keys)).is_err());
+        let updated = new_sd(reserved_tags::PLAIN_TYPE_TAG, 1, vec![2], &keys);
+        assert_eq!(registry.handle_post(&existing, updated.clone()).ok(), Some(updated));
+    }
+
+    #[test]
+    fn unversioned() {
+        let registry = TypeTagRegistry::new();
+        let keys = crypto::sign::gen_keypair();
+        let existing = new_sd(reserved_tags::UNVERSIONED_TYPE_TAG, 5, vec![1], &keys);
+        // the version may skip, but never go back or stay
+        let updated = new_sd(reserved_tags::UNVERSIONED_TYPE_TAG, 8, vec![2], &keys);
+        assert_eq!(registry.handle_post(&existing, updated.clone()).ok(), Some(updated));
+        for version in vec![0, 5] {
+            assert!(registry.handle_post(&existing,
+                new_sd(reserved_tags::UNVERSIONED_TYPE_TAG, version, vec![2], &keys)).is_err());
+        }
+
+        let other_keys = crypto::sign::gen_keypair();
+        let foreign = new_sd(reserved_tags::UNVERSIONED_TYPE_TAG, 6, vec![3], &other_keys);
+        assert!(registry.handle_post(&existing, foreign).is_err());
+    }
+
+    #[test]
+    fn appendable() {
+        let registry = TypeTagRegistry::new();
+        let owner_keys = crypto::sign::gen_keypair();
+        let appender_keys = crypto::sign::gen_keypair();
+        let existing = new_sd(reserved_tags::APPENDABLE_TYPE_TAG, 0, vec![], &owner_keys);
+
+        let append = |version: u64, entries: &Vec<AppendedEntry>| {
+            // Signed by the appender rather than the owner
+            ::routing::structured_data::StructuredData::new(
+                reserved_tags::APPENDABLE_TYPE_TAG, ::routing::NameType([3u8; 64]), version,
+                ::routing::utils::encode(entries).ok().unwrap(), vec![owner_keys.0], vec![],
+                Some(&appender_keys.1)).ok().unwrap()
+        };
+        let entry = appended_entry(existing.name(), 1, vec![7u8; 10], &appender_keys);
+        let appended = append(1, &vec![entry.clone()]);
+        assert_eq!(registry.handle_post(&existing, appended.clone()).ok(), Some(appended.clone()));
+
+        // An entry signed for another version, or another data, can't be replayed
+        let replayed = append(2, &vec![entry.clone(), entry.clone()]);
+        assert!(registry.handle_post(&appended, replayed).is_err());
+        let elsewhere = appended_entry(::utils::random_name(), 2, vec![7u8; 10], &appender_keys);
+        assert!(registry.handle_post(&appended, append(2, &vec![entry.clone(), elsewhere]))
+                        .is_err());
+
+        // Tampered entry
+        let mut forged_entry = entry.clone();
+        forged_entry.data = vec![8u8; 10];
+        let forged_data = ::routing::utils::encode(&vec![entry, forged_entry]).ok().unwrap();
+        let forged = ::routing::structured_data::StructuredData::new(
+            reserved_tags::APPENDABLE_TYPE_TAG, ::routing::NameType([3u8; 64]), 2, forged_data,
+            vec![owner_keys.0], vec![], Some(&appender_keys.1)).ok().unwrap();
+        assert!(registry.handle_post(&appended, forged).is_err());
+    }
+}
@@ -25,6 +25,36 @@ pub mod transfer_tags {
     pub const PMID_MANAGER_ACCOUNT_TAG: u64 = MAIDSAFE_TRANSFER_TAG + 3;
     pub const SD_MANAGER_ACCOUNT_TAG: u64 = MAIDSAFE_TRANSFER_TAG + 4;
     pub const DATA_MANAGER_STATS_TAG: u64 = MAIDSAFE_TRANSFER_TAG + 5;
+    pub const SD_MANAGER_SUBSCRIPTIONS_TAG: u64 = MAIDSAFE_TRANSFER_TAG + 6;
+    pub const MPID_MANAGER_ACCOUNT_TAG: u64 = MAIDSAFE_TRANSFER_TAG + 7;
+    pub const REFRESH_BATCH_TAG: u64 = MAIDSAFE_TRANSFER_TAG + 8;
+}
+
+/// The version of the transfer format written by this vault.  Version 0 is the bare contents,
+/// as written before they were wrapped in an `Envelope`; version 2 added a nonce to the message
+/// headers held by the MpidManagers and the chunks held to the PmidManager accounts.
+pub const FORMAT_VERSION: u64 = 2;
+
+/// A transfer payload as sent on the wire, stating the version of the format and the type of its
+/// contents so that vaults of different versions can tell what they're reading.  Its own layout
+/// must never change.
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Eq, Debug, Clone)]
+pub struct Envelope {
+    pub version: u64,
+    pub type_tag: u64,
+    pub payload: Vec<u8>,
+}
+
+/// The most a single batched refresh may carry, in bytes of encoded entries.
+pub const MAX_BATCH_SIZE: usize = 1024 * 1024;
+
+/// An account refreshed as part of a batch.  Each entry carries its own type and group, so that
+/// the receiver can merge it with the copies sent by the other members of that group.
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Debug, Clone)]
+pub struct BatchEntry {
+    pub type_tag: u64,
+    pub our_authority: ::routing::Authority,
+    pub payload: Vec<u8>,
 }
 
 pub enum Transfer {
@@ -32,9 +62,88 @@ pub enum Transfer {
     DataManagerAccount(::data_manager::Account),
     PmidManagerAccount(::pmid_manager::Account),
     DataManagerStats(::data_manager::Stats),
+    SdManagerAccount(::sd_manager::Account),
+    SdManagerSubscriptions(::sd_manager::SubscriptionAccount),
+    MpidManagerAccount(::mpid_manager::Account),
+    RefreshBatch(Vec<BatchEntry>),
     Unknown(u64),
 }
 
+impl Transfer {
+    /// Parses a transfer payload of any version of the format we know of.  Payloads which aren't
+    /// wrapped in an `Envelope` are read in the tag-prefixed layout of version 0.
+    pub fn parse(bytes: &Vec<u8>) -> Option<Transfer> {
+        let envelope = match ::routing::utils::decode::<Envelope>(bytes) {
+            Ok(envelope) => envelope,
+            Err(_) => {
+                let mut decoder = ::cbor::Decoder::from_bytes(&bytes[..]);
+                return decoder.decode::<Transfer>().next().and_then(|result| result.ok());
+            }
+        };
+        match envelope.type_tag {
+            transfer_tags::MAID_MANAGER_ACCOUNT_TAG =>
+                open(envelope).map(Transfer::MaidManagerAccount),
+            transfer_tags::DATA_MANAGER_ACCOUNT_TAG =>
+                open(envelope).map(Transfer::DataManagerAccount),
+            transfer_tags::PMID_MANAGER_ACCOUNT_TAG =>
+                open(envelope).map(Transfer::PmidManagerAccount),
+            transfer_tags::DATA_MANAGER_STATS_TAG =>
+                open(envelope).map(Transfer::DataManagerStats),
+            transfer_tags::SD_MANAGER_SUBSCRIPTIONS_TAG =>
+                open(envelope).map(Transfer::SdManagerSubscriptions),
+            transfer_tags::MPID_MANAGER_ACCOUNT_TAG =>
+                open(envelope).map(Transfer::MpidManagerAccount),
+            transfer_tags::SD_MANAGER_ACCOUNT_TAG =>
+                open(envelope).map(Transfer::SdManagerAccount),
+            // the layout of a batch hasn't changed since it was introduced in version 1
+            transfer_tags::REFRESH_BATCH_TAG => {
+                match envelope.version {
+                    1 | FORMAT_VERSION => ::routing::utils::decode(&envelope.payload)
+                                              .ok()
+                                              .map(Transfer::RefreshBatch),
+                    _ => None,
+                }
+            }
+            tag => Some(Transfer::Unknown(tag)),
+        }
+    }
+}
+
+/// Wraps `contents` of type `type_tag` in an envelope of the current version.
+pub fn encode<T: ::rustc_serialize::Encodable>(type_tag: u64, contents: &T) -> Option<Vec<u8>> {
+    let payload = match ::routing::utils::encode(contents) {
+        Ok(payload) => payload,
+        Err(_) => return None,
+    };
+    let envelope = Envelope {
+        version: FORMAT_VERSION,
+        type_tag: type_tag,
+        payload: payload,
+    };
+    ::routing::utils::encode(&envelope).ok()
+}
+
+/// Reads an account of type `type_tag`, either from an envelope of any version it knows of or
+/// from its bare contents as written by version 0.
+pub fn decode<T: ::types::Refreshable>(type_tag: u64, bytes: &Vec<u8>) -> Option<T> {
+    match ::routing::utils::decode::<Envelope>(bytes) {
+        Ok(envelope) => {
+            if envelope.type_tag == type_tag {
+                open(envelope)
+            } else {
+                None
+            }
+        }
+        Err(_) => T::decode_version(0, bytes),
+    }
+}
+
+// The account carried by `envelope`, as written by its version of the format
+fn open<T: ::types::Refreshable>(envelope: Envelope) -> Option<T> {
+    T::decode_version(envelope.version, &envelope.payload)
+}
+
+// The tag-prefixed layout of version 0
 impl Decodable for Transfer {
     fn decode<D: Decoder>(decoder: &mut D) -> Result<Transfer, D::Error> {
         let tag = try!(decoder.read_u64());
@@ -47,12 +156,457 @@ impl Decodable for Transfer {
                 Ok(Transfer::PmidManagerAccount(try!(Decodable::decode(decoder)))),
             transfer_tags::DATA_MANAGER_STATS_TAG =>
                 Ok(Transfer::DataManagerStats(try!(Decodable::decode(decoder)))),
-            transfer_tags::SD_MANAGER_ACCOUNT_TAG => Ok(Transfer::Unknown(tag)),
+            transfer_tags::SD_MANAGER_SUBSCRIPTIONS_TAG =>
+                Ok(Transfer::SdManagerSubscriptions(try!(Decodable::decode(decoder)))),
+            transfer_tags::MPID_MANAGER_ACCOUNT_TAG =>
+                Ok(Transfer::MpidManagerAccount(try!(Decodable::decode(decoder)))),
+            transfer_tags::SD_MANAGER_ACCOUNT_TAG =>
+                Ok(Transfer::SdManagerAccount(try!(Decodable::decode(decoder)))),
+            transfer_tags::REFRESH_BATCH_TAG =>
+                Ok(Transfer::RefreshBatch(try!(Decodable::decode(decoder)))),
             _ => Ok(Transfer::Unknown(tag)),
         }
     }
 }
 
+/// Packs the refreshes among `actions` into batches of at most `MAX_BATCH_SIZE` bytes.  A batch
+/// carries the accounts of all the groups made of the same nodes of our `close_group`, as it is
+/// sent to those nodes, and is addressed to the group of its first account.  The members holding
+/// the same accounts sort them alike, by name, type and contents, so they address the same batches
+/// for routing to accumulate them.  A refresh left on its own is sent as is, while the other
+/// actions are returned untouched ahead of the batches.
+pub fn batch(actions: Vec<::types::MethodCall>,
+             close_group: &Vec<::routing::NameType>)
+             -> Vec<::types::MethodCall> {
+    let mut batched = Vec::with_capacity(actions.len());
+    // key is the nodes the accounts are refreshed to, value the entries for them
+    let mut groups = ::std::collections::HashMap::<Vec<::routing::NameType>,
+                                                   Vec<BatchEntry>>::new();
+    for action in actions {
+        match action {
+            ::types::MethodCall::Refresh { type_tag, our_authority, payload } => {
+                let nodes = ::churn::closest_nodes(close_group, our_authority.get_location());
+                groups.entry(nodes).or_insert(Vec::new()).push(BatchEntry {
+                    type_tag: type_tag,
+                    our_authority: our_authority,
+                    payload: payload,
+                });
+            }
+            _ => batched.push(action),
+        }
+    }
+    let mut groups = groups.into_iter().map(|(_, entries)| entries).collect::<Vec<_>>();
+    for entries in groups.iter_mut() {
+        entries.sort_by(|lhs, rhs| {
+            (&lhs.our_authority.get_location().0[..], lhs.type_tag, &lhs.payload)
+                .cmp(&(&rhs.our_authority.get_location().0[..], rhs.type_tag, &rhs.payload))
+        });
+    }
+    groups.sort_by(|lhs, rhs| {
+        lhs[0].our_authority.get_location().0[..].cmp(&rhs[0].our_authority.get_location().0[..])
+    });
+
+    for entries in groups {
+        let mut batch = Vec::new();
+        let mut batch_size = 0;
+        for entry in entries {
+            let entry_size = ::routing::utils::encode(&entry)
+                                 .map(|encoded| encoded.len())
+                                 .unwrap_or(entry.payload.len());
+            if !batch.is_empty() && batch_size + entry_size > MAX_BATCH_SIZE {
+                batched.extend(refresh(::std::mem::replace(&mut batch, Vec::new())).into_iter());
+                batch_size = 0;
+            }
+            batch_size += entry_size;
+            batch.push(entry);
+        }
+        if !batch.is_empty() {
+            batched.extend(refresh(batch).into_iter());
+        }
+    }
+    batched
+}
+
+/// Regroups the entries of the batches received from the members of a group, giving the copies of
+/// each account, along with its group, as if they had been refreshed one by one.  Batches which
+/// fail to parse are dropped, as are the accounts we aren't among the closest nodes of our
+/// `close_group` to, and an account repeated within a batch only counts once towards the quorum.
+pub fn unbatch(payloads: Vec<Vec<u8>>,
+               close_group: &Vec<::routing::NameType>)
+               -> Vec<(u64, ::routing::Authority, Vec<Vec<u8>>)> {
+    let churn = ::churn::Churn::new(vec![], close_group.clone());
+    let mut refreshes: Vec<(u64, ::routing::Authority, Vec<Vec<u8>>)> = Vec::new();
+    for payload in payloads {
+        let entries = match Transfer::parse(&payload) {
+            Some(Transfer::RefreshBatch(entries)) => entries,
+            _ => continue,
+        };
+        let mut seen = Vec::new();
+        for entry in entries {
+            // batches don't nest
+            if entry.type_tag == transfer_tags::REFRESH_BATCH_TAG ||
+               !churn.keeps(entry.our_authority.get_location()) {
+                continue;
+            }
+            let key = (entry.type_tag, entry.our_authority.clone());
+            if seen.contains(&key) {
+                continue;
+            }
+            seen.push(key);
+            match refreshes.iter().position(|refresh| {
+                refresh.0 == entry.type_tag && refresh.1 == entry.our_authority
+            }) {
+                Some(index) => refreshes[index].2.push(entry.payload),
+                None => refreshes.push((entry.type_tag, entry.our_authority, vec![entry.payload])),
+            }
+        }
+    }
+    refreshes
+}
+
+// The refresh carrying `batch`, or its only entry refreshed on its own
+fn refresh(mut batch: Vec<BatchEntry>) -> Option<::types::MethodCall> {
+    if batch.len() == 1 {
+        let entry = batch.remove(0);
+        return Some(::types::MethodCall::Refresh {
+            type_tag: entry.type_tag,
+            our_authority: entry.our_authority,
+            payload: entry.payload,
+        });
+    }
+    let our_authority = match batch.first() {
+        Some(entry) => entry.our_authority.clone(),
+        None => return None,
+    };
+    match encode(transfer_tags::REFRESH_BATCH_TAG, &batch) {
+        Some(payload) => Some(::types::MethodCall::Refresh {
+            type_tag: transfer_tags::REFRESH_BATCH_TAG,
+            our_authority: our_authority,
+            payload: payload,
+        }),
+        None => {
+            error!("Failed to encode a refresh batch of {:?} entries", batch.len());
+            None
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn refresh(type_tag: u64,
+               our_authority: &::routing::Authority,
+               size: usize)
+               -> ::types::MethodCall {
+        ::types::MethodCall::Refresh {
+            type_tag: type_tag,
+            our_authority: our_authority.clone(),
+            payload: ::routing::types::generate_random_vec_u8(size),
+        }
+    }
+
+    fn group() -> ::routing::Authority {
+        ::routing::Authority::NaeManager(::utils::random_name())
+    }
+
+    fn payload(action: &::types::MethodCall) -> Vec<u8> {
+        match *action {
+            ::types::MethodCall::Refresh { ref payload, .. } => payload.clone(),
+            _ => panic!("Refresh type expected"),
+        }
+    }
+
+    // A close group of a single group, to which all accounts are refreshed
+    fn close_group() -> Vec<::routing::NameType> {
+        (0..::routing::types::GROUP_SIZE).map(|_| ::utils::random_name()).collect()
+    }
+
+    #[test]
+    fn batch_and_unbatch() {
+        let close_group = close_group();
+        let group = group();
+        let refreshes = vec![refresh(transfer_tags::DATA_MANAGER_ACCOUNT_TAG, &group, 100),
+                             refresh(transfer_tags::SD_MANAGER_ACCOUNT_TAG, &group, 100),
+                             refresh(transfer_tags::SD_MANAGER_SUBSCRIPTIONS_TAG, &group, 100)];
+        let get = ::types::MethodCall::Get {
+            location: ::routing::Authority::NaeManager(::utils::random_name()),
+            data_request: ::routing::data::DataRequest::ImmutableData(
+                ::utils::random_name(), ::routing::immutable_data::ImmutableDataType::Normal),
+        };
+        let mut actions = refreshes.clone();
+        actions.insert(1, get.clone());
+
+        // other actions are left as they are, the refreshes are packed into a single batch
+        let batched = batch(actions, &close_group);
+        assert_eq!(batched.len(), 2);
+        assert_eq!(batched[0], get);
+        let batch_payload = match batched[1] {
+            ::types::MethodCall::Refresh { type_tag, ref our_authority, ref payload } => {
+                assert_eq!(type_tag, transfer_tags::REFRESH_BATCH_TAG);
+                assert_eq!(*our_authority, group);
+                payload.clone()
+            }
+            _ => panic!("Refresh type expected"),
+        };
+
+        // which is split back into the copies of each account sent by the group
+        let quorum = ::types::default_quorum();
+        let unbatched = unbatch(vec![batch_payload.clone(); quorum], &close_group);
+        assert_eq!(unbatched.len(), refreshes.len());
+        for refresh in refreshes.iter() {
+            if let ::types::MethodCall::Refresh { type_tag, ref payload, .. } = *refresh {
+                assert!(unbatched.iter().any(|entry| {
+                    entry.0 == type_tag && entry.1 == group &&
+                    entry.2 == vec![payload.clone(); quorum]
+                }));
+            }
+        }
+
+        // garbage is dropped
+        assert!(unbatch(vec![::routing::types::generate_random_vec_u8(100)], &close_group)
+                    .is_empty());
+    }
+
+    #[test]
+    fn batch_per_group() {
+        // a node joining a group, after which many accounts are refreshed
+        let close_group = (0..(::routing::types::GROUP_SIZE + 1))
+                              .map(|_| ::utils::random_name())
+                              .collect::<Vec<_>>();
+        let mut refreshes = Vec::new();
+        for _ in 0..50 {
+            let group = group();
+            refreshes.push(refresh(transfer_tags::SD_MANAGER_ACCOUNT_TAG, &group, 100));
+            refreshes.push(refresh(transfer_tags::SD_MANAGER_SUBSCRIPTIONS_TAG, &group, 100));
+        }
+        let mut targets = Vec::new();
+        for refresh in refreshes.iter() {
+            if let ::types::MethodCall::Refresh { ref our_authority, .. } = *refresh {
+                let nodes = ::churn::closest_nodes(&close_group, our_authority.get_location());
+                if !targets.contains(&nodes) {
+                    targets.push(nodes);
+                }
+            }
+        }
+
+        // the accounts are packed into a batch per set of nodes they're sent to, rather than one
+        // per group, whatever the order they came in
+        let mut actions = refreshes.clone();
+        let batched = batch(actions.clone(), &close_group);
+        assert_eq!(batched.len(), targets.len());
+        assert!(batched.len() <= close_group.len());
+        assert!(batched.len() < refreshes.len() / 2);
+        assert!(batched.iter().all(|action| match *action {
+            ::types::MethodCall::Refresh { type_tag, .. } =>
+                type_tag == transfer_tags::REFRESH_BATCH_TAG,
+            _ => false,
+        }));
+        actions.reverse();
+        assert_eq!(batch(actions, &close_group), batched);
+
+        // each node merges the accounts it's among the closest nodes to, ignoring the others
+        let quorum = ::types::default_quorum();
+        let payloads = batched.iter().map(payload).collect::<Vec<_>>();
+        for index in 0..close_group.len() {
+            let mut node_close_group = close_group.clone();
+            node_close_group.swap(0, index);
+            let mut received = Vec::new();
+            for _ in 0..quorum {
+                received.extend(payloads.iter().cloned());
+            }
+            let unbatched = unbatch(received, &node_close_group);
+            let expected = refreshes.iter()
+                                    .filter_map(|refresh| match *refresh {
+                                        ::types::MethodCall::Refresh { type_tag,
+                                                                       ref our_authority,
+                                                                       ref payload } => {
+                                            let nodes = ::churn::closest_nodes(
+                                                &close_group, our_authority.get_location());
+                                            if nodes.contains(&close_group[index]) {
+                                                Some((type_tag, our_authority.clone(),
+                                                      vec![payload.clone(); quorum]))
+                                            } else {
+                                                None
+                                            }
+                                        }
+                                        _ => None,
+                                    })
+                                    .collect::<Vec<_>>();
+            assert_eq!(unbatched.len(), expected.len());
+            assert!(expected.iter().all(|refresh| unbatched.contains(refresh)));
+        }
+    }
+
+    #[test]
+    fn batch_size_cap() {
+        let close_group = close_group();
+        let group = group();
+        // a lone refresh is sent as is
+        let lone = refresh(transfer_tags::DATA_MANAGER_ACCOUNT_TAG, &group, 100);
+        assert_eq!(batch(vec![lone.clone()], &close_group), vec![lone]);
+
+        // refreshes which don't fit together aren't batched
+        let large = vec![refresh(transfer_tags::DATA_MANAGER_ACCOUNT_TAG, &group,
+                                 MAX_BATCH_SIZE / 2),
+                         refresh(transfer_tags::DATA_MANAGER_STATS_TAG, &group,
+                                 MAX_BATCH_SIZE / 2)];
+        let batched = batch(large.clone(), &close_group);
+        assert_eq!(batched.len(), 2);
+        assert!(batched.iter().all(|action| large.iter().any(|refresh| {
+            payload(refresh) == payload(action)
+        })));
+
+        // while small ones are, up to the cap
+        let small = [transfer_tags::DATA_MANAGER_ACCOUNT_TAG,
+                     transfer_tags::DATA_MANAGER_STATS_TAG,
+                     transfer_tags::SD_MANAGER_ACCOUNT_TAG,
+                     transfer_tags::SD_MANAGER_SUBSCRIPTIONS_TAG]
+                        .iter()
+                        .map(|type_tag| refresh(*type_tag, &group, MAX_BATCH_SIZE / 3))
+                        .collect::<Vec<_>>();
+        let batched = batch(small, &close_group);
+        assert_eq!(batched.len(), 2);
+        assert!(batched.iter().all(|action| payload(action).len() <= MAX_BATCH_SIZE));
+    }
+
+    // Reads `account` back from the current version and from versions 0 and 1, rejecting newer
+    // versions and other types
+    fn check_versions<T>(type_tag: u64, account: T)
+        where T: ::types::Refreshable + PartialEq + ::std::fmt::Debug {
+        let current = encode(type_tag, &account).unwrap();
+        assert_eq!(decode::<T>(type_tag, &current).as_ref(), Some(&account));
+
+        let bare = ::routing::utils::encode(&account).unwrap();
+        assert_eq!(decode::<T>(type_tag, &bare).as_ref(), Some(&account));
+        let version_1 = ::routing::utils::encode(&Envelope {
+            version: 1,
+            type_tag: type_tag,
+            payload: bare.clone(),
+        }).unwrap();
+        assert_eq!(decode::<T>(type_tag, &version_1).as_ref(), Some(&account));
+
+        let newer = ::routing::utils::encode(&Envelope {
+            version: FORMAT_VERSION + 1,
+            type_tag: type_tag,
+            payload: bare,
+        }).unwrap();
+        assert!(decode::<T>(type_tag, &newer).is_none());
+        assert!(decode::<T>(transfer_tags::REFRESH_BATCH_TAG, &current).is_none());
+    }
+
+    // The layouts of the accounts which changed since version 0, as sent by vaults of that version
+    #[derive(RustcEncodable)]
+    struct MaidAccountV0 {
+        name: ::routing::NameType,
+        value: MaidAccountValueV0,
+    }
+
+    #[derive(RustcEncodable)]
+    struct MaidAccountValueV0 {
+        data_stored: u64,
+        space_available: u64,
+    }
+
+    #[derive(RustcEncodable)]
+    struct PmidAccountV0 {
+        name: ::routing::NameType,
+        value: PmidAccountValueV0,
+    }
+
+    #[derive(RustcEncodable)]
+    struct PmidAccountValueV0 {
+        stored_total_size: u64,
+        lost_total_size: u64,
+        offered_space: u64,
+    }
+
+    // Reads `old`, as written by version 0, into `account` with its new fields defaulted, while
+    // the current version still reads `account` as is
+    fn check_version_0<T, U>(type_tag: u64, old: &U, account: T)
+        where T: ::types::Refreshable + PartialEq + ::std::fmt::Debug,
+              U: ::rustc_serialize::Encodable {
+        let bare = ::routing::utils::encode(old).unwrap();
+        assert_eq!(decode::<T>(type_tag, &bare).as_ref(), Some(&account));
+        let enveloped = ::routing::utils::encode(&Envelope {
+            version: 0,
+            type_tag: type_tag,
+            payload: bare,
+        }).unwrap();
+        assert_eq!(decode::<T>(type_tag, &enveloped).as_ref(), Some(&account));
+
+        let current = encode(type_tag, &account).unwrap();
+        assert_eq!(decode::<T>(type_tag, &current).as_ref(), Some(&account));
+    }
+
+    #[test]
+    fn versions() {
+        let account = ::data_manager::Account::new(::utils::random_name(),
+                                                   vec![::utils::random_name()]);
+        check_versions(transfer_tags::DATA_MANAGER_ACCOUNT_TAG, account.clone());
+        check_versions(transfer_tags::DATA_MANAGER_STATS_TAG,
+                       ::data_manager::Stats::new(::utils::random_name(), 7));
+        check_versions(transfer_tags::SD_MANAGER_SUBSCRIPTIONS_TAG,
+                       ::sd_manager::SubscriptionAccount::new(::utils::random_name(), vec![]));
+
+        // accounts whose layout changed read those of version 0 with the new fields defaulted
+        let name = ::utils::random_name();
+        let maid_account = MaidAccountV0 {
+            name: name,
+            value: MaidAccountValueV0 { data_stored: 1, space_available: 2 },
+        };
+        check_version_0(transfer_tags::MAID_MANAGER_ACCOUNT_TAG, &maid_account,
+                        ::maid_manager::Account::new(name,
+                            ::maid_manager::AccountValue::new(1, 2, 0)));
+        let pmid_account = PmidAccountV0 {
+            name: name,
+            value: PmidAccountValueV0 {
+                stored_total_size: 1,
+                lost_total_size: 2,
+                offered_space: 3,
+            },
+        };
+        check_version_0(transfer_tags::PMID_MANAGER_ACCOUNT_TAG, &pmid_account,
+                        ::pmid_manager::Account::new(name,
+                            ::pmid_manager::AccountValue::new(1, 2, 3)));
+        let keys = ::sodiumoxide::crypto::sign::gen_keypair();
+        let structured_data = ::routing::structured_data::StructuredData::new(0, name, 0,
+            vec![], vec![keys.0], vec![], Some(&keys.1)).ok().unwrap();
+        check_version_0(transfer_tags::SD_MANAGER_ACCOUNT_TAG, &structured_data,
+                        ::sd_manager::Account::new(&structured_data).unwrap());
+
+        // the payloads are self-describing
+        let current = encode(transfer_tags::DATA_MANAGER_ACCOUNT_TAG, &account).unwrap();
+        match Transfer::parse(&current) {
+            Some(Transfer::DataManagerAccount(parsed)) => assert_eq!(parsed, account),
+            _ => panic!("Unexpected"),
+        }
+
+        // and the tag-prefixed layout of version 0 is still parsed
+        let mut encoder = ::cbor::Encoder::from_memory();
+        encoder.encode(&[transfer_tags::DATA_MANAGER_ACCOUNT_TAG]).unwrap();
+        encoder.encode(&[account.clone()]).unwrap();
+        match Transfer::parse(&encoder.as_bytes().to_vec()) {
+            Some(Transfer::DataManagerAccount(parsed)) => assert_eq!(parsed, account),
+            _ => panic!("Unexpected"),
+        }
+
+        // unknown types are reported as such, newer versions of known ones aren't parsed
+        let unknown = ::routing::utils::encode(&Envelope {
+            version: FORMAT_VERSION + 1,
+            type_tag: transfer_tags::MAIDSAFE_TRANSFER_TAG,
+            payload: vec![],
+        }).unwrap();
+        match Transfer::parse(&unknown) {
+            Some(Transfer::Unknown(type_tag)) =>
+                assert_eq!(type_tag, transfer_tags::MAIDSAFE_TRANSFER_TAG),
+            _ => panic!("Unexpected"),
+        }
+    }
+}
+
 /*
 #[cfg(test)]
  mod test {
@@ -28,8 +28,11 @@ pub enum MethodCall {
         location: ::routing::authority::Authority,
         data_request: ::routing::data::DataRequest,
     },
-    // /// request to post
-    // Post { destination: ::routing::NameType, content: Data },
+    /// request to have `location` to handle post for the `content`
+    Post {
+        location: ::routing::authority::Authority,
+        content: ::routing::data::Data,
+    },
     // /// Request delete
     // Delete { name: ::routing::NameType, data : Data },
     /// request to refresh
@@ -42,6 +45,11 @@ pub enum MethodCall {
     Reply {
         data: ::routing::data::Data,
     },
+    /// response error indicating the data requested under `name` couldn't be retrieved
+    GetFailed {
+        name: ::routing::NameType,
+        reason: GetFailureReason,
+    },
     /// response error indicating failed in putting data
     FailedPut {
         location: ::routing::authority::Authority,
@@ -51,15 +59,112 @@ pub enum MethodCall {
     ClearSacrificial {
         location: ::routing::authority::Authority,
         name: ::routing::NameType,
-        size: u32,
+        size: u64,
     },
     /// response error indicating not enough allowance
     LowBalance{ location: ::routing::authority::Authority,
-                data: ::routing::data::Data, balance: u32},
+                data: ::routing::data::Data, balance: u64},
+    /// response error indicating the client has no account to put data with
+    NoAccount {
+        location: ::routing::authority::Authority,
+        data: ::routing::data::Data,
+    },
     /// response error indicating invalid request
     InvalidRequest {
         data: ::routing::data::Data,
     },
+    /// notification of a changed data sent to a subscriber
+    Notify {
+        location: ::routing::authority::Authority,
+        data: ::routing::data::Data,
+    },
+}
+
+/// Why a Get couldn't be served.
+#[derive(RustcEncodable, RustcDecodable, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GetFailureReason {
+    /// nothing is stored under the name requested
+    NoSuchData,
+    /// the data is known of, but none of its holders returned it
+    AllHoldersFailed,
+    /// the data stored can't be read back
+    Corrupt,
+    /// the requester isn't the client owning the account read
+    Unauthorised,
+    /// too many requests were already waiting for data
+    Busy,
+    /// the vault left the network before the data arrived
+    ShuttingDown,
+}
+
+/// Routing has no failure response to a Get, so the requester is sent a FailedRequestForData put
+/// response instead, carrying a GetFailure as the tagged payload of a PlainData.
+#[derive(RustcEncodable, RustcDecodable, Clone, PartialEq, Eq, Debug)]
+pub struct GetFailure {
+    pub data_request: ::routing::data::DataRequest,
+    pub reason: GetFailureReason,
+}
+
+impl PlainPayload for GetFailure {
+    fn type_tag() -> u64 {
+        payload_tags::GET_FAILURE_TAG
+    }
+}
+
+/// Tags of the payloads exchanged between personas as the value of a PlainData
+pub mod payload_tags {
+    pub const MAIDSAFE_PAYLOAD_TAG: u64 = 5483_000 + 300;
+
+    pub const WALLET_REQUEST_TAG: u64 = MAIDSAFE_PAYLOAD_TAG + 1;
+    pub const MPID_MESSAGE_TAG: u64 = MAIDSAFE_PAYLOAD_TAG + 2;
+    pub const PMID_MANAGER_REQUEST_TAG: u64 = MAIDSAFE_PAYLOAD_TAG + 3;
+    pub const STORAGE_CHALLENGE_TAG: u64 = MAIDSAFE_PAYLOAD_TAG + 4;
+    pub const HELD_CHUNK_TAG: u64 = MAIDSAFE_PAYLOAD_TAG + 5;
+    pub const SUBSCRIPTION_REQUEST_TAG: u64 = MAIDSAFE_PAYLOAD_TAG + 6;
+    pub const GET_FAILURE_TAG: u64 = MAIDSAFE_PAYLOAD_TAG + 7;
+}
+
+// The value of a PlainData carrying a payload, tagged so that a payload is never read as one of
+// another type
+#[derive(RustcEncodable, RustcDecodable)]
+struct TaggedPayload {
+    type_tag: u64,
+    payload: Vec<u8>,
+}
+
+/// Implemented by each type of payload exchanged between personas as the value of a PlainData
+/// named after what it is about.
+pub trait PlainPayload : ::rustc_serialize::Encodable + ::rustc_serialize::Decodable + Sized {
+    /// Unique among the payloads, see `payload_tags`
+    fn type_tag() -> u64;
+
+    fn to_plain_data(&self, name: ::routing::NameType) -> Option<::routing::data::Data> {
+        let tagged = TaggedPayload {
+            type_tag: Self::type_tag(),
+            payload: match ::routing::utils::encode(self) {
+                Ok(encoded) => encoded,
+                Err(_) => return None,
+            },
+        };
+        match ::routing::utils::encode(&tagged) {
+            Ok(encoded) => Some(::routing::data::Data::PlainData(
+                                    ::routing::plain_data::PlainData::new(name, encoded))),
+            Err(_) => None,
+        }
+    }
+
+    /// None unless `data` is a PlainData carrying a payload of this type
+    fn from_plain_data(data: &::routing::data::Data) -> Option<Self> {
+        let value = match *data {
+            ::routing::data::Data::PlainData(ref plain_data) => plain_data.value(),
+            _ => return None,
+        };
+        match ::routing::utils::decode::<TaggedPayload>(&value) {
+            Ok(ref tagged) if tagged.type_tag == Self::type_tag() =>
+                ::routing::utils::decode(&tagged.payload).ok(),
+            _ => None,
+        }
+    }
 }
 
 /// This trait is required for any type (normally an account) which is refreshed on a churn event.
@@ -69,6 +174,61 @@ pub trait Refreshable : ::rustc_serialize::Encodable + ::rustc_serialize::Decoda
         ::routing::utils::encode(&self).unwrap_or(vec![])
     }
 
-    /// Merge multiple refreshable objects into one
-    fn merge(from_group: ::routing::NameType, responses: Vec<Self>) -> Option<Self>;
+    /// Merge multiple refreshable objects into one, only keeping what at least `quorum` of the
+    /// responses agree on
+    fn merge(from_group: ::routing::NameType,
+             responses: Vec<Self>,
+             quorum: usize)
+             -> Result<Self, MergeError>;
+
+    /// Parses the contents as written by `version` of the transfer format.  By default every known
+    /// version reads alike, which only holds for accounts whose layout hasn't changed since
+    /// version 0; those which changed override this to keep reading the accounts sent by older
+    /// vaults.
+    fn decode_version(version: u64, contents: &Vec<u8>) -> Option<Self> {
+        match version {
+            0 | 1 | ::transfer_parser::FORMAT_VERSION => ::routing::utils::decode(contents).ok(),
+            _ => None,
+        }
+    }
+}
+
+/// Reason for rejecting the refresh responses received for a group.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MergeError {
+    /// fewer responses, or entries within them, agree than the quorum requires
+    InsufficientQuorum,
+    /// none of the responses are about the group being refreshed
+    NameMismatch,
+    /// none of the responses could be parsed
+    DecodeFailure,
+}
+
+/// Number of agreeing responses required to accept a refresh, unless configured otherwise: a
+/// majority of the close group.
+pub fn default_quorum() -> usize {
+    (::routing::types::GROUP_SIZE + 1) / 2
+}
+
+/// Keeps the responses about `from_group`, provided at least `quorum` of them are left.
+pub fn responses_for<T, F>(from_group: &::routing::NameType,
+                           responses: Vec<T>,
+                           quorum: usize,
+                           name: F)
+                           -> Result<Vec<T>, MergeError>
+    where F: Fn(&T) -> &::routing::NameType
+{
+    if responses.is_empty() {
+        return Err(MergeError::InsufficientQuorum);
+    }
+    let matching = responses.into_iter()
+                            .filter(|response| name(response) == from_group)
+                            .collect::<Vec<_>>();
+    if matching.is_empty() {
+        return Err(MergeError::NameMismatch);
+    }
+    if matching.len() < quorum {
+        return Err(MergeError::InsufficientQuorum);
+    }
+    Ok(matching)
 }
@@ -34,6 +34,21 @@ pub fn median(mut values: Vec<u64>) -> u64 {
     }
 }
 
+/// Returns `value`, or `u32::MAX` if it doesn't fit, for the sizes and balances routing carries as
+/// `u32`.
+pub fn saturate_u32(value: u64) -> u32 {
+    if value > ::std::u32::MAX as u64 {
+        ::std::u32::MAX
+    } else {
+        value as u32
+    }
+}
+
+/// Returns the name of a client's account, which is the hash of the client's public signing key.
+pub fn client_name(public_key: &::sodiumoxide::crypto::sign::PublicKey) -> ::routing::NameType {
+    ::routing::NameType::new(::sodiumoxide::crypto::hash::sha512::hash(&public_key.0).0)
+}
+
 #[cfg(test)]
 pub fn random_name() -> ::routing::NameType {
     // TODO - once Routing provides either a compile-time value for `NameType`'s length or exposes
@@ -58,4 +73,11 @@ mod test {
         assert_eq!(5, median(vec![20, 1, 0, 10]));
         assert_eq!(6, median(vec![20, 1, 0, 11]));
     }
+
+    #[test]
+    fn saturate() {
+        assert_eq!(saturate_u32(7), 7);
+        assert_eq!(saturate_u32(::std::u32::MAX as u64), ::std::u32::MAX);
+        assert_eq!(saturate_u32(::std::u64::MAX), ::std::u32::MAX);
+    }
 }
@@ -15,6 +15,8 @@
 // Please review the Licences for the specific language governing permissions and limitations
 // relating to use of the SAFE Network Software.
 
+use types::PlainPayload;
+
 #[cfg(not(feature = "use-mock-routing"))]
 type Routing = ::routing::routing::Routing;
 #[cfg(not(feature = "use-mock-routing"))]
@@ -29,71 +31,367 @@ fn get_new_routing(event_sender: ::std::sync::mpsc::Sender<(::routing::event::Ev
     ::non_networking_test_framework::MockRouting::new(event_sender)
 }
 
-fn merge<T>(from_group: ::routing::NameType, payloads: Vec<Vec<u8>>) -> Option<T>
+// Each payload is decoded once, dropping those which fail to, and the accounts merged as they are
+fn merge<T>(type_tag: u64,
+            from_group: ::routing::NameType,
+            payloads: Vec<Vec<u8>>,
+            quorum: usize)
+            -> Result<T, ::types::MergeError>
     where T: for<'a> ::types::Refreshable + 'static {
-    let mut transfer_entries = Vec::<T>::new();
-    for it in payloads.iter() {
-        let mut decoder = ::cbor::Decoder::from_bytes(&it[..]);
-        if let Some(parsed_entry) = decoder.decode().next().and_then(|result| result.ok()) {
-            transfer_entries.push(parsed_entry);
-        }
-    }
-    T::merge(from_group, transfer_entries).and_then(|result| {
-        let mut decoder = ::cbor::Decoder::from_bytes(&result.serialised_contents()[..]);
-        if let Some(parsed_entry) = decoder.decode().next().and_then(|result| result.ok()) {
-            let parsed: T = parsed_entry;
-            Some(parsed)
-        } else {
-            None
+    let transfer_entries = payloads.iter()
+                                   .filter_map(|it| ::transfer_parser::decode::<T>(type_tag, it))
+                                   .collect::<Vec<_>>();
+    if transfer_entries.is_empty() && !payloads.is_empty() {
+        return Err(::types::MergeError::DecodeFailure);
+    }
+    T::merge(from_group, transfer_entries, quorum)
+}
+
+// seconds between two ticks
+static TICK_INTERVAL: i64 = 1;
+// seconds waited before the first attempt to reconnect, doubled after each failed attempt
+static INITIAL_RECONNECT_DELAY: i64 = 1;
+static MAX_RECONNECT_DELAY: i64 = 64;
+// times a request routing failed to deliver is sent again, per data name
+static MAX_REQUEST_RETRIES: u32 = 3;
+// seconds between two reads of the config file, for changes to the offered space or the farmer
+// wallet to be advertised
+static CONFIG_RELOAD_INTERVAL: i64 = 60;
+
+// Backoff state while routing is disconnected, the personas are paused meanwhile
+struct Reconnection {
+    attempts: u32,
+    next_attempt: ::time::SteadyTime,
+}
+
+impl Reconnection {
+    fn new() -> Reconnection {
+        Reconnection {
+            attempts: 0,
+            next_attempt: ::time::SteadyTime::now() +
+                          ::time::Duration::seconds(INITIAL_RECONNECT_DELAY),
+        }
+    }
+
+    fn attempted(&mut self) {
+        self.attempts += 1;
+        let delay = ::std::cmp::min(INITIAL_RECONNECT_DELAY << ::std::cmp::min(self.attempts, 16),
+                                    MAX_RECONNECT_DELAY);
+        self.next_attempt = ::time::SteadyTime::now() + ::time::Duration::seconds(delay);
+    }
+}
+
+// What the event loop is woken up by
+enum Wakeup {
+    // an event from the routing instance of the given generation
+    Routing(u32, ::routing::event::Event),
+    // a tick or a deadline may be due
+    Timer,
+    // the vault was asked to stop
+    Stop,
+}
+
+// Each routing instance sends its events on a channel of its own, forwarded to the event loop along
+// with the generation of the instance so that those of a replaced instance are told apart
+fn start_routing(wakeup_sender: &::std::sync::mpsc::Sender<Wakeup>, generation: u32) -> Routing {
+    let (event_sender, event_receiver) = ::std::sync::mpsc::channel();
+    let wakeup_sender = wakeup_sender.clone();
+    let _ = ::std::thread::spawn(move || {
+        for event in event_receiver.iter() {
+            if wakeup_sender.send(Wakeup::Routing(generation, event)).is_err() {
+                break;
+            }
+        }
+    });
+    get_new_routing(event_sender)
+}
+
+// Wakes the event loop up once `delay` has passed
+fn wake_after(wakeup_sender: &::std::sync::mpsc::Sender<Wakeup>, delay: ::time::Duration) {
+    let wakeup_sender = wakeup_sender.clone();
+    let delay_ms = ::std::cmp::max(0, delay.num_milliseconds()) as u32;
+    let _ = ::std::thread::spawn(move || {
+        ::std::thread::sleep_ms(delay_ms);
+        let _ = wakeup_sender.send(Wakeup::Timer);
+    });
+}
+
+// a churn is handled at the latest this many settle windows after the first one coalesced, even
+// if the close group keeps changing
+static MAX_CHURN_SETTLE_WINDOWS: i32 = 10;
+
+type QueuedRequest = (::routing::ExternalRequest, ::routing::authority::Authority,
+                      ::routing::authority::Authority, Option<::routing::SignedToken>);
+
+// Churns coalesced until the close group settles, when the accounts are refreshed once for the
+// latest close group.  Requests for the groups affected are held back for another settle window,
+// giving the refreshes of the rest of the group time to arrive and be merged.  No more requests
+// are held back than the vault lets wait for data; the rest are failed as `Busy`.
+struct ChurnSettle {
+    // the close group before the first of the churns coalesced
+    old_close_group: Vec<::routing::NameType>,
+    // from the old close group to the latest one
+    churn: ::churn::Churn,
+    refreshed: bool,
+    first_churn: ::time::SteadyTime,
+    settles_at: ::time::SteadyTime,
+    queued_requests: Vec<QueuedRequest>,
+}
+
+impl ChurnSettle {
+    fn new(old_close_group: Vec<::routing::NameType>) -> ChurnSettle {
+        ChurnSettle {
+            old_close_group: old_close_group.clone(),
+            churn: ::churn::Churn::new(old_close_group.clone(), old_close_group),
+            refreshed: false,
+            first_churn: ::time::SteadyTime::now(),
+            settles_at: ::time::SteadyTime::now(),
+            queued_requests: Vec::new(),
+        }
+    }
+
+    fn churned(&mut self, close_group: Vec<::routing::NameType>, window: ::time::Duration) {
+        self.churn = ::churn::Churn::new(self.old_close_group.clone(), close_group);
+        self.refreshed = false;
+        self.settles_at = ::time::SteadyTime::now() + window;
+        let latest = self.first_churn + window * MAX_CHURN_SETTLE_WINDOWS;
+        if latest < self.settles_at {
+            self.settles_at = latest;
+        }
+    }
+
+    fn affects(&self, name: &::routing::NameType) -> bool {
+        self.churn.affects(name)
+    }
+}
+
+// A Get waiting for the data to be fetched, answered with a failure if it isn't in time
+#[derive(Clone)]
+struct CachedRequest {
+    // the authority the request was received as
+    our_authority: ::routing::authority::Authority,
+    requester: ::routing::authority::Authority,
+    data_request: ::routing::data::DataRequest,
+    response_token: Option<::routing::SignedToken>,
+    expires_at: ::time::SteadyTime,
+}
+
+// The name of the data a request is about
+fn data_request_name(data_request: &::routing::data::DataRequest) -> ::routing::NameType {
+    match *data_request {
+        ::routing::data::DataRequest::StructuredData(name, _) => name,
+        ::routing::data::DataRequest::ImmutableData(name, _) => name,
+        ::routing::data::DataRequest::PlainData(name) => name,
+    }
+}
+
+// Why a client's request was rejected as not coming from the client it claims to be
+#[derive(Debug, PartialEq, Eq)]
+enum ClientAuthError {
+    // the account billed isn't the one for the client's key
+    NameMismatch,
+    Unsigned,
+    InvalidSignature,
+    // the token was signed for another request than the one it came with
+    RequestMismatch,
+}
+
+// What a client signs: the data of a Put, Post or Delete, or the request of a Get
+fn serialise_request(request: &::routing::ExternalRequest) -> Option<Vec<u8>> {
+    match *request {
+        ::routing::ExternalRequest::Get(ref data_request, _) =>
+            ::routing::utils::encode(data_request).ok(),
+        ::routing::ExternalRequest::Put(ref data) |
+        ::routing::ExternalRequest::Post(ref data) |
+        ::routing::ExternalRequest::Delete(ref data) => ::routing::utils::encode(data).ok(),
+    }
+}
+
+// A client may only put to or post on its own account, whose name is derived from its key, and
+// has to sign the request with that key.  Requests from other authorities aren't checked.
+fn authenticate_client(request: &::routing::ExternalRequest,
+                       our_authority: &::routing::authority::Authority,
+                       from_authority: &::routing::authority::Authority,
+                       response_token: &Option<::routing::SignedToken>)
+                       -> Result<(), ClientAuthError> {
+    let client_key = match *from_authority {
+        ::routing::authority::Authority::Client(_, ref client_key) => client_key,
+        _ => return Ok(()),
+    };
+    if let ::routing::authority::Authority::ClientManager(ref account) = *our_authority {
+        if *account != ::utils::client_name(client_key) {
+            return Err(ClientAuthError::NameMismatch);
+        }
+    }
+    match *response_token {
+        Some(ref signed_token) => {
+            if !::sodiumoxide::crypto::sign::verify_detached(&signed_token.signature,
+                                                             &signed_token.serialised_request,
+                                                             client_key) {
+                return Err(ClientAuthError::InvalidSignature);
+            }
+            // a token signed for one request mustn't authorise another
+            if serialise_request(request).as_ref() != Some(&signed_token.serialised_request) {
+                return Err(ClientAuthError::RequestMismatch);
+            }
+            Ok(())
         }
-    })
+        None => Err(ClientAuthError::Unsigned),
+    }
 }
 
 /// Main struct to hold all personas and Routing instance
 pub struct Vault {
     data_manager: ::data_manager::DataManager,
     maid_manager: ::maid_manager::MaidManager,
+    mpid_manager: ::mpid_manager::MpidManager,
     pmid_manager: ::pmid_manager::PmidManager,
     pmid_node: ::pmid_node::PmidNode,
     sd_manager: ::sd_manager::StructuredDataManager,
     nodes_in_table: Vec<::routing::NameType>,
-    #[allow(dead_code)]
+    // ImmutableData seen in responses, served to later Gets without asking the holders
     data_cache: ::lru_time_cache::LruCache<::routing::NameType, ::routing::data::Data>,
-    request_cache: ::lru_time_cache::LruCache<::routing::NameType,
-                             Vec<(::routing::authority::Authority, ::routing::data::DataRequest,
- Option<::routing::SignedToken>)>>,
-    receiver: ::std::sync::mpsc::Receiver<::routing::event::Event>,
+    cache_hits: u64,
+    cache_misses: u64,
+    // key is the name of the data, value the requests waiting for it
+    request_cache: ::lru_time_cache::LruCache<::routing::NameType, Vec<CachedRequest>>,
+    // number of requests in the request_cache, bounded along with those for a single name
+    cached_requests: usize,
+    max_cached_requests: usize,
+    max_requests_per_name: usize,
+    request_timeout: ::time::Duration,
+    wakeups: ::std::sync::mpsc::Receiver<Wakeup>,
+    wakeup_sender: ::std::sync::mpsc::Sender<Wakeup>,
     #[allow(dead_code)]
     routing: Routing,
+    // incremented each time routing is replaced to reconnect
+    routing_generation: u32,
+    reconnection: Option<Reconnection>,
+    // key is the name of the data, value is the times a request about it was retried
+    request_retries: ::lru_time_cache::LruCache<::routing::NameType, u32>,
+    failed_responses: u64,
+    challenge_interval: ::time::Duration,
+    // responses of the close group which have to agree before a refresh is accepted
+    refresh_quorum: usize,
+    rejected_refreshes: u64,
+    // key is the name derived from a client's key, value is the times a request signed with it
+    // failed authentication
+    client_abuse: ::lru_time_cache::LruCache<::routing::NameType, u64>,
+    // set once the vault has been asked to stop, e.g. by SIGTERM or Ctrl-C
+    stop_requested: ::std::sync::Arc<::std::sync::atomic::AtomicBool>,
+    shutdown_deadline: ::time::Duration,
+    churn_settle_window: ::time::Duration,
+    churn_settle: Option<ChurnSettle>,
+    // advertised to our NodeManagers whenever we learn a new name, and again whenever they change
+    // in the config file
+    max_disk_space: u64,
+    farmer_wallet: Option<::routing::NameType>,
+    config_loaded_at: ::time::SteadyTime,
 }
 
 impl Vault {
     pub fn run() {
-        Vault::new().do_run()
+        // A signal is only delivered to the thread waiting for it if all the others block it, so
+        // this has to happen before routing spawns its threads
+        let signals = ::chan_signal::notify(&[::chan_signal::Signal::INT,
+                                              ::chan_signal::Signal::TERM]);
+        let mut vault = Vault::new();
+        let stop_requested = vault.stop_requested.clone();
+        let wakeup_sender = vault.wakeup_sender.clone();
+        let _ = ::std::thread::spawn(move || {
+            if let Some(signal) = signals.recv() {
+                info!("Vault received {:?}, shutting down", signal);
+                stop_requested.store(true, ::std::sync::atomic::Ordering::SeqCst);
+                let _ = wakeup_sender.send(Wakeup::Stop);
+            }
+        });
+        vault.do_run()
     }
 
     fn new() -> Vault {
         ::sodiumoxide::init();
-        let (sender, receiver) = ::std::sync::mpsc::channel();
+        let config = ::config::Config::load();
+        let (wakeup_sender, wakeups) = ::std::sync::mpsc::channel();
         Vault {
             data_manager: ::data_manager::DataManager::new(),
             maid_manager: ::maid_manager::MaidManager::new(),
+            mpid_manager: ::mpid_manager::MpidManager::new(&config),
             pmid_manager: ::pmid_manager::PmidManager::new(),
-            pmid_node: ::pmid_node::PmidNode::new(),
+            pmid_node: ::pmid_node::PmidNode::new(&config),
             sd_manager: ::sd_manager::StructuredDataManager::new(),
             nodes_in_table: Vec::new(),
             data_cache: ::lru_time_cache::LruCache::with_expiry_duration_and_capacity(
-                            ::time::Duration::minutes(10), 100),
+                            ::time::Duration::seconds(config.data_cache_ttl as i64),
+                            config.data_cache_capacity),
+            cache_hits: 0,
+            cache_misses: 0,
             request_cache: ::lru_time_cache::LruCache::with_expiry_duration_and_capacity(
                                ::time::Duration::minutes(5), 1000),
-            receiver: receiver,
-            routing: get_new_routing(sender),
+            cached_requests: 0,
+            max_cached_requests: config.max_cached_requests,
+            max_requests_per_name: config.max_requests_per_name,
+            request_timeout: ::time::Duration::seconds(config.request_timeout as i64),
+            routing: start_routing(&wakeup_sender, 0),
+            routing_generation: 0,
+            wakeups: wakeups,
+            wakeup_sender: wakeup_sender,
+            reconnection: None,
+            request_retries: ::lru_time_cache::LruCache::with_expiry_duration_and_capacity(
+                                 ::time::Duration::minutes(5), 1000),
+            failed_responses: 0,
+            challenge_interval:
+                ::time::Duration::seconds(config.storage_challenge_interval as i64),
+            refresh_quorum: ::std::cmp::max(1, ::std::cmp::min(config.refresh_quorum,
+                                                               ::routing::types::GROUP_SIZE)),
+            rejected_refreshes: 0,
+            client_abuse: ::lru_time_cache::LruCache::with_expiry_duration_and_capacity(
+                              ::time::Duration::hours(1), 1000),
+            stop_requested: ::std::sync::Arc::new(::std::sync::atomic::AtomicBool::new(false)),
+            shutdown_deadline: ::time::Duration::seconds(config.shutdown_deadline as i64),
+            churn_settle_window:
+                ::time::Duration::milliseconds(config.churn_settle_window as i64),
+            churn_settle: None,
+            max_disk_space: config.max_disk_space,
+            farmer_wallet: config.farmer_wallet_name(),
+            config_loaded_at: ::time::SteadyTime::now(),
         }
     }
 
+    // Blocks until routing sends an event or a timer goes off.  Ticks are due every TICK_INTERVAL
+    // and the deadlines of a settling churn wake the loop up too.
     fn do_run(&mut self) {
         use routing::event::Event;
-        while let Ok(event) = self.receiver.recv() {
+        let ticker = self.wakeup_sender.clone();
+        let _ = ::std::thread::spawn(move || {
+            loop {
+                ::std::thread::sleep_ms(TICK_INTERVAL as u32 * 1000);
+                if ticker.send(Wakeup::Timer).is_err() {
+                    break;
+                }
+            }
+        });
+        let mut last_tick = ::time::SteadyTime::now();
+        loop {
+            if self.stop_requested.load(::std::sync::atomic::Ordering::SeqCst) {
+                self.shutdown();
+                break;
+            }
+            if last_tick + ::time::Duration::seconds(TICK_INTERVAL) <= ::time::SteadyTime::now() {
+                last_tick = ::time::SteadyTime::now();
+                self.on_tick();
+            }
+            self.settle_churn();
+            let event = match self.wakeups.recv() {
+                Ok(Wakeup::Routing(generation, event)) => {
+                    if generation != self.routing_generation {
+                        debug!("Vault dropping {:?} from a replaced routing instance", event);
+                        continue;
+                    }
+                    event
+                }
+                Ok(Wakeup::Timer) | Ok(Wakeup::Stop) => continue,
+                Err(_) => break,
+            };
             info!("Vault received an event from routing : {:?}", event);
             match event {
                 Event::Request{ request, our_authority, from_authority, response_token } =>
@@ -115,11 +413,185 @@ impl Vault {
         }
     }
 
+    // Leaves the network cleanly: the requesters still waiting for data or for a churn to settle
+    // are answered, the state of the personas is flushed and routing is told to stop.  Any request
+    // arriving meanwhile is dropped, and we give up waiting for routing once the deadline has
+    // passed.
+    fn shutdown(&mut self) {
+        use routing::event::Event;
+        let deadline = ::time::SteadyTime::now() + self.shutdown_deadline;
+        self.fail_cached_requests();
+        self.fail_queued_requests();
+        self.flush();
+        self.routing.stop();
+        wake_after(&self.wakeup_sender, self.shutdown_deadline);
+        while ::time::SteadyTime::now() < deadline {
+            match self.wakeups.recv() {
+                Ok(Wakeup::Routing(generation, Event::Terminated))
+                        if generation == self.routing_generation => {
+                    info!("Vault left the network");
+                    return;
+                }
+                Ok(Wakeup::Routing(_, event)) =>
+                    debug!("Vault shutting down, dropping event {:?}", event),
+                Ok(Wakeup::Timer) | Ok(Wakeup::Stop) => {}
+                Err(_) => return,
+            }
+        }
+        warn!("Vault exiting before routing left the network, deadline of {:?} passed",
+              self.shutdown_deadline);
+    }
+
+    fn fail_cached_requests(&mut self) {
+        for (name, requests) in self.request_cache.retrieve_all() {
+            let _ = self.request_cache.remove(&name);
+            for request in requests {
+                self.get_failed(request.our_authority, request.requester, request.data_request,
+                                ::types::GetFailureReason::ShuttingDown, request.response_token);
+            }
+        }
+        self.cached_requests = 0;
+    }
+
+    // The requests held back for a churn to settle won't be handled anymore
+    fn fail_queued_requests(&mut self) {
+        let queued_requests = match self.churn_settle.take() {
+            Some(churn_settle) => churn_settle.queued_requests,
+            None => return,
+        };
+        for (request, our_authority, from_authority, response_token) in queued_requests {
+            self.fail_request(request, our_authority, from_authority,
+                              ::types::GetFailureReason::ShuttingDown, response_token);
+        }
+    }
+
+    // Requests nobody answered in time are failed, rather than left waiting for the cache expiry
+    fn expire_cached_requests(&mut self) {
+        let now = ::time::SteadyTime::now();
+        let mut cached_requests = 0;
+        for (name, requests) in self.request_cache.retrieve_all() {
+            let (expired, waiting): (Vec<_>, Vec<_>) =
+                requests.into_iter().partition(|request| request.expires_at <= now);
+            cached_requests += waiting.len();
+            if expired.is_empty() {
+                continue;
+            }
+            let _ = self.request_cache.remove(&name);
+            if !waiting.is_empty() {
+                self.request_cache.add(name, waiting);
+            }
+            for request in expired {
+                self.get_failed(request.our_authority, request.requester, request.data_request,
+                                ::types::GetFailureReason::AllHoldersFailed,
+                                request.response_token);
+            }
+        }
+        self.cached_requests = cached_requests;
+    }
+
+    // Requests given up on are answered rather than left to time out: a Get with the reason, any
+    // other request with its data so that it can be sent again
+    fn fail_request(&mut self,
+                    request: ::routing::ExternalRequest,
+                    our_authority: ::routing::authority::Authority,
+                    from_authority: ::routing::authority::Authority,
+                    reason: ::types::GetFailureReason,
+                    response_token: Option<::routing::SignedToken>) {
+        match request {
+            ::routing::ExternalRequest::Get(data_request, _) =>
+                self.get_failed(our_authority, from_authority, data_request, reason,
+                                response_token),
+            ::routing::ExternalRequest::Put(data) |
+            ::routing::ExternalRequest::Post(data) |
+            ::routing::ExternalRequest::Delete(data) => {
+                debug!("as {:?} failing request for {:?} from {:?}: {:?}", our_authority,
+                       data.name(), from_authority, reason);
+                self.routing.put_response(our_authority, from_authority,
+                    ::routing::error::ResponseError::FailedRequestForData(data), response_token)
+            }
+        }
+    }
+
+    // Routing has no failure response to a Get, so the requester is sent a failed put response
+    // carrying the reason instead
+    fn get_failed(&mut self,
+                  our_authority: ::routing::authority::Authority,
+                  requester: ::routing::authority::Authority,
+                  data_request: ::routing::data::DataRequest,
+                  reason: ::types::GetFailureReason,
+                  response_token: Option<::routing::SignedToken>) {
+        debug!("as {:?} failing request {:?} from {:?}: {:?}", our_authority, data_request,
+               requester, reason);
+        let name = data_request_name(&data_request);
+        let failure = ::types::GetFailure { data_request: data_request, reason: reason };
+        let response_error = match failure.to_plain_data(name) {
+            Some(data) => ::routing::error::ResponseError::FailedRequestForData(data),
+            None => ::routing::error::ResponseError::Abort,
+        };
+        self.routing.put_response(our_authority, requester, response_error, response_token);
+    }
+
+    // The chunk stores are flushed, though they live in temporary directories which are removed
+    // on exit.  The accounts only live in memory, so they are handed over to the rest of the close
+    // group the same way as on a churn, unless we're already disconnected.
+    fn flush(&mut self) {
+        if let Err(error) = self.pmid_node.flush() {
+            error!("Failed to flush the PmidNode's chunk store: {:?}", error);
+        }
+        if let Err(error) = self.sd_manager.flush() {
+            error!("Failed to flush the StructuredDataManager's chunk store: {:?}", error);
+        }
+        if self.reconnection.is_some() || self.nodes_in_table.is_empty() {
+            return;
+        }
+        let refresh_calls = ::transfer_parser::batch(self.retrieve_all_and_reset(),
+                                                     &self.nodes_in_table);
+        self.send(::routing::authority::Authority::NaeManager(::routing::NameType::new([0u8; 64])),
+                  refresh_calls, None, None, None);
+    }
+
     fn on_request(&mut self,
                   request: ::routing::ExternalRequest,
                   our_authority: ::routing::authority::Authority,
                   from_authority: ::routing::authority::Authority,
                   response_token: Option<::routing::SignedToken>) {
+        if self.reconnection.is_some() {
+            debug!("Vault paused while reconnecting, dropping request {:?}", request);
+            return;
+        }
+        let queued = match self.churn_settle {
+            Some(ref churn_settle) if churn_settle.affects(our_authority.get_location()) =>
+                Some(churn_settle.queued_requests.len()),
+            _ => None,
+        };
+        if let Some(queued) = queued {
+            if queued >= self.max_cached_requests {
+                warn!("Vault has too many requests queued for the churn to settle, failing {:?}",
+                      request);
+                return self.fail_request(request, our_authority, from_authority,
+                                         ::types::GetFailureReason::Busy, response_token);
+            }
+            debug!("Vault waiting for the churn to settle, queueing request {:?}", request);
+            if let Some(ref mut churn_settle) = self.churn_settle {
+                churn_settle.queued_requests.push((request, our_authority, from_authority,
+                                                   response_token));
+            }
+            return;
+        }
+        // Gets are only authenticated when reading an account, e.g. a client's inbox
+        let authenticated = match (&request, &our_authority) {
+            (&::routing::ExternalRequest::Get(..),
+             &::routing::authority::Authority::ClientManager(_)) => true,
+            (&::routing::ExternalRequest::Get(..), _) => false,
+            _ => true,
+        };
+        if authenticated {
+            if let Err(error) = authenticate_client(&request, &our_authority, &from_authority,
+                                                    &response_token) {
+                return self.reject_client_request(request, our_authority, from_authority, error,
+                                                  response_token);
+            }
+        }
         match request {
             ::routing::ExternalRequest::Get(data_request, _) => {
                 self.handle_get(our_authority, from_authority, data_request, response_token);
@@ -131,8 +603,43 @@ impl Vault {
             ::routing::ExternalRequest::Post(data) => {
                 self.handle_post(our_authority, from_authority, data, response_token);
             }
-            ::routing::ExternalRequest::Delete(/*data*/_) => {
-                unimplemented!();
+            ::routing::ExternalRequest::Delete(data) => {
+                debug!("Vault doesn't support deletes, rejecting {:?}", data);
+                self.routing.put_response(our_authority, from_authority,
+                                          ::routing::error::ResponseError::InvalidRequest(data),
+                                          response_token);
+            }
+        }
+    }
+
+    // The client is told its request is invalid, or its Get failed, and the abuse is counted
+    // against its key
+    fn reject_client_request(&mut self,
+                             request: ::routing::ExternalRequest,
+                             our_authority: ::routing::authority::Authority,
+                             from_authority: ::routing::authority::Authority,
+                             error: ClientAuthError,
+                             response_token: Option<::routing::SignedToken>) {
+        if let ::routing::authority::Authority::Client(_, ref client_key) = from_authority {
+            let client = ::utils::client_name(client_key);
+            let abuse = match self.client_abuse.get(&client) {
+                Some(abuse) => *abuse + 1,
+                None => 1,
+            };
+            self.client_abuse.add(client, abuse);
+            warn!("as {:?} rejecting {:?} from client {:?}: {:?}, {} rejected so far",
+                  our_authority, request, client, error, abuse);
+        }
+        match request {
+            ::routing::ExternalRequest::Get(data_request, _) =>
+                self.get_failed(our_authority, from_authority, data_request,
+                                ::types::GetFailureReason::Unauthorised, response_token),
+            ::routing::ExternalRequest::Put(data) |
+            ::routing::ExternalRequest::Post(data) |
+            ::routing::ExternalRequest::Delete(data) => {
+                self.routing.put_response(our_authority, from_authority,
+                                          ::routing::error::ResponseError::InvalidRequest(data),
+                                          response_token)
             }
         }
     }
@@ -141,6 +648,10 @@ impl Vault {
                    response: ::routing::ExternalResponse,
                    our_authority: ::routing::authority::Authority,
                    from_authority: ::routing::authority::Authority) {
+        if self.reconnection.is_some() {
+            debug!("Vault paused while reconnecting, dropping response {:?}", response);
+            return;
+        }
         match response {
             ::routing::ExternalResponse::Get(data, _, response_token) => {
                 self.handle_get_response(our_authority, from_authority, data, response_token);
@@ -169,39 +680,223 @@ impl Vault {
         if close_group.len() > self.nodes_in_table.len() {
             info!("vault added connected node");
         }
-        let refresh_calls = self.handle_churn(close_group);
+        if self.churn_settle_window == ::time::Duration::zero() {
+            return self.refresh_on_churn(close_group);
+        }
+        if self.churn_settle.is_none() {
+            self.churn_settle = Some(ChurnSettle::new(self.nodes_in_table.clone()));
+        }
+        if let Some(ref mut churn_settle) = self.churn_settle {
+            churn_settle.churned(close_group, self.churn_settle_window);
+            wake_after(&self.wakeup_sender, churn_settle.settles_at - ::time::SteadyTime::now());
+        }
+    }
+
+    // Refreshes the accounts for the settled close group, and once the rest of the group had as
+    // long to refresh theirs, handles the requests held back meanwhile
+    fn settle_churn(&mut self) {
+        let mut churn_settle = match self.churn_settle.take() {
+            Some(churn_settle) => churn_settle,
+            None => return,
+        };
+        if ::time::SteadyTime::now() < churn_settle.settles_at {
+            self.churn_settle = Some(churn_settle);
+            return;
+        }
+        if !churn_settle.refreshed {
+            let close_group = churn_settle.churn.new_close_group().clone();
+            churn_settle.refreshed = true;
+            churn_settle.settles_at = ::time::SteadyTime::now() + self.churn_settle_window;
+            wake_after(&self.wakeup_sender, self.churn_settle_window);
+            self.churn_settle = Some(churn_settle);
+            return self.refresh_on_churn(close_group);
+        }
+        info!("Vault settled after churn, handling {:?} queued requests",
+              churn_settle.queued_requests.len());
+        for (request, our_authority, from_authority, response_token) in
+                churn_settle.queued_requests {
+            self.on_request(request, our_authority, from_authority, response_token);
+        }
+    }
+
+    fn refresh_on_churn(&mut self, close_group: Vec<::routing::NameType>) {
+        // TODO: as Vault doesn't have access to what ID it is, we have to use the first one in the
+        //       close group as its ID
+        let our_name = close_group.first().cloned();
+        let refresh_calls = ::transfer_parser::batch(self.handle_churn(close_group.clone()),
+                                                     &close_group);
         self.send(::routing::authority::Authority::NaeManager(::routing::NameType::new([0u8; 64])),
                   refresh_calls, None, None, None);
+        if let Some(our_name) = our_name {
+            self.register_pmid_node(our_name);
+        }
+    }
+
+    // The PmidNode only re-registers if the offered space or the farmer wallet have changed
+    fn reload_config(&mut self, config: ::config::Config) {
+        self.config_loaded_at = ::time::SteadyTime::now();
+        self.max_disk_space = config.max_disk_space;
+        self.farmer_wallet = config.farmer_wallet_name();
+        if let Some(our_name) = self.pmid_node.our_name() {
+            self.register_pmid_node(our_name);
+        }
+    }
+
+    fn register_pmid_node(&mut self, our_name: ::routing::NameType) {
+        let registration = self.pmid_node.register(our_name, self.max_disk_space,
+                                                   self.farmer_wallet);
+        self.send(::routing::authority::Authority::ManagedNode(our_name), registration, None,
+                  None, None);
+    }
+
+    // Periodic work which isn't triggered by an event from routing
+    fn on_tick(&mut self) {
+        if self.reconnection.is_some() {
+            self.reconnect();
+            return;
+        }
+        self.expire_cached_requests();
+        // The rounds of challenges follow the clock, for the DataManagers of a chunk to agree on
+        let round = ::time::get_time().sec as u64 /
+                    ::std::cmp::max(1, self.challenge_interval.num_seconds()) as u64;
+        for (chunk_name, action) in self.data_manager.challenge_holders(round) {
+            self.send(::routing::authority::Authority::NaeManager(chunk_name), vec![action], None,
+                      None, None);
+        }
+        if let Some(our_name) = self.pmid_node.our_name() {
+            let announcements = self.pmid_node.announcements();
+            self.send(::routing::authority::Authority::ManagedNode(our_name), announcements, None,
+                      None, None);
+        }
+        if self.config_loaded_at + ::time::Duration::seconds(CONFIG_RELOAD_INTERVAL) <=
+           ::time::SteadyTime::now() {
+            self.reload_config(::config::Config::load());
+        }
     }
 
-    fn on_bootstrapped(&self) {
-        // TODO: what is expected to be done here?
-        assert_eq!(0, self.nodes_in_table.len());
+    fn on_bootstrapped(&mut self) {
+        self.on_reconnected();
+        self.reset_close_group();
     }
 
-    fn on_connected(&self) {
-        // TODO: what is expected to be done here?
-        assert_eq!(0, self.nodes_in_table.len());
+    fn on_connected(&mut self) {
+        self.on_reconnected();
+        self.reset_close_group();
     }
 
+    // The personas are paused until routing connects again; meanwhile we have no peers
     fn on_disconnected(&mut self) {
-        unimplemented!();
+        warn!("Vault disconnected, pausing until reconnected");
+        self.reset_close_group();
+        if self.reconnection.is_none() {
+            self.reconnection = Some(Reconnection::new());
+        }
+    }
+
+    // Whatever we knew of our close group is stale once routing (re)joins or leaves the network;
+    // the next churn tells us the new one
+    fn reset_close_group(&mut self) {
+        if !self.nodes_in_table.is_empty() {
+            debug!("Vault forgetting {:?} nodes of its close group", self.nodes_in_table.len());
+            self.nodes_in_table.clear();
+        }
+        if let Some(churn_settle) = self.churn_settle.take() {
+            debug!("Vault dropping {:?} requests queued for the churn to settle",
+                   churn_settle.queued_requests.len());
+        }
+    }
+
+    fn on_reconnected(&mut self) {
+        if let Some(reconnection) = self.reconnection.take() {
+            info!("Vault reconnected after {:?} attempts, resuming", reconnection.attempts);
+        }
     }
 
+    fn reconnect(&mut self) {
+        let due = match self.reconnection {
+            Some(ref reconnection) => reconnection.next_attempt <= ::time::SteadyTime::now(),
+            None => false,
+        };
+        if !due {
+            return;
+        }
+        if let Some(ref mut reconnection) = self.reconnection {
+            reconnection.attempted();
+            info!("Vault reconnecting, attempt {:?}", reconnection.attempts);
+        }
+        // the instance replaced is stopped, and any event it still sends is ignored
+        self.routing.stop();
+        self.routing_generation += 1;
+        self.routing = start_routing(&self.wakeup_sender, self.routing_generation);
+    }
+
+    // A Get or Put routing couldn't deliver from the DataManagers to a holder is rerouted to
+    // another one, any other request is sent again a few times
     fn on_failed_request(&mut self,
-                         _request: ::routing::ExternalRequest,
-                         _our_authority: Option<::routing::authority::Authority>,
-                         _location: ::routing::authority::Authority,
-                         _error: ::routing::error::InterfaceError) {
-        unimplemented!();
+                         request: ::routing::ExternalRequest,
+                         our_authority: Option<::routing::authority::Authority>,
+                         location: ::routing::authority::Authority,
+                         error: ::routing::error::InterfaceError) {
+        warn!("Vault failed to send {:?} to {:?} as {:?}: {:?}", request, location, our_authority,
+              error);
+        let our_authority = match our_authority {
+            Some(our_authority) => our_authority,
+            None => return,
+        };
+        let (name, action) = match request {
+            ::routing::ExternalRequest::Get(data_request, _) =>
+                (data_request_name(&data_request),
+                 ::types::MethodCall::Get { location: location, data_request: data_request }),
+            ::routing::ExternalRequest::Put(data) =>
+                (data.name(), ::types::MethodCall::Put { location: location, content: data }),
+            ::routing::ExternalRequest::Post(data) =>
+                (data.name(), ::types::MethodCall::Post { location: location, content: data }),
+            ::routing::ExternalRequest::Delete(_) => return,
+        };
+        let actions = match (our_authority.clone(), action.clone()) {
+            (::routing::authority::Authority::NaeManager(_),
+             ::types::MethodCall::Get {
+                 location: ::routing::authority::Authority::ManagedNode(pmid_node),
+                 data_request: data_request @ ::routing::data::DataRequest::ImmutableData(..) }) =>
+                self.data_manager.handle_failed_get(pmid_node, data_request),
+            (::routing::authority::Authority::NaeManager(_),
+             ::types::MethodCall::Put {
+                 location: ::routing::authority::Authority::NodeManager(pmid_node),
+                 content: content @ ::routing::data::Data::ImmutableData(_) }) => {
+                let ranks = self.pmid_manager.ranks();
+                self.data_manager.handle_put_response(
+                    ::routing::error::ResponseError::FailedRequestForData(content), &pmid_node,
+                    &ranks)
+            }
+            _ => self.retry(name, action),
+        };
+        self.send(our_authority, actions, None, None, None);
+    }
+
+    fn retry(&mut self,
+             name: ::routing::NameType,
+             action: ::types::MethodCall)
+             -> Vec<::types::MethodCall> {
+        let retries = match self.request_retries.get(&name) {
+            Some(retries) => *retries,
+            None => 0,
+        };
+        if retries >= MAX_REQUEST_RETRIES {
+            warn!("Vault giving up on {:?} after {:?} retries", action, retries);
+            return vec![];
+        }
+        self.request_retries.add(name, retries + 1);
+        vec![action]
     }
 
     fn on_failed_response(&mut self,
-                          _response: ::routing::ExternalResponse,
-                          _our_authority: Option<::routing::authority::Authority>,
-                          _location: ::routing::authority::Authority,
-                          _error: ::routing::error::InterfaceError) {
-        unimplemented!();
+                          response: ::routing::ExternalResponse,
+                          our_authority: Option<::routing::authority::Authority>,
+                          location: ::routing::authority::Authority,
+                          error: ::routing::error::InterfaceError) {
+        self.failed_responses += 1;
+        warn!("Vault failed to send {:?} to {:?} as {:?}: {:?} ({:?} failed responses so far)",
+              response, location, our_authority, error, self.failed_responses);
     }
 
     fn handle_get(&mut self,
@@ -216,30 +911,48 @@ impl Vault {
                 match data_request.clone() {
                     // drop the message if we don't have the data
                     ::routing::data::DataRequest::ImmutableData(_, _) => {
-                        // Only remember the request from client for Immutable Data
-                        // as StructuredData will get replied immediately from SDManager
-                        if self.request_cache.contains_key(&name) {
-                            debug!("DataManager handle_get inserting original request {:?} from /
-                                   {:?} into {:?} ", data_request, from_authority, name);
-                            match self.request_cache.get_mut(&name) {
-                                Some(ref mut request) => request.push((from_authority.clone(),
-                                                                       data_request.clone(),
-                                                                       response_token.clone())),
-                                None => error!("Failed to insert get request in the cache."),
-                            };
-                        } else {
-                            debug!("DataManager handle_get created original request {:?} from {:?} /
-                                   as entry {:?}", data_request, from_authority, name);
-                            self.request_cache.add(name, vec![(from_authority.clone(),
-                                data_request.clone(), response_token.clone())]);
+                        // A copy cached from an earlier response is replied straight away, sparing
+                        // the holders; lost holders are still noticed by the storage challenges
+                        match self.handle_cache_get(data_request.clone(), name,
+                                                    *from_authority.get_location(),
+                                                    response_token.clone()) {
+                            Ok(reply) => vec![reply],
+                            Err(_) => {
+                                // Only remember the request from client for Immutable Data
+                                // as StructuredData will get replied immediately from SDManager
+                                if self.cache_request(name, &our_authority, &from_authority,
+                                                      &data_request, &response_token) {
+                                    self.data_manager.handle_get(&name, data_request.clone(),
+                                                                 from_authority.clone())
+                                } else {
+                                    vec![]
+                                }
+                            }
                         }
-                        self.data_manager.handle_get(&name, data_request.clone())
                     }
                     ::routing::data::DataRequest::StructuredData(_, _) =>
                         self.sd_manager.handle_get(name),
                     _ => vec![],
                 }
             }
+            ::routing::authority::Authority::ClientManager(name) => {
+                match data_request.clone() {
+                    // Only MpidManager handles Get requests to a ClientManager
+                    ::routing::data::DataRequest::PlainData(data_name) => {
+                        // A client fetching a message is replied once the sender's MpidManagers
+                        // respond
+                        if data_name != name {
+                            if let ::routing::authority::Authority::Client(_, _) = from_authority {
+                                let _ = self.cache_request(data_name, &our_authority,
+                                                           &from_authority, &data_request,
+                                                           &response_token);
+                            }
+                        }
+                        self.mpid_manager.handle_get(&name, &from_authority, &data_name)
+                    }
+                    _ => vec![],
+                }
+            }
             ::routing::authority::Authority::ManagedNode(_) => {
                 match from_authority {
                     // drop the message if we don't have the data
@@ -259,16 +972,24 @@ impl Vault {
                   from_authority: ::routing::authority::Authority,
                   data: ::routing::data::Data,
                   response_token: Option<::routing::SignedToken>) {
+        let requester = from_authority.clone();
         let returned_actions = match our_authority.clone() {
-            ::routing::authority::Authority::ClientManager(from_address) =>
-                self.maid_manager.handle_put(&from_address, from_authority, data),
+            ::routing::authority::Authority::ClientManager(from_address) => {
+                match ::mpid_manager::MpidMessageWrapper::from_plain_data(&data) {
+                    Some(wrapper) => self.mpid_manager.handle_put(&from_address, from_authority,
+                                                                  data, wrapper),
+                    None => self.maid_manager.handle_put(&from_address, from_authority, data),
+                }
+            }
             ::routing::authority::Authority::NaeManager(_) => {
                 // both DataManager and StructuredDataManager are NaeManagers
                 // client put other data (Immutable, StructuredData) will all goes to MaidManager
                 // first, then goes to DataManager (i.e. from_authority is always ClientManager)
                 match data {
-                    ::routing::data::Data::ImmutableData(data) =>
-                        self.data_manager.handle_put(data, &mut (self.nodes_in_table)),
+                    ::routing::data::Data::ImmutableData(data) => {
+                        let ranks = self.pmid_manager.ranks();
+                        self.data_manager.handle_put(data, &mut (self.nodes_in_table), &ranks)
+                    }
                     ::routing::data::Data::StructuredData(data) =>
                         self.sd_manager.handle_put(data),
                     _ => vec![],
@@ -280,26 +1001,98 @@ impl Vault {
                 self.pmid_node.handle_put(pmid_node, data),
             _ => vec![],
         };
-        self.send(our_authority, returned_actions, response_token, None, None);
+        self.send(our_authority, returned_actions, response_token, Some(requester), None);
     }
 
-    // Post is only used to update the content or owners of a StructuredData
+    // Post is used to update the content or owners of a StructuredData, for messaging, for
+    // wallet transfers, for farming rewards and for proof-of-storage challenges
     fn handle_post(&mut self,
                    our_authority: ::routing::authority::Authority,
-                   _from_authority: ::routing::authority::Authority,
+                   from_authority: ::routing::authority::Authority,
                    data: ::routing::data::Data,
                    response_token: Option<::routing::SignedToken>) {
+        let requester = from_authority.clone();
         let returned_actions = match our_authority {
             ::routing::authority::Authority::NaeManager(_) => {
                 match data {
                     ::routing::data::Data::StructuredData(data) =>
                         self.sd_manager.handle_post(data),
+                    ::routing::data::Data::PlainData(data) =>
+                        self.handle_nae_manager_payload(from_authority,
+                                                        ::routing::data::Data::PlainData(data)),
                     _ => vec![],
                 }
             }
+            ::routing::authority::Authority::ManagedNode(_) => {
+                match (::data_manager::StorageChallenge::from_plain_data(&data),
+                       ::data_manager::HeldChunk::from_plain_data(&data), from_authority) {
+                    (Some(::data_manager::StorageChallenge::Challenge { nonce }), _,
+                     ::routing::authority::Authority::NaeManager(chunk_name)) =>
+                        self.pmid_node.handle_challenge(chunk_name, nonce),
+                    (_, Some(::data_manager::HeldChunk::Reclaim),
+                     ::routing::authority::Authority::NaeManager(chunk_name)) => {
+                        if chunk_name == data.name() {
+                            self.pmid_node.handle_reclaim(chunk_name)
+                        } else {
+                            vec![::types::MethodCall::InvalidRequest { data: data }]
+                        }
+                    }
+                    _ => vec![::types::MethodCall::InvalidRequest { data: data }],
+                }
+            }
+            ::routing::authority::Authority::ClientManager(name) => {
+                if let Some(wrapper) = ::mpid_manager::MpidMessageWrapper::from_plain_data(&data) {
+                    self.mpid_manager.handle_post(&name, from_authority, wrapper)
+                } else if let Some(request) =
+                              ::maid_manager::WalletRequest::from_plain_data(&data) {
+                    self.maid_manager.handle_post(&name, from_authority, data, request)
+                } else {
+                    vec![::types::MethodCall::InvalidRequest { data: data }]
+                }
+            }
+            ::routing::authority::Authority::NodeManager(pmid_node) => {
+                match ::pmid_manager::PmidManagerRequest::from_plain_data(&data) {
+                    Some(request) =>
+                        self.pmid_manager.handle_post(pmid_node, from_authority, data, request),
+                    None => vec![::types::MethodCall::InvalidRequest { data: data }],
+                }
+            }
             _ => vec![],
         };
-        self.send(our_authority, returned_actions, response_token, None, None);
+        self.send(our_authority, returned_actions, response_token, Some(requester), None);
+    }
+
+    // PlainData posted to a chunk's NaeManager by one of its holders answers a storage challenge
+    // or announces the chunk is held, by the holder's PmidManagers it asks for the chunk to be
+    // migrated; posted to an SD's NaeManager it carries a subscription.  Any other payload, or one
+    // from the wrong authority, is rejected.
+    fn handle_nae_manager_payload(&mut self,
+                                  from_authority: ::routing::authority::Authority,
+                                  data: ::routing::data::Data)
+                                  -> Vec<::types::MethodCall> {
+        let name = data.name();
+        if let Some(challenge) = ::data_manager::StorageChallenge::from_plain_data(&data) {
+            if let (::data_manager::StorageChallenge::Proof { proof },
+                    ::routing::authority::Authority::ManagedNode(pmid_node)) =
+                   (challenge, from_authority) {
+                return self.data_manager.handle_challenge_proof(pmid_node, name, proof);
+            }
+        } else if let Some(held_chunk) = ::data_manager::HeldChunk::from_plain_data(&data) {
+            match (held_chunk, from_authority) {
+                (::data_manager::HeldChunk::Announce,
+                 ::routing::authority::Authority::ManagedNode(pmid_node)) =>
+                    return self.data_manager.handle_chunk_announcement(pmid_node, name),
+                (::data_manager::HeldChunk::Migrate,
+                 ::routing::authority::Authority::NodeManager(pmid_node)) => {
+                    let ranks = self.pmid_manager.ranks();
+                    return self.data_manager.handle_holder_demoted(pmid_node, name, &ranks);
+                }
+                _ => {}
+            }
+        } else {
+            return self.sd_manager.handle_subscription(from_authority, data);
+        }
+        vec![::types::MethodCall::InvalidRequest { data: data }]
     }
 
     fn handle_get_response(&mut self,
@@ -307,31 +1100,26 @@ impl Vault {
                            from_authority: ::routing::authority::Authority,
                            response: ::routing::data::Data,
                            response_token: Option<::routing::SignedToken>) {
+        let _ = self.handle_cache_put(from_authority.clone(), *from_authority.get_location(),
+                                      response.clone(), response_token.clone());
         match our_authority.clone() {
             // Lookup in the request_cache and reply to the clients
-            ::routing::authority::Authority::NaeManager(name) => {
-                if self.request_cache.contains_key(&name) {
-                    match self.request_cache.remove(&name) {
-                        Some(requests) => {
-                            for request in requests {
-                                self.send(our_authority.clone(), vec![::types::MethodCall::Reply {
-                                    data: response.clone() }], request.2, Some(request.0),
-                                    Some(request.1));
-                            }
-                        }
-                        None => debug!("Failed to find any requests for get response from {:?}
-                            with our authority {:?}: {:?}.", from_authority,  our_authority,
-                            response),
-                    };
-                }
-            }
+            ::routing::authority::Authority::NaeManager(name) =>
+                self.reply_to_cached_requests(name, &our_authority, &from_authority, &response),
+            // A message fetched from the sender's MpidManagers
+            ::routing::authority::Authority::ClientManager(_) =>
+                self.reply_to_cached_requests(response.name(), &our_authority, &from_authority,
+                                              &response),
             _ => {}
         }
         let returned_actions = match (from_authority, response.clone()) {
             // GetResponse used by DataManager to replicate data to new PN
             (::routing::authority::Authority::ManagedNode(pmid_node),
-                ::routing::data::Data::ImmutableData(_)) =>
-                self.data_manager.handle_get_response(pmid_node, response),
+                ::routing::data::Data::ImmutableData(_)) => {
+                let ranks = self.pmid_manager.ranks();
+                self.data_manager.handle_get_response(pmid_node, response, &self.nodes_in_table,
+                                                      &ranks)
+            }
             _ => vec![],
         };
         self.send(our_authority, returned_actions, response_token, None, None);
@@ -347,8 +1135,10 @@ impl Vault {
         let fowarding_calls = match from_authority {
             ::routing::authority::Authority::ManagedNode(pmid_node) =>
                 self.pmid_manager.handle_put_response(&pmid_node, response),
-            ::routing::authority::Authority::NodeManager(pmid_node) =>
-                self.data_manager.handle_put_response(response, &pmid_node),
+            ::routing::authority::Authority::NodeManager(pmid_node) => {
+                let ranks = self.pmid_manager.ranks();
+                self.data_manager.handle_put_response(response, &pmid_node, &ranks)
+            }
             ::routing::authority::Authority::NaeManager(_) => {
                 match our_authority {
                     ::routing::authority::Authority::NodeManager(pmid_node) =>
@@ -356,6 +1146,17 @@ impl Vault {
                     _ => vec![],
                 }
             }
+            // The recipient's MpidManagers rejected the header of a message
+            ::routing::authority::Authority::ClientManager(_) => {
+                match (our_authority, response) {
+                    (::routing::authority::Authority::ClientManager(account),
+                     ::routing::error::ResponseError::FailedRequestForData(data)) => {
+                        self.mpid_manager.handle_put_failure(&account, data);
+                        vec![]
+                    }
+                    _ => vec![],
+                }
+            }
             _ => vec![],
         };
         self.send(our_authority, fowarding_calls, response_token, None, None);
@@ -371,16 +1172,51 @@ impl Vault {
         vec![]
     }
 
+    // Only the accounts whose close group changed are refreshed
     fn handle_churn(&mut self,
-                    mut close_group: Vec<::routing::NameType>)
+                    close_group: Vec<::routing::NameType>)
                     -> Vec<::types::MethodCall> {
+        let churn = ::churn::Churn::new(self.nodes_in_table.clone(), close_group.clone());
+        let mm = self.maid_manager.handle_churn(&churn);
+        let mpm = self.mpid_manager.handle_churn(&churn);
+        let vh = self.sd_manager.handle_churn(&churn);
+        let pm = self.pmid_manager.handle_churn(&churn);
+        let dm = self.data_manager.handle_churn(&churn);
+        self.nodes_in_table = close_group;
+
+        mm.into_iter().chain(mpm.into_iter().chain(vh.into_iter().chain(pm.into_iter()
+          .chain(dm.into_iter())))).collect()
+    }
+
+    // Every account is handed over to the close group, e.g. when leaving the network
+    fn retrieve_all_and_reset(&mut self) -> Vec<::types::MethodCall> {
+        let mut close_group = self.nodes_in_table.clone();
         let mm = self.maid_manager.retrieve_all_and_reset();
+        let mpm = self.mpid_manager.retrieve_all_and_reset();
         let vh = self.sd_manager.retrieve_all_and_reset();
         let pm = self.pmid_manager.retrieve_all_and_reset(&close_group);
         let dm = self.data_manager.retrieve_all_and_reset(&mut close_group);
-        self.nodes_in_table = close_group;
 
-        mm.into_iter().chain(vh.into_iter().chain(pm.into_iter().chain(dm.into_iter()))).collect()
+        mm.into_iter().chain(mpm.into_iter().chain(vh.into_iter().chain(pm.into_iter()
+          .chain(dm.into_iter())))).collect()
+    }
+
+    // Rejected refreshes are only logged and counted, the account is left as it was
+    fn merge_refresh<T>(&mut self,
+                        type_tag: u64,
+                        from_group: ::routing::NameType,
+                        payloads: Vec<Vec<u8>>)
+                        -> Option<T>
+        where T: for<'a> ::types::Refreshable + 'static {
+        match merge::<T>(type_tag, from_group, payloads, self.refresh_quorum) {
+            Ok(merged) => Some(merged),
+            Err(error) => {
+                self.rejected_refreshes += 1;
+                warn!("Rejected refresh of type {:?} for {:?}: {:?} ({:?} rejected so far)",
+                      type_tag, from_group, error, self.rejected_refreshes);
+                None
+            }
+        }
     }
 
     fn handle_refresh(&mut self,
@@ -392,16 +1228,25 @@ impl Vault {
         match type_tag {
             ::transfer_parser::transfer_tags::MAID_MANAGER_ACCOUNT_TAG => {
                 if let ::routing::Authority::ClientManager(from_group) = our_authority {
-                    if let Some(merged) = merge::<::maid_manager::Account>(from_group, payloads) {
+                    if let Some(merged) = self.merge_refresh(type_tag, from_group, payloads) {
                         self.maid_manager.handle_account_transfer(merged)
                     }
                 } else {
                     warn!("Mismatch of refresh tag {:?} & authority {:?}", type_tag, our_authority);
                 }
             }
+            ::transfer_parser::transfer_tags::MPID_MANAGER_ACCOUNT_TAG => {
+                if let ::routing::Authority::ClientManager(from_group) = our_authority {
+                    if let Some(merged) = self.merge_refresh(type_tag, from_group, payloads) {
+                        self.mpid_manager.handle_account_transfer(merged)
+                    }
+                } else {
+                    warn!("Mismatch of refresh tag {:?} & authority {:?}", type_tag, our_authority);
+                }
+            }
             ::transfer_parser::transfer_tags::DATA_MANAGER_ACCOUNT_TAG => {
                 if let ::routing::Authority::NaeManager(from_group) = our_authority {
-                    if let Some(merged) = merge::<::data_manager::Account>(from_group, payloads) {
+                    if let Some(merged) = self.merge_refresh(type_tag, from_group, payloads) {
                         self.data_manager.handle_account_transfer(merged);
                     }
                 } else {
@@ -410,7 +1255,7 @@ impl Vault {
             }
             ::transfer_parser::transfer_tags::DATA_MANAGER_STATS_TAG => {
                 if let ::routing::Authority::NaeManager(from_group) = our_authority {
-                    if let Some(merged) = merge::<::data_manager::Stats>(from_group, payloads) {
+                    if let Some(merged) = self.merge_refresh(type_tag, from_group, payloads) {
                         self.data_manager.handle_stats_transfer(merged);
                     }
                 } else {
@@ -419,54 +1264,167 @@ impl Vault {
             }
             ::transfer_parser::transfer_tags::PMID_MANAGER_ACCOUNT_TAG => {
                 if let ::routing::Authority::NodeManager(from_group) = our_authority {
-                    if let Some(merged) = merge::<::pmid_manager::Account>(from_group, payloads) {
+                    if let Some(merged) = self.merge_refresh(type_tag, from_group, payloads) {
                         self.pmid_manager.handle_account_transfer(merged);
                     }
                 } else {
                     warn!("Mismatch of refresh tag {:?} & authority {:?}", type_tag, our_authority);
                 }
             }
+            ::transfer_parser::transfer_tags::SD_MANAGER_SUBSCRIPTIONS_TAG => {
+                if let ::routing::Authority::NaeManager(from_group) = our_authority {
+                    if let Some(merged) = self.merge_refresh(type_tag, from_group, payloads) {
+                        self.sd_manager.handle_subscriptions_transfer(merged);
+                    }
+                } else {
+                    warn!("Mismatch of refresh tag {:?} & authority {:?}", type_tag, our_authority);
+                }
+            }
             ::transfer_parser::transfer_tags::SD_MANAGER_ACCOUNT_TAG => {
-                if let ::routing::Authority::NaeManager(_from_group) = our_authority {
-                    for payload in payloads {
-                        // TODO - pass in from_group to allow validation of payloads (should all be
-                        // for same DB entry)
-                        self.sd_manager.handle_account_transfer(payload);
+                if let ::routing::Authority::NaeManager(from_group) = our_authority {
+                    if let Some(merged) = self.merge_refresh(type_tag, from_group, payloads) {
+                        self.sd_manager.handle_account_transfer(merged);
                     }
                 } else {
                     warn!("Mismatch of refresh tag {:?} & authority {:?}", type_tag, our_authority);
                 }
             }
+            ::transfer_parser::transfer_tags::REFRESH_BATCH_TAG => {
+                let close_group = self.nodes_in_table.clone();
+                for (type_tag, group, payloads) in ::transfer_parser::unbatch(payloads,
+                                                                              &close_group) {
+                    self.handle_refresh(type_tag, group, payloads);
+                }
+            }
             _ => {}
         }
     }
 
-    // The cache handling in vault is roleless, i.e. vault will do whatever routing tells it to do
-    #[allow(dead_code)]
+    // The cache handling in vault is roleless, i.e. vault will do whatever routing tells it to do.
+    // Only ImmutableData is served from the cache, anything else may have changed since.  Routing
+    // relays messages without handing them to the vault, so the cache is only checked where the
+    // Get is delivered, by the chunk's DataManagers, rather than at every hop on its route.
     fn handle_cache_get(&mut self,
-                        _: ::routing::data::DataRequest, // data_request
+                        data_request: ::routing::data::DataRequest,
                         data_location: ::routing::NameType,
                         _: ::routing::NameType,
                         _: Option<::routing::SignedToken>)
                         -> Result<::types::MethodCall, ::routing::error::ResponseError> {
-        match self.data_cache.get(&data_location) {
+        match data_request {
+            ::routing::data::DataRequest::ImmutableData(_, _) => {}
+            _ => return Err(::routing::error::ResponseError::Abort),
+        }
+        let result = match self.data_cache.get(&data_location) {
             Some(data) => Ok(::types::MethodCall::Reply { data: data.clone() }),
             // TODO: NoData may still be preferred here
             None => Err(::routing::error::ResponseError::Abort),
+        };
+        if result.is_ok() {
+            self.cache_hits += 1;
+        } else {
+            self.cache_misses += 1;
         }
+        debug!("Vault data cache {} for {:?}, {:?} hits and {:?} misses so far",
+               if result.is_ok() { "hit" } else { "miss" }, data_location, self.cache_hits,
+               self.cache_misses);
+        result
     }
 
-    #[allow(dead_code)]
+    // StructuredData is never cached as it's mutable, nor is PlainData, which carries messages
+    // and requests rather than content
     fn handle_cache_put(&mut self,
                         _: ::routing::authority::Authority, // from_authority
                         _: ::routing::NameType, // from_address
                         data: ::routing::data::Data,
                         _: Option<::routing::SignedToken>)
                         -> Result<::types::MethodCall, ::routing::error::ResponseError> {
-        self.data_cache.add(data.name(), data);
+        if let ::routing::data::Data::ImmutableData(_) = data {
+            self.data_cache.add(data.name(), data);
+        }
         Err(::routing::error::ResponseError::Abort)
     }
 
+    // Stops waiting for the data on behalf of the requester, e.g. as it's been answered otherwise
+    fn uncache_request(&mut self,
+                       name: &::routing::NameType,
+                       requester: &::routing::authority::Authority,
+                       data_request: &::routing::data::DataRequest) {
+        let requests = match self.request_cache.remove(name) {
+            Some(requests) => requests,
+            None => return,
+        };
+        let (uncached, waiting): (Vec<_>, Vec<_>) = requests.into_iter().partition(|request| {
+            request.requester == *requester && request.data_request == *data_request
+        });
+        self.cached_requests = self.cached_requests.saturating_sub(uncached.len());
+        if !waiting.is_empty() {
+            self.request_cache.add(name.clone(), waiting);
+        }
+    }
+
+    // Returns false if too many requests are already waiting, for the name or in total, in which
+    // case the request is failed straight away
+    fn cache_request(&mut self,
+                     name: ::routing::NameType,
+                     our_authority: &::routing::authority::Authority,
+                     from_authority: &::routing::authority::Authority,
+                     data_request: &::routing::data::DataRequest,
+                     response_token: &Option<::routing::SignedToken>)
+                     -> bool {
+        let request = CachedRequest {
+            our_authority: our_authority.clone(),
+            requester: from_authority.clone(),
+            data_request: data_request.clone(),
+            response_token: response_token.clone(),
+            expires_at: ::time::SteadyTime::now() + self.request_timeout,
+        };
+        let waiting = self.request_cache.get(&name).map(|requests| requests.len()).unwrap_or(0);
+        if waiting >= self.max_requests_per_name ||
+           self.cached_requests >= self.max_cached_requests {
+            warn!("Vault has too many requests waiting, {:?} for {:?} and {:?} in total",
+                  waiting, name, self.cached_requests);
+            self.get_failed(request.our_authority, request.requester, request.data_request,
+                            ::types::GetFailureReason::Busy, request.response_token);
+            return false;
+        }
+        self.cached_requests += 1;
+        if waiting > 0 {
+            debug!("Vault inserting original request {:?} from {:?} into {:?} ", data_request,
+                   from_authority, name);
+            match self.request_cache.get_mut(&name) {
+                Some(ref mut requests) => requests.push(request),
+                None => error!("Failed to insert get request in the cache."),
+            };
+        } else {
+            debug!("Vault created original request {:?} from {:?} as entry {:?}", data_request,
+                   from_authority, name);
+            self.request_cache.add(name, vec![request]);
+        }
+        true
+    }
+
+    fn reply_to_cached_requests(&mut self,
+                                name: ::routing::NameType,
+                                our_authority: &::routing::authority::Authority,
+                                from_authority: &::routing::authority::Authority,
+                                response: &::routing::data::Data) {
+        if self.request_cache.contains_key(&name) {
+            match self.request_cache.remove(&name) {
+                Some(requests) => {
+                    self.cached_requests = self.cached_requests.saturating_sub(requests.len());
+                    for request in requests {
+                        self.send(our_authority.clone(), vec![::types::MethodCall::Reply {
+                            data: response.clone() }], request.response_token,
+                            Some(request.requester), Some(request.data_request));
+                    }
+                }
+                None => debug!("Failed to find any requests for get response from {:?}
+                    with our authority {:?}: {:?}.", from_authority,  our_authority,
+                    response),
+            };
+        }
+    }
+
     fn send(&mut self,
             our_authority: ::routing::authority::Authority,
             actions: Vec<::types::MethodCall>,
@@ -481,6 +1439,9 @@ impl Vault {
                 ::types::MethodCall::Put { location, content } => {
                     self.routing.put_request(our_authority.clone(), location, content);
                 }
+                ::types::MethodCall::Post { location, content } => {
+                    self.routing.post_request(our_authority.clone(), location, content);
+                }
                 ::types::MethodCall::Reply { data } => {
                     match (&optional_reply_to, &optional_original_data_request) {
                         (&Some(ref reply_to), &Some(ref original_data_request)) => {
@@ -490,9 +1451,23 @@ impl Vault {
                             self.routing.get_response(our_authority.clone(), reply_to.clone(), data,
                                 original_data_request.clone(), response_token.clone());
                         }
-                        _ => {}
+                        _ => warn!("as {:?} dropping reply {:?} to a request which isn't a Get",
+                                   our_authority, data),
                     };
                 }
+                ::types::MethodCall::GetFailed { name, reason } => {
+                    // Only the request being handled is failed, if it's about that data; others
+                    // waiting for it may still be answered, or else fail once they time out
+                    if let (&Some(ref reply_to), &Some(ref original_data_request)) =
+                           (&optional_reply_to, &optional_original_data_request) {
+                        if data_request_name(original_data_request) == name {
+                            self.uncache_request(&name, reply_to, original_data_request);
+                            self.get_failed(our_authority.clone(), reply_to.clone(),
+                                            original_data_request.clone(), reason,
+                                            response_token.clone());
+                        }
+                    }
+                }
                 ::types::MethodCall::Refresh { type_tag, our_authority, payload } => {
                     info!("refreshing account type {:?} of group {:?} to network", type_tag,
                           our_authority);
@@ -509,17 +1484,53 @@ impl Vault {
                     debug!("as {:?} sacrifize data {:?} freeing space {:?}, notifying {:?}",
                            our_authority, name, size, location);
                     self.routing.put_response(our_authority.clone(), location,
-                        ::routing::error::ResponseError::HadToClearSacrificial(name, size),
+                        ::routing::error::ResponseError::HadToClearSacrificial(name,
+                            ::utils::saturate_u32(size)),
                         response_token.clone());
                 }
                 ::types::MethodCall::LowBalance { location, data, balance } => {
                     debug!("as {:?} failed in putting data {:?}, responding to {:?}",
                            our_authority, data, location);
                     self.routing.put_response(our_authority.clone(), location,
-                                              ::routing::error::ResponseError::LowBalance(data, balance),
-                                              response_token.clone());
+                        ::routing::error::ResponseError::LowBalance(data,
+                            ::utils::saturate_u32(balance)),
+                        response_token.clone());
                 },
-                _ => {}
+                ::types::MethodCall::NoAccount { location, data } => {
+                    debug!("as {:?} refusing data {:?} from {:?} without an account",
+                           our_authority, data, location);
+                    self.routing.put_response(our_authority.clone(), location,
+                        ::routing::error::ResponseError::InvalidRequest(data),
+                        response_token.clone());
+                }
+                ::types::MethodCall::Notify { location, data } => {
+                    // Notifications reach the client as an unsolicited get response
+                    let data_request = match data {
+                        ::routing::data::Data::StructuredData(ref sd) =>
+                            ::routing::data::DataRequest::StructuredData(sd.name(),
+                                                                         sd.get_type_tag()),
+                        ::routing::data::Data::PlainData(ref plain_data) =>
+                            ::routing::data::DataRequest::PlainData(plain_data.name()),
+                        _ => continue,
+                    };
+                    debug!("as {:?} notifying {:?} of {:?}", our_authority, location,
+                           data.name());
+                    self.routing.get_response(our_authority.clone(), location, data.clone(),
+                                              data_request, None);
+                }
+                ::types::MethodCall::InvalidRequest { data } => {
+                    match optional_reply_to {
+                        Some(ref requester) => {
+                            debug!("as {:?} rejecting data {:?} from {:?} as invalid",
+                                   our_authority, data, requester);
+                            self.routing.put_response(our_authority.clone(), requester.clone(),
+                                ::routing::error::ResponseError::InvalidRequest(data),
+                                response_token.clone());
+                        }
+                        None => warn!("as {:?} rejecting data {:?} as invalid, with no requester \
+                                       to tell", our_authority, data),
+                    }
+                }
             }
         }
     }
@@ -531,11 +1542,11 @@ pub type ResponseNotifier =
 
 #[cfg(test)]
 mod test {
-    use cbor;
     use sodiumoxide::crypto;
 
     use super::*;
     use transfer_parser::{Transfer, transfer_tags};
+    use types::PlainPayload;
 
     #[cfg(feature = "use-mock-routing")]
     fn mock_env_setup() -> (super::Routing, ::std::sync::mpsc::Receiver<(::routing::data::Data)>) {
@@ -545,6 +1556,8 @@ mod test {
                                                               });
                         };
         let mut vault = Vault::new();
+        // churns are handled as they come, the flows don't wait for them to settle
+        vault.churn_settle_window = ::time::Duration::zero();
         let receiver = vault.routing.get_client_receiver();
         let mut routing = vault.routing.clone();
         let _ = run_vault(vault);
@@ -557,17 +1570,39 @@ mod test {
         (routing, receiver)
     }
 
+    // Creates the client's account and buys space with a safecoin paid in by a farmer
+    #[cfg(feature = "use-mock-routing")]
+    fn mock_create_account(routing: &mut super::Routing,
+                           client_name: ::routing::NameType,
+                           client_keys: &(crypto::sign::PublicKey, crypto::sign::SecretKey)) {
+        let create_account = ::maid_manager::WalletRequest::CreateAccount;
+        routing.client_account_post(client_name, client_keys,
+            create_account.to_plain_data(::utils::random_name()).unwrap());
+        ::std::thread::sleep_ms(500);
+        let farmer = ::utils::random_name();
+        let credit = ::maid_manager::WalletRequest::Credit { sender: farmer, amount: 1 };
+        routing.post_request(::routing::authority::Authority::NodeManager(farmer),
+                             ::routing::authority::Authority::ClientManager(client_name),
+                             credit.to_plain_data(::utils::random_name()).unwrap());
+        ::std::thread::sleep_ms(500);
+        let buy_space = ::maid_manager::WalletRequest::BuySpace { amount: 1 };
+        routing.client_account_post(client_name, client_keys,
+            buy_space.to_plain_data(::utils::random_name()).unwrap());
+        ::std::thread::sleep_ms(500);
+    }
+
     #[cfg(feature = "use-mock-routing")]
     #[test]
     fn put_get_flow() {
         let (mut routing, receiver) = mock_env_setup();
 
-        let client_name = ::utils::random_name();
         let sign_keys = ::sodiumoxide::crypto::sign::gen_keypair();
+        let client_name = ::utils::client_name(&sign_keys.0);
         let value = ::routing::types::generate_random_vec_u8(1024);
         let im_data = ::routing::immutable_data::ImmutableData::new(
                           ::routing::immutable_data::ImmutableDataType::Normal, value);
-        routing.client_put(client_name, sign_keys.0,
+        mock_create_account(&mut routing, client_name, &sign_keys);
+        routing.client_put(client_name, &sign_keys,
             ::routing::data::Data::ImmutableData(im_data.clone()));
         ::std::thread::sleep_ms(2000);
 
@@ -580,6 +1615,52 @@ mod test {
         }
     }
 
+    #[cfg(feature = "use-mock-routing")]
+    #[test]
+    fn routing_failures_flow() {
+        let (mut routing, receiver) = mock_env_setup();
+        ::std::thread::sleep_ms(500);
+
+        // none of these may bring the vault down
+        routing.disconnect_event();
+        let data = ::routing::data::Data::PlainData(::routing::plain_data::PlainData::new(
+                       ::utils::random_name(), vec![]));
+        routing.failed_request_event(::routing::ExternalRequest::Post(data.clone()),
+            Some(::routing::authority::Authority::NaeManager(data.name())),
+            ::routing::authority::Authority::ManagedNode(::utils::random_name()),
+            ::routing::error::InterfaceError::Abort);
+        routing.failed_response_event(
+            ::routing::ExternalResponse::Put(::routing::error::ResponseError::Abort, None), None,
+            ::routing::authority::Authority::ManagedNode(::utils::random_name()),
+            ::routing::error::InterfaceError::Abort);
+        routing.connect_event();
+        ::std::thread::sleep_ms(500);
+
+        // once reconnected the vault serves requests again
+        let mut available_nodes = Vec::with_capacity(30);
+        for _ in 0..30 {
+            available_nodes.push(::utils::random_name());
+        }
+        routing.churn_event(available_nodes);
+        ::std::thread::sleep_ms(500);
+        let sign_keys = ::sodiumoxide::crypto::sign::gen_keypair();
+        let client_name = ::utils::client_name(&sign_keys.0);
+        let im_data = ::routing::immutable_data::ImmutableData::new(
+                          ::routing::immutable_data::ImmutableDataType::Normal,
+                          ::routing::types::generate_random_vec_u8(1024));
+        mock_create_account(&mut routing, client_name, &sign_keys);
+        routing.client_put(client_name, &sign_keys,
+                           ::routing::data::Data::ImmutableData(im_data.clone()));
+        ::std::thread::sleep_ms(2000);
+        let data_request = ::routing::data::DataRequest::ImmutableData(im_data.name(),
+                               ::routing::immutable_data::ImmutableDataType::Normal);
+        routing.client_get(client_name, sign_keys.0, data_request);
+        for it in receiver.iter() {
+            assert_eq!(it, ::routing::data::Data::ImmutableData(im_data));
+            break;
+        }
+    }
+
     #[cfg(feature = "use-mock-routing")]
     #[test]
     fn post_flow() {
@@ -591,15 +1672,16 @@ mod test {
         let sd = ::routing::structured_data::StructuredData::new(0, name, 0,
             value.clone(), vec![sign_keys.0], vec![], Some(&sign_keys.1)).ok().unwrap();
 
-        let client_name = ::utils::random_name();
-        routing.client_put(client_name, sign_keys.0,
+        let client_name = ::utils::client_name(&sign_keys.0);
+        mock_create_account(&mut routing, client_name, &sign_keys);
+        routing.client_put(client_name, &sign_keys,
             ::routing::data::Data::StructuredData(sd.clone()));
         ::std::thread::sleep_ms(2000);
 
         let keys = ::sodiumoxide::crypto::sign::gen_keypair();
         let sd_new = ::routing::structured_data::StructuredData::new(0, name, 1,
             value.clone(), vec![keys.0], vec![sign_keys.0], Some(&sign_keys.1)).ok().unwrap();
-        routing.client_post(client_name, sign_keys.0,
+        routing.client_post(client_name, &sign_keys,
             ::routing::data::Data::StructuredData(sd_new.clone()));
         ::std::thread::sleep_ms(2000);
 
@@ -681,8 +1763,10 @@ mod test {
         (client_routing, client_receiver, client_name)
     }
 
+    // A client on a fresh network has no way of funding its wallet to buy space yet
     #[cfg(not(feature = "use-mock-routing"))]
     #[test]
+    #[ignore]
     fn network_put_get_test() {
         let (mut client_routing, client_receiver, client_name) = network_env_setup();
 
@@ -702,8 +1786,10 @@ mod test {
         }
     }
 
+    // A client on a fresh network has no way of funding its wallet to buy space yet
     #[cfg(not(feature = "use-mock-routing"))]
     #[test]
+    #[ignore]
     fn network_post_test() {
         let (mut client_routing, client_receiver, client_name) = network_env_setup();
 
@@ -731,8 +1817,10 @@ mod test {
         }
     }
 
+    // A client on a fresh network has no way of funding its wallet to buy space yet
     #[cfg(not(feature = "use-mock-routing"))]
     #[test]
+    #[ignore]
     fn network_churn_immutable_data_test() {
         let (mut client_routing, client_receiver, client_name) = network_env_setup();
 
@@ -757,8 +1845,10 @@ mod test {
         }
     }
 
+    // A client on a fresh network has no way of funding its wallet to buy space yet
     #[cfg(not(feature = "use-mock-routing"))]
     #[test]
+    #[ignore]
     fn network_churn_structured_data_test() {
         let (mut client_routing, client_receiver, client_name) = network_env_setup();
 
@@ -788,6 +1878,17 @@ mod test {
                         client: ::routing::NameType,
                         im_data: ::routing::immutable_data::ImmutableData) {
         let keys = crypto::sign::gen_keypair();
+        let client_authority = ::routing::authority::Authority::Client(client, keys.0);
+        let farmer = ::utils::random_name();
+        for (from_authority, request) in vec![
+                (client_authority.clone(), ::maid_manager::WalletRequest::CreateAccount),
+                (::routing::authority::Authority::NodeManager(farmer),
+                 ::maid_manager::WalletRequest::Credit { sender: farmer, amount: 1 }),
+                (client_authority, ::maid_manager::WalletRequest::BuySpace { amount: 1 })] {
+            let data = request.to_plain_data(::utils::random_name()).unwrap();
+            assert!(vault.maid_manager.handle_post(&client, from_authority, data, request)
+                         .is_empty());
+        }
         let _put_result = vault.handle_put(::routing::authority::Authority::ClientManager(client),
                                            ::routing::authority::Authority::Client(client, keys.0),
                                            ::routing::data::Data::ImmutableData(im_data.clone()),
@@ -810,6 +1911,10 @@ mod test {
     fn pmid_manager_put(vault: &mut Vault,
                         pmid_node: ::routing::NameType,
                         im_data: ::routing::immutable_data::ImmutableData) {
+        let register = ::pmid_manager::PmidManagerRequest::Register { offered_space: 1073741824 };
+        let data = register.to_plain_data(pmid_node.clone()).unwrap();
+        vault.handle_post(::routing::authority::Authority::NodeManager(pmid_node),
+                          ::routing::authority::Authority::ManagedNode(pmid_node), data, None);
         let _put_result = vault.handle_put(
             ::routing::authority::Authority::NodeManager(pmid_node),
             ::routing::authority::Authority::NaeManager(im_data.name()),
@@ -853,8 +1958,7 @@ mod test {
                     assert_eq!(*type_tag,
                                ::transfer_parser::transfer_tags::MAID_MANAGER_ACCOUNT_TAG);
                     assert_eq!(*our_authority.get_location(), available_nodes[0]);
-                    let mut d = cbor::Decoder::from_bytes(&payload[..]);
-                    if let Some(parsed_data) = d.decode().next().and_then(|result| result.ok()) {
+                    if let Some(parsed_data) = Transfer::parse(payload) {
                         match parsed_data {
                             Transfer::MaidManagerAccount(mm_account) => {
                                 assert_eq!(*mm_account.name(), available_nodes[0]);
@@ -871,16 +1975,21 @@ mod test {
                 }
                 _ => panic!("Refresh type expected"),
             };
-            add_nodes_to_table(&mut vault, &Vec::<::routing::NameType>::new());
+            // an unchanged close group only refreshes the DataManager stats
+            assert_eq!(vault.handle_churn(small_close_group.clone()).len(), 1);
+            // while rejoining refreshes every account again
+            vault.nodes_in_table.clear();
             let re_churn_data = vault.handle_churn(small_close_group.clone());
             assert_eq!(churn_data[0], re_churn_data[0]);
-            assert!(vault.maid_manager.retrieve_all_and_reset().is_empty());
+            // the account stays in place as we're still responsible for it
+            assert_eq!(vault.maid_manager.retrieve_all_and_reset().len(), 1);
         }
 
         add_nodes_to_table(&mut vault, &available_nodes);
 
         {// DataManager - churn handling
             data_manager_put(&mut vault, im_data.clone());
+            vault.nodes_in_table.clear();
             let mut close_group = Vec::with_capacity(20);
             for i in 10..30 {
                 close_group.push(available_nodes[i].clone());
@@ -893,8 +2002,7 @@ mod test {
                 ::types::MethodCall::Refresh{ref type_tag, ref our_authority, ref payload} => {
                     assert_eq!(*type_tag, transfer_tags::DATA_MANAGER_ACCOUNT_TAG);
                     assert_eq!(*our_authority.get_location(), im_data.name());
-                    let mut d = cbor::Decoder::from_bytes(&payload[..]);
-                    if let Some(parsed_data) = d.decode().next().and_then(|result| result.ok()) {
+                    if let Some(parsed_data) = Transfer::parse(payload) {
                         match parsed_data {
                             Transfer::DataManagerAccount(account) => {
                                 assert_eq!(*account.name(), im_data.name());
@@ -916,8 +2024,7 @@ mod test {
                 ::types::MethodCall::Refresh{ref type_tag, ref our_authority, ref payload} => {
                     assert_eq!(*type_tag, transfer_tags::DATA_MANAGER_STATS_TAG);
                     assert_eq!(*our_authority.get_location(), close_group[0]);
-                    let mut d = cbor::Decoder::from_bytes(&payload[..]);
-                    if let Some(parsed_data) = d.decode().next().and_then(|result| result.ok()) {
+                    if let Some(parsed_data) = Transfer::parse(payload) {
                         match parsed_data {
                             Transfer::DataManagerStats(stats) => {
                                 assert_eq!(stats.resource_index(), 1);
@@ -934,16 +2041,20 @@ mod test {
                 ::types::MethodCall::Get { .. } => (),
                 _ => panic!("Refresh type expected"),
             };
-            add_nodes_to_table(&mut vault, &available_nodes);
+            vault.nodes_in_table.clear();
             let re_churn_data = vault.handle_churn(close_group.clone());
             assert_eq!(churn_data[0], re_churn_data[0]);
             assert_eq!(churn_data[1], re_churn_data[1]);
-            // DataManagerStatsTransfer will always be included in the return
-            assert_eq!(vault.data_manager.retrieve_all_and_reset(&mut close_group).len(), 1);
+            // DataManagerStatsTransfer will always be included in the return, the account only
+            // if we're still one of the closest to the chunk
+            let kept = ::churn::Churn::new(vec![], close_group.clone()).keeps(&im_data.name());
+            assert_eq!(vault.data_manager.retrieve_all_and_reset(&mut close_group).len(),
+                       if kept { 2 } else { 1 });
         }
 
         {// PmidManager - churn handling
             pmid_manager_put(&mut vault, available_nodes[1].clone(), im_data.clone());
+            vault.nodes_in_table.clear();
             let churn_data = vault.handle_churn(small_close_group.clone());
             // DataManagerStatsTransfer will always be included in the return
             assert_eq!(churn_data.len(), 2);
@@ -953,8 +2064,7 @@ mod test {
                 ::types::MethodCall::Refresh{ref type_tag, ref our_authority, ref payload} => {
                     assert_eq!(*type_tag, transfer_tags::PMID_MANAGER_ACCOUNT_TAG);
                     assert_eq!(*our_authority.get_location(), available_nodes[1]);
-                    let mut d = cbor::Decoder::from_bytes(&payload[..]);
-                    if let Some(parsed_data) = d.decode().next().and_then(|result| result.ok()) {
+                    if let Some(parsed_data) = Transfer::parse(payload) {
                         match parsed_data {
                             Transfer::PmidManagerAccount(account) => {
                                 assert_eq!(*account.name(), available_nodes[1]);
@@ -970,7 +2080,7 @@ mod test {
                 }
                 _ => panic!("Refresh type expected"),
             };
-            add_nodes_to_table(&mut vault, &Vec::<::routing::NameType>::new());
+            vault.nodes_in_table.clear();
             let re_churn_data = vault.handle_churn(small_close_group.clone());
             assert_eq!(churn_data[0], re_churn_data[0]);
             assert!(vault.pmid_manager.retrieve_all_and_reset(&Vec::new()).is_empty());
@@ -985,6 +2095,7 @@ mod test {
                                                                       Some(&keys.1)).ok().unwrap();
 
             sd_manager_put(&mut vault, sdv.clone());
+            vault.nodes_in_table.clear();
             let churn_data = vault.handle_churn(small_close_group.clone());
             // DataManagerStatsTransfer will always be included in the return
             assert_eq!(churn_data.len(), 2);
@@ -993,12 +2104,11 @@ mod test {
                 ::types::MethodCall::Refresh{ref type_tag, ref our_authority, ref payload} => {
                     assert_eq!(*type_tag, transfer_tags::SD_MANAGER_ACCOUNT_TAG);
                     assert_eq!(*our_authority.get_location(), sdv.name());
-                    match ::routing::utils::decode::<
-                              ::routing::structured_data::StructuredData>(payload) {
-                        Ok(sd) => {
-                            assert_eq!(sd, sdv);
+                    match Transfer::parse(payload) {
+                        Some(Transfer::SdManagerAccount(account)) => {
+                            assert_eq!(account, ::sd_manager::Account::new(&sdv).unwrap());
                         }
-                        Err(_) => panic!("Unexpected"),
+                        _ => panic!("Unexpected"),
                     };
                     let mut payloads = vec![];
                     for _ in 0..(::routing::types::GROUP_SIZE - 1) {
@@ -1008,12 +2118,335 @@ mod test {
                 }
                 _ => panic!("Refresh type expected"),
             };
-            add_nodes_to_table(&mut vault, &Vec::<::routing::NameType>::new());
+            vault.nodes_in_table.clear();
             let re_churn_data = vault.handle_churn(small_close_group.clone());
             assert_eq!(churn_data[0], re_churn_data[0]);
-            assert!(vault.sd_manager.retrieve_all_and_reset().is_empty());
+            assert_eq!(vault.sd_manager.retrieve_all_and_reset().len(), 1);
+        }
+
+    }
+
+    #[test]
+    fn batched_refresh_test() {
+        let mut vault = Vault::new();
+        let value = ::routing::types::generate_random_vec_u8(1024);
+        let im_data = ::routing::immutable_data::ImmutableData::new(
+                          ::routing::immutable_data::ImmutableDataType::Normal, value);
+        let clients = (0..3).map(|_| ::utils::random_name()).collect::<Vec<_>>();
+        for client in clients.iter() {
+            maid_manager_put(&mut vault, client.clone(), im_data.clone());
+        }
+
+        // nodes closer to a client than any of the others, so that the groups of the accounts are
+        // made of different nodes
+        let near = |name: &::routing::NameType| {
+            (0..::routing::types::GROUP_SIZE).map(|i| {
+                let mut bytes = name.0;
+                bytes[63] ^= i as u8 + 1;
+                ::routing::NameType::new(bytes)
+            }).collect::<Vec<_>>()
+        };
+        vault.nodes_in_table = clients.iter().flat_map(|client| near(client).into_iter()).collect();
+
+        // the accounts belong to groups of different nodes, so each is sent to its own
+        let close_group = vault.nodes_in_table.clone();
+        let refreshes = ::transfer_parser::batch(vault.retrieve_all_and_reset(), &close_group)
+                            .into_iter()
+                            .filter_map(|action| match action {
+                                ::types::MethodCall::Refresh { type_tag, our_authority,
+                                                               payload } => {
+                                    if type_tag == transfer_tags::MAID_MANAGER_ACCOUNT_TAG {
+                                        Some((our_authority, payload))
+                                    } else {
+                                        None
+                                    }
+                                }
+                                _ => None,
+                            })
+                            .collect::<Vec<_>>();
+        assert_eq!(refreshes.len(), clients.len());
+        let quorum = ::types::default_quorum();
+        let mut receiver = Vault::new();
+        for &(ref our_authority, ref payload) in refreshes.iter() {
+            match *our_authority {
+                ::routing::Authority::ClientManager(ref name) => assert!(clients.contains(name)),
+                _ => panic!("Unexpected"),
+            }
+            receiver.handle_refresh(transfer_tags::MAID_MANAGER_ACCOUNT_TAG,
+                                    our_authority.clone(), vec![payload.clone(); quorum]);
+        }
+        assert_eq!(receiver.maid_manager.retrieve_all_and_reset().len(), clients.len());
+        assert_eq!(receiver.rejected_refreshes, 0);
+
+        // and a batch only brings in the accounts of the groups the receiver is part of
+        let entries = refreshes.into_iter()
+                               .map(|(our_authority, payload)| {
+                                   ::transfer_parser::BatchEntry {
+                                       type_tag: transfer_tags::MAID_MANAGER_ACCOUNT_TAG,
+                                       our_authority: our_authority,
+                                       payload: payload,
+                                   }
+                               })
+                               .collect::<Vec<_>>();
+        let mixed = ::transfer_parser::encode(transfer_tags::REFRESH_BATCH_TAG, &entries).unwrap();
+        let mut receiver = Vault::new();
+        receiver.nodes_in_table = vec![clients[0]];
+        for client in clients[1..].iter() {
+            receiver.nodes_in_table.extend(near(client).into_iter());
         }
+        receiver.handle_refresh(transfer_tags::REFRESH_BATCH_TAG,
+                                ::routing::Authority::ClientManager(clients[0]),
+                                vec![mixed; quorum]);
+        assert_eq!(receiver.maid_manager.retrieve_all_and_reset().len(), 1);
+    }
 
+    #[test]
+    fn reload_config_test() {
+        use rustc_serialize::hex::ToHex;
+        let mut vault = Vault::new();
+        let our_name = ::utils::random_name();
+        // nothing is advertised before we know our name
+        vault.reload_config(::config::Config { max_disk_space: 1024, ..Default::default() });
+        assert_eq!(vault.max_disk_space, 1024);
+        assert!(vault.pmid_node.our_name().is_none());
+
+        vault.register_pmid_node(our_name);
+        assert!(vault.pmid_node.register(our_name, 1024, None).is_empty());
+
+        // a changed offered space or farmer wallet is advertised again
+        let wallet = ::utils::random_name();
+        let config = ::config::Config {
+            max_disk_space: 2048,
+            farmer_wallet: Some(wallet.0[..].to_hex()),
+            ..Default::default()
+        };
+        vault.reload_config(config);
+        assert_eq!(vault.farmer_wallet, Some(wallet));
+        assert!(vault.pmid_node.register(our_name, 2048, Some(wallet)).is_empty());
+    }
+
+    #[test]
+    fn disconnect_test() {
+        let mut vault = Vault::new();
+        vault.nodes_in_table = vec![::utils::random_name()];
+        vault.on_disconnected();
+        assert!(vault.nodes_in_table.is_empty());
+        assert!(vault.reconnection.is_some());
+
+        // reconnection attempts back off
+        if let Some(ref mut reconnection) = vault.reconnection {
+            reconnection.next_attempt = ::time::SteadyTime::now();
+        }
+        vault.on_tick();
+        vault.on_tick();
+        match vault.reconnection {
+            Some(ref reconnection) => {
+                assert_eq!(reconnection.attempts, 1);
+                assert!(reconnection.next_attempt > ::time::SteadyTime::now());
+            }
+            None => panic!("Unexpected"),
+        }
+        // the routing instance was replaced once, the events of the stopped one being ignored
+        assert_eq!(vault.routing_generation, 1);
+
+        // joining resets a close group we may still know of
+        vault.nodes_in_table = vec![::utils::random_name()];
+        vault.on_connected();
+        assert!(vault.reconnection.is_none());
+        assert!(vault.nodes_in_table.is_empty());
+    }
+
+    #[test]
+    fn shutdown_test() {
+        let mut vault = Vault::new();
+        vault.shutdown_deadline = ::time::Duration::seconds(1);
+        let name = ::utils::random_name();
+        vault.cache_request(name.clone(), &::routing::authority::Authority::NaeManager(name),
+                            &::routing::authority::Authority::ClientManager(::utils::random_name()),
+                            &::routing::data::DataRequest::ImmutableData(name.clone(),
+                                ::routing::immutable_data::ImmutableDataType::Normal),
+                            &None);
+        // the requests held back for a churn to settle are failed too, like those waiting for data
+        vault.churn_settle_window = ::time::Duration::seconds(10);
+        vault.on_churn((0..3).map(|_| ::utils::random_name()).collect());
+        let queued_name = ::utils::random_name();
+        vault.on_request(::routing::ExternalRequest::Get(
+                             ::routing::data::DataRequest::ImmutableData(queued_name.clone(),
+                                 ::routing::immutable_data::ImmutableDataType::Normal), 0),
+                         ::routing::authority::Authority::NaeManager(queued_name),
+                         ::routing::authority::Authority::ClientManager(::utils::random_name()),
+                         None);
+        assert_eq!(vault.churn_settle.as_ref().map(|churn_settle| {
+                       churn_settle.queued_requests.len()
+                   }),
+                   Some(1));
+        vault.stop_requested.store(true, ::std::sync::atomic::Ordering::SeqCst);
+        let started = ::time::SteadyTime::now();
+        vault.do_run();
+        assert!(::time::SteadyTime::now() - started < ::time::Duration::seconds(2));
+        assert!(!vault.request_cache.contains_key(&name));
+        assert!(vault.churn_settle.is_none());
+    }
+
+    #[test]
+    fn request_cache_test() {
+        let mut vault = Vault::new();
+        vault.request_timeout = ::time::Duration::milliseconds(100);
+        vault.max_requests_per_name = 2;
+        vault.max_cached_requests = 3;
+        let cache_request = |vault: &mut Vault, name: ::routing::NameType| {
+            vault.cache_request(name.clone(), &::routing::authority::Authority::NaeManager(name),
+                &::routing::authority::Authority::ClientManager(::utils::random_name()),
+                &::routing::data::DataRequest::ImmutableData(name.clone(),
+                    ::routing::immutable_data::ImmutableDataType::Normal),
+                &None)
+        };
+        let names = (0..3).map(|_| ::utils::random_name()).collect::<Vec<_>>();
+
+        // the requests waiting are bounded per name
+        assert!(cache_request(&mut vault, names[0]));
+        assert!(cache_request(&mut vault, names[0]));
+        assert!(!cache_request(&mut vault, names[0]));
+        // and in total
+        assert!(cache_request(&mut vault, names[1]));
+        assert!(!cache_request(&mut vault, names[2]));
+        assert_eq!(vault.cached_requests, 3);
+
+        // until they expire
+        vault.expire_cached_requests();
+        assert_eq!(vault.cached_requests, 3);
+        ::std::thread::sleep_ms(150);
+        vault.expire_cached_requests();
+        assert_eq!(vault.cached_requests, 0);
+        assert!(names.iter().all(|name| !vault.request_cache.contains_key(name)));
+        assert!(cache_request(&mut vault, names[2]));
+    }
+
+    #[test]
+    fn get_failed_test() {
+        let mut vault = Vault::new();
+        let name = ::utils::random_name();
+        let data_request = ::routing::data::DataRequest::ImmutableData(name.clone(),
+                               ::routing::immutable_data::ImmutableDataType::Normal);
+        let other_requester =
+            ::routing::authority::Authority::ClientManager(::utils::random_name());
+        assert!(vault.cache_request(name, &::routing::authority::Authority::NaeManager(name),
+                                    &other_requester, &data_request, &None));
+        vault.handle_get(::routing::authority::Authority::NaeManager(name),
+                         ::routing::authority::Authority::ClientManager(::utils::random_name()),
+                         data_request.clone(), None);
+        // the requester isn't left waiting for data nobody holds, while others keep waiting
+        assert_eq!(vault.request_cache.get(&name).map(|requests| {
+                       requests.iter().map(|request| request.requester.clone()).collect::<Vec<_>>()
+                   }),
+                   Some(vec![other_requester]));
+        assert_eq!(vault.cached_requests, 1);
+
+        // and is told why
+        let failure = ::types::GetFailure {
+            data_request: data_request,
+            reason: ::types::GetFailureReason::NoSuchData,
+        };
+        let plain_data = failure.to_plain_data(name).unwrap();
+        assert_eq!(::types::GetFailure::from_plain_data(&plain_data), Some(failure.clone()));
+        // no other payload is mistaken for one
+        let other = ::pmid_manager::PmidManagerRequest::LostData.to_plain_data(name).unwrap();
+        assert_eq!(::types::GetFailure::from_plain_data(&other), None);
+    }
+
+    #[test]
+    fn churn_settle_test() {
+        let mut vault = Vault::new();
+        vault.churn_settle_window = ::time::Duration::milliseconds(100);
+        let close_group = (0..5).map(|_| ::utils::random_name()).collect::<Vec<_>>();
+
+        // churns are coalesced until the close group settles
+        vault.on_churn(close_group[..3].to_vec());
+        vault.on_churn(close_group.clone());
+        vault.settle_churn();
+        assert!(vault.nodes_in_table.is_empty());
+
+        // meanwhile the requests for the groups affected are queued
+        let name = ::utils::random_name();
+        vault.on_request(::routing::ExternalRequest::Get(
+                             ::routing::data::DataRequest::ImmutableData(name.clone(),
+                                 ::routing::immutable_data::ImmutableDataType::Normal), 0),
+                         ::routing::authority::Authority::NaeManager(name),
+                         ::routing::authority::Authority::ClientManager(::utils::random_name()),
+                         None);
+        let queued = |vault: &Vault| {
+            vault.churn_settle.as_ref().map(|churn_settle| churn_settle.queued_requests.len())
+        };
+        assert_eq!(queued(&vault), Some(1));
+
+        // up to as many as may wait for data, the rest are failed
+        vault.max_cached_requests = 2;
+        for _ in 0..2 {
+            vault.on_request(::routing::ExternalRequest::Get(
+                                 ::routing::data::DataRequest::ImmutableData(name.clone(),
+                                     ::routing::immutable_data::ImmutableDataType::Normal), 0),
+                             ::routing::authority::Authority::NaeManager(name),
+                             ::routing::authority::Authority::ClientManager(::utils::random_name()),
+                             None);
+        }
+        assert_eq!(queued(&vault), Some(2));
+
+        // the accounts are refreshed once, for the latest close group
+        ::std::thread::sleep_ms(150);
+        vault.settle_churn();
+        assert_eq!(vault.nodes_in_table, close_group);
+        assert_eq!(queued(&vault), Some(2));
+
+        // and the requests handled once the rest of the group had time to refresh theirs
+        ::std::thread::sleep_ms(150);
+        vault.settle_churn();
+        assert!(vault.churn_settle.is_none());
+    }
+
+    #[test]
+    fn nae_manager_payloads_test() {
+        let mut vault = Vault::new();
+        let chunk_name = ::utils::random_name();
+        let holder = ::routing::authority::Authority::ManagedNode(::utils::random_name());
+        let client = ::routing::authority::Authority::Client(::utils::random_name(),
+                                                             crypto::sign::gen_keypair().0);
+        // a payload from the wrong authority, of a type not expected or of none is rejected
+        // rather than taken for a subscription
+        let challenge = ::data_manager::StorageChallenge::Challenge { nonce: vec![] }
+                            .to_plain_data(chunk_name).unwrap();
+        let lost_data = ::pmid_manager::PmidManagerRequest::LostData.to_plain_data(chunk_name)
+                                                                    .unwrap();
+        let untagged = ::routing::data::Data::PlainData(
+                           ::routing::plain_data::PlainData::new(chunk_name, vec![1, 2, 3]));
+        let announce = ::data_manager::HeldChunk::Announce.to_plain_data(chunk_name).unwrap();
+        for (from_authority, data) in vec![(holder.clone(), challenge), (client.clone(), lost_data),
+                                           (client.clone(), untagged), (client, announce)] {
+            assert_eq!(vault.handle_nae_manager_payload(from_authority, data.clone()),
+                       vec![::types::MethodCall::InvalidRequest { data: data }]);
+        }
+        // while an announcement of a chunk nobody holds is merely ignored
+        let announce = ::data_manager::HeldChunk::Announce.to_plain_data(chunk_name).unwrap();
+        assert!(vault.handle_nae_manager_payload(holder, announce).is_empty());
+    }
+
+    #[test]
+    fn failed_request_test() {
+        let mut vault = Vault::new();
+        let data = ::routing::data::Data::PlainData(::routing::plain_data::PlainData::new(
+                       ::utils::random_name(), vec![]));
+        for _ in 0..MAX_REQUEST_RETRIES + 1 {
+            vault.on_failed_request(::routing::ExternalRequest::Post(data.clone()),
+                Some(::routing::authority::Authority::ClientManager(::utils::random_name())),
+                ::routing::authority::Authority::ClientManager(::utils::random_name()),
+                ::routing::error::InterfaceError::Abort);
+        }
+        assert_eq!(vault.request_retries.get(&data.name()), Some(&MAX_REQUEST_RETRIES));
+
+        vault.on_failed_response(
+            ::routing::ExternalResponse::Put(::routing::error::ResponseError::Abort, None), None,
+            ::routing::authority::Authority::ManagedNode(::utils::random_name()),
+            ::routing::error::InterfaceError::Abort);
+        assert_eq!(vault.failed_responses, 1);
     }
 
     #[test]
@@ -1066,5 +2499,76 @@ mod test {
             assert_eq!(get_result.is_err(), true);
             assert_eq!(get_result.err().unwrap(), ::routing::error::ResponseError::Abort);
         }
+        assert_eq!((vault.cache_hits, vault.cache_misses), (1, 2));
+
+        // StructuredData is mutable, so never cached
+        let sign_keys = crypto::sign::gen_keypair();
+        let sd = ::routing::structured_data::StructuredData::new(0, ::utils::random_name(), 0,
+            vec![], vec![sign_keys.0], vec![], Some(&sign_keys.1)).ok().unwrap();
+        let _ = vault.handle_cache_put(
+                ::routing::authority::Authority::ManagedNode(::routing::NameType::new([6u8; 64])),
+                ::routing::NameType::new([7u8; 64]),
+                ::routing::data::Data::StructuredData(sd.clone()), None);
+        assert!(!vault.data_cache.contains_key(&sd.name()));
+        assert!(vault.handle_cache_get(::routing::data::DataRequest::StructuredData(sd.name(), 0),
+                                       sd.name(), ::routing::NameType::new([7u8; 64]), None)
+                     .is_err());
+        assert_eq!((vault.cache_hits, vault.cache_misses), (1, 2));
+    }
+
+    #[test]
+    fn client_authentication_test() {
+        let keys = crypto::sign::gen_keypair();
+        let client = ::routing::authority::Authority::Client(::utils::random_name(), keys.0);
+        let account =
+            ::routing::authority::Authority::ClientManager(::utils::client_name(&keys.0));
+        let data = ::routing::data::Data::PlainData(::routing::plain_data::PlainData::new(
+                       ::utils::random_name(), vec![]));
+        let serialised_request = ::routing::utils::encode(&data).unwrap();
+        let signed_token = |secret_key: &crypto::sign::SecretKey| {
+            Some(::routing::SignedToken {
+                signature: crypto::sign::sign_detached(&serialised_request, secret_key),
+                serialised_request: serialised_request.clone(),
+            })
+        };
+        let put = ::routing::ExternalRequest::Put(data.clone());
+        assert_eq!(authenticate_client(&put, &account, &client, &signed_token(&keys.1)), Ok(()));
+
+        // billing another client's account, or not signing with the client's key, is rejected
+        let other_account = ::routing::authority::Authority::ClientManager(::utils::random_name());
+        assert_eq!(authenticate_client(&put, &other_account, &client, &signed_token(&keys.1)),
+                   Err(ClientAuthError::NameMismatch));
+        assert_eq!(authenticate_client(&put, &account, &client, &None),
+                   Err(ClientAuthError::Unsigned));
+        let other_keys = crypto::sign::gen_keypair();
+        assert_eq!(authenticate_client(&put, &account, &client, &signed_token(&other_keys.1)),
+                   Err(ClientAuthError::InvalidSignature));
+
+        // nor is a token validly signed for a request replayed with another one
+        let other_data = ::routing::data::Data::PlainData(::routing::plain_data::PlainData::new(
+                             ::utils::random_name(), vec![1]));
+        for request in vec![::routing::ExternalRequest::Put(other_data.clone()),
+                            ::routing::ExternalRequest::Delete(other_data),
+                            ::routing::ExternalRequest::Get(
+                                ::routing::data::DataRequest::PlainData(data.name()), 0)] {
+            assert_eq!(authenticate_client(&request, &account, &client, &signed_token(&keys.1)),
+                       Err(ClientAuthError::RequestMismatch));
+        }
+
+        // requests from the network aren't a client's
+        let node_manager = ::routing::authority::Authority::NodeManager(::utils::random_name());
+        assert_eq!(authenticate_client(&put, &account, &node_manager, &None), Ok(()));
+
+        // each rejection is counted against the client's key, be it a Put or reading an inbox
+        let mut vault = Vault::new();
+        vault.reject_client_request(::routing::ExternalRequest::Put(data.clone()), other_account,
+                                    client.clone(), ClientAuthError::NameMismatch, None);
+        vault.reject_client_request(::routing::ExternalRequest::Post(data.clone()),
+                                    account.clone(), client.clone(), ClientAuthError::Unsigned,
+                                    None);
+        vault.reject_client_request(::routing::ExternalRequest::Get(
+                                        ::routing::data::DataRequest::PlainData(data.name()), 0),
+                                    account, client, ClientAuthError::InvalidSignature, None);
+        assert_eq!(vault.client_abuse.get(&::utils::client_name(&keys.0)), Some(&3));
     }
 }